@@ -0,0 +1,125 @@
+use bevy::prelude::*;
+use bevy::sprite::ColorMaterial;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use bevy_rts::ground::{ground_meshes, ground_sprites, WorldSize};
+use bevy_rts::presentation::camera_view_check;
+use bevy_rts::replay::ReplayVision;
+use bevy_rts::scenario::Scenario;
+use bevy_rts::{SimulationPlugin, SpriteSheets, WorldSeed};
+
+const WORLD_SIZES: [u32; 3] = [100, 200, 400];
+
+fn world(size: u32) -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugin(SimulationPlugin)
+        .insert_resource(WorldSeed(42))
+        .insert_resource(WorldSize::Fixed(UVec2::splat(size)));
+    app
+}
+
+// the two ways of drawing the ground, the sprite grid is how it was before chunk meshes
+#[derive(Clone, Copy)]
+enum GroundPath {
+    ChunkMeshes,
+    SpriteGrid,
+}
+
+impl GroundPath {
+    const ALL: [GroundPath; 2] = [GroundPath::ChunkMeshes, GroundPath::SpriteGrid];
+
+    fn name(self) -> &'static str {
+        match self {
+            GroundPath::ChunkMeshes => "chunk meshes",
+            GroundPath::SpriteGrid => "sprite grid",
+        }
+    }
+}
+
+// the presentation side of the ground without a window, the atlases are built from their
+// grids so the images never have to load
+fn presented_world(size: u32, ground_path: GroundPath) -> App {
+    let mut app = world(size);
+    app.add_plugin(AssetPlugin::default())
+        .add_asset::<Image>()
+        .add_asset::<TextureAtlas>()
+        .add_asset::<Mesh>()
+        .add_asset::<ColorMaterial>()
+        .init_resource::<ReplayVision>()
+        .add_system(camera_view_check);
+    let sprite_sheets = SpriteSheets::load(&mut app.world);
+    app.insert_resource(sprite_sheets);
+    app.world.spawn(Camera2dBundle::default());
+    match ground_path {
+        GroundPath::ChunkMeshes => app.add_system(ground_meshes),
+        GroundPath::SpriteGrid => app.add_system(ground_sprites),
+    };
+    app
+}
+
+fn entity_count(app: &App) -> Throughput {
+    Throughput::Elements(app.world.entities().len() as u64)
+}
+
+fn world_generation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("world generation");
+    group.sample_size(10);
+    for size in WORLD_SIZES {
+        let mut app = world(size);
+        app.update();
+        group.throughput(entity_count(&app));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
+            b.iter(|| world(size).update())
+        });
+    }
+    group.finish();
+}
+
+// lumberjacks chop, walk and push each other apart while the tick is measured
+fn tick(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tick");
+    for size in WORLD_SIZES {
+        let mut app = world(size);
+        app.insert_resource(Scenario::builtin());
+        app.update();
+        group.throughput(entity_count(&app));
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.iter(|| app.world.run_schedule(CoreSchedule::FixedUpdate))
+        });
+    }
+    group.finish();
+}
+
+// building the ground of a new world, then one frame of culling with the camera at the origin
+fn ground(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ground build");
+    group.sample_size(10);
+    for size in WORLD_SIZES {
+        for ground_path in GroundPath::ALL {
+            let mut app = presented_world(size, ground_path);
+            app.update();
+            group.throughput(entity_count(&app));
+            group.bench_function(BenchmarkId::new(ground_path.name(), size), |b| {
+                b.iter(|| presented_world(size, ground_path).update())
+            });
+        }
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("ground frame");
+    for size in WORLD_SIZES {
+        for ground_path in GroundPath::ALL {
+            let mut app = presented_world(size, ground_path);
+            app.update();
+            group.throughput(entity_count(&app));
+            group.bench_function(BenchmarkId::new(ground_path.name(), size), |b| {
+                b.iter(|| app.update())
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, world_generation, tick, ground);
+criterion_main!(benches);
@@ -0,0 +1,152 @@
+use bevy::prelude::*;
+
+use crate::definition::Definitions;
+use crate::equipment::{Armory, Equipment};
+use crate::input_action::InputAction;
+use crate::presentation::NORMAL_BUTTON;
+use crate::research::ResearchState;
+use crate::simulation::{LocalInputs, LocalTeam, PlayerInput};
+use crate::unit::Team;
+use crate::Stats;
+
+pub struct ArmoryPanelPlugin;
+
+impl Plugin for ArmoryPanelPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(setup_armory_panel)
+            .add_system(armory_panel_toggle)
+            .add_system(armory_panel_buttons)
+            .add_system(armory_panel_text);
+    }
+}
+
+// toggled with `InputAction::ToggleArmory`
+#[derive(Component)]
+struct ArmoryPanel;
+
+#[derive(Component)]
+struct CraftButton(Equipment);
+
+#[derive(Component)]
+struct CraftButtonText(Equipment);
+
+fn setup_armory_panel(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = asset_server.load("fonts/roboto_regular.ttf");
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                // below the fps counter, the research panel takes the left side
+                position: UiRect {
+                    right: Val::Px(16.0),
+                    top: Val::Px(48.0),
+                    ..default()
+                },
+                flex_direction: FlexDirection::Column,
+                gap: Size::all(Val::Px(4.0)),
+                ..default()
+            },
+            visibility: Visibility::Hidden,
+            ..default()
+        })
+        .insert(Name::new("Armory Panel"))
+        .insert(ArmoryPanel)
+        .with_children(|parent| {
+            for equipment in Equipment::all() {
+                parent
+                    .spawn(ButtonBundle {
+                        style: Style {
+                            padding: UiRect::all(Val::Px(8.0)),
+                            ..default()
+                        },
+                        background_color: NORMAL_BUTTON.into(),
+                        ..default()
+                    })
+                    .with_children(|parent| {
+                        parent
+                            .spawn(TextBundle::from_section(
+                                "",
+                                TextStyle {
+                                    font: font.clone(),
+                                    font_size: 16.0,
+                                    color: Color::WHITE,
+                                },
+                            ))
+                            .insert(CraftButtonText(equipment));
+                    })
+                    .insert(CraftButton(equipment));
+            }
+        });
+}
+
+fn armory_panel_toggle(
+    actions: Res<Input<InputAction>>,
+    mut panel_query: Query<&mut Visibility, With<ArmoryPanel>>,
+) {
+    if !actions.just_pressed(InputAction::ToggleArmory) {
+        return;
+    }
+    for mut visibility in panel_query.iter_mut() {
+        *visibility = match *visibility {
+            Visibility::Hidden => Visibility::Inherited,
+            _ => Visibility::Hidden,
+        };
+    }
+}
+
+fn armory_panel_buttons(
+    query: Query<(&Interaction, &CraftButton), Changed<Interaction>>,
+    mut inputs: ResMut<LocalInputs>,
+) {
+    for (interaction, button) in query.iter() {
+        if *interaction == Interaction::Clicked {
+            inputs.push(PlayerInput::Craft(button.0));
+        }
+    }
+}
+
+fn armory_panel_text(
+    mut text_query: Query<(&mut Text, &CraftButtonText)>,
+    armory_query: Query<(&Armory, &Team)>,
+    research: Res<ResearchState>,
+    team: Res<LocalTeam>,
+    stats: Res<Stats>,
+    definitions: Res<Definitions>,
+) {
+    for (mut text, CraftButtonText(equipment)) in text_query.iter_mut() {
+        let name = match equipment {
+            Equipment::Weapon(weapon) => format!("{weapon:?}"),
+            Equipment::Armor(armor) => format!("{armor:?} armor"),
+        };
+        // the local player's armories
+        let armories = || {
+            armory_query
+                .iter()
+                .filter(|(_, armory_team)| **armory_team == **team)
+                .map(|(armory, _)| armory)
+        };
+        let queued = armories()
+            .flat_map(|armory| armory.queue.iter())
+            .filter(|queued| *queued == equipment)
+            .count();
+        let stock = armories()
+            .flat_map(|armory| armory.stock.iter())
+            .filter(|stocked| *stocked == equipment)
+            .count();
+        let cost = equipment.cost(&definitions);
+        let status = if !equipment.unlocked(**team, &research, &definitions) {
+            "needs research".to_string()
+        } else if !cost.affordable(&stats.team(**team)) {
+            "not enough resources".to_string()
+        } else {
+            String::new()
+        };
+        let label = format!(
+            "{name} - {cost}, {:.0}s, {stock} ready, {queued} queued {status}",
+            equipment.craft_time(&definitions)
+        );
+        if text.sections[0].value != label {
+            text.sections[0].value = label;
+        }
+    }
+}
@@ -0,0 +1,646 @@
+use std::collections::VecDeque;
+
+use bevy::ecs::entity::{EntityMap, MapEntities, MapEntitiesError};
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use bevy::window::{CursorIcon, PrimaryWindow};
+use serde::{Deserialize, Serialize};
+
+use crate::control_group::ActiveSubgroup;
+use crate::forest::{Sapling, Stump};
+use crate::input_action::InputAction;
+use crate::lumberjack::Lumberjack;
+use crate::presentation::Cursor;
+use crate::simulation::{LocalInputs, LocalTeam, PlayerInput, SimulationAppExt, SimulationSet};
+use crate::soldier::{Soldier, Stance};
+use crate::unit::{SelectedMark, Team, Unit, UnitKind};
+use crate::{Barrack, BuildingKind, Tree};
+
+pub struct CommandPlugin;
+
+impl Plugin for CommandPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_simulation_event::<CommandEvent>().add_system(
+            command_dispatch
+                .in_set(SimulationSet::Input)
+                .in_schedule(CoreSchedule::FixedUpdate),
+        );
+    }
+}
+
+const WAYPOINT_SIZE: f32 = 3.0;
+// how close the cursor has to be to something to target it
+const UNIT_PICK_RADIUS: f32 = 8.0;
+const TREE_PICK_RADIUS: f32 = 8.0;
+const BARRACK_PICK_RADIUS: f32 = 16.0;
+
+/// An order for a unit. Units skip orders their role can't carry out.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Command {
+    Move(Vec2),
+    AttackMove(Vec2),
+    Attack(Entity),
+    Gather(Entity),
+    Deposit(Entity),
+    // dig out a stump so the spot can be built on
+    Clear(Entity),
+    // lumberjacks walk there and put up the building, see `stump_near`
+    Build(BuildingKind, Vec2),
+    Stop,
+    Hold,
+    Patrol(Vec2),
+    // applies right away instead of waiting in the order queue
+    SetStance(Stance),
+}
+
+/// Every order goes through this event, whether it comes from the player, the network, a
+/// replay or the AI. The player's orders arrive as `PlayerInput::Command`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CommandEvent {
+    // only units of this team follow the order, inputs get the team of their sender
+    #[serde(skip)]
+    pub team: Team,
+    pub units: Vec<Entity>,
+    pub command: Command,
+    // append to the order queue instead of replacing it
+    pub queue: bool,
+}
+
+/// The order a unit is carrying out and the ones queued after it.
+#[derive(Component, Default, Clone, Serialize, Deserialize)]
+pub struct OrderQueue {
+    current: Option<Command>,
+    orders: VecDeque<Command>,
+}
+
+/// Orders from hotkeys and the command panel, they go to the selected units.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PanelOrder {
+    Stop,
+    Hold,
+    // wait for a right click on the target or the first patrol point
+    AttackMove,
+    Patrol,
+    // waits for a right click on the building site
+    Build(BuildingKind),
+    Stance(Stance),
+    CycleStance,
+}
+
+pub struct PanelOrderEvent(pub PanelOrder);
+
+/// An order that waits for the next right click to pick its position.
+#[derive(Resource, Default)]
+pub struct PendingOrder(Option<fn(Vec2) -> Command>);
+
+#[derive(Component)]
+pub(crate) struct WaypointMarker;
+
+/// What a right click would order, see `CursorTargets`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CursorTarget {
+    Enemy(Entity),
+    Tree(Entity),
+    Stump(Entity),
+    Barrack(Entity),
+    Ground(Vec2),
+}
+
+/// Resolves what is under the cursor for the player's right click.
+#[derive(SystemParam)]
+pub struct CursorTargets<'w, 's> {
+    cursor: Res<'w, Cursor>,
+    unit_query: Query<'w, 's, (Entity, &'static Transform, &'static Team), With<Unit>>,
+    tree_query: Query<'w, 's, (Entity, &'static Transform, &'static Tree), Without<Sapling>>,
+    stump_query: Query<'w, 's, (Entity, &'static Transform), With<Stump>>,
+    barrack_query: Query<'w, 's, (Entity, &'static Transform, &'static Team), With<Barrack>>,
+}
+
+impl<'w, 's> CursorTargets<'w, 's> {
+    /// Enemies come before trees, trees before stumps and stumps before barracks, anything else
+    /// is the ground. Enemies are only targets when `soldiers` are selected to attack them.
+    pub fn resolve(&self, team: Team, soldiers: bool) -> CursorTarget {
+        let cursor = **self.cursor;
+        let nearest = |candidates: &mut dyn Iterator<Item = (Entity, &Transform)>, radius: f32| {
+            candidates
+                .map(|(entity, transform)| {
+                    (entity, transform.translation.truncate().distance(cursor))
+                })
+                .filter(|(_, distance)| *distance < radius)
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(entity, _)| entity)
+        };
+        let mut enemies = self
+            .unit_query
+            .iter()
+            .filter(|(_, _, unit_team)| soldiers && **unit_team != team)
+            .map(|(entity, transform, _)| (entity, transform));
+        if let Some(entity) = nearest(&mut enemies, UNIT_PICK_RADIUS) {
+            return CursorTarget::Enemy(entity);
+        }
+        let mut trees = self
+            .tree_query
+            .iter()
+            .filter(|(_, _, tree)| tree.resource > 0)
+            .map(|(entity, transform, _)| (entity, transform));
+        if let Some(entity) = nearest(&mut trees, TREE_PICK_RADIUS) {
+            return CursorTarget::Tree(entity);
+        }
+        if let Some(entity) = nearest(&mut self.stump_query.iter(), TREE_PICK_RADIUS) {
+            return CursorTarget::Stump(entity);
+        }
+        let mut barracks = self
+            .barrack_query
+            .iter()
+            .filter(|(_, _, barrack_team)| **barrack_team == team)
+            .map(|(entity, transform, _)| (entity, transform));
+        if let Some(entity) = nearest(&mut barracks, BARRACK_PICK_RADIUS) {
+            return CursorTarget::Barrack(entity);
+        }
+        CursorTarget::Ground(cursor)
+    }
+}
+
+impl CursorTarget {
+    /// The order a right click gives to a unit with these roles, falls back to moving there.
+    pub fn command(
+        self,
+        cursor: Vec2,
+        lumberjack: Option<&Lumberjack>,
+        soldier: Option<&Soldier>,
+    ) -> Command {
+        match self {
+            CursorTarget::Enemy(target) if soldier.is_some() => Command::Attack(target),
+            CursorTarget::Tree(target) if lumberjack.is_some() => Command::Gather(target),
+            CursorTarget::Stump(target) if lumberjack.is_some() => Command::Clear(target),
+            CursorTarget::Barrack(target) if lumberjack.is_some_and(Lumberjack::carries_wood) => {
+                Command::Deposit(target)
+            }
+            _ => Command::Move(cursor),
+        }
+    }
+}
+
+impl OrderQueue {
+    pub fn push(&mut self, command: Command, queue: bool) {
+        // stop can't wait for the orders before it
+        if !queue || command == Command::Stop {
+            self.current = None;
+            self.orders.clear();
+        }
+        self.orders.push_back(command);
+    }
+
+    /// The next order to start, once the unit is `idle` or has nothing to do. Roles call this
+    /// until it returns `None`, so orders they can't carry out are skipped.
+    pub fn next(&mut self, idle: bool) -> Option<Command> {
+        if self.current.is_some() && !idle {
+            return None;
+        }
+        self.current = self.orders.pop_front();
+        self.current
+    }
+
+    pub fn current(&self) -> Option<Command> {
+        self.current
+    }
+
+    /// The order that starts after the current one.
+    pub fn peek(&self) -> Option<Command> {
+        self.orders.front().copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Command> {
+        self.current.iter().chain(self.orders.iter())
+    }
+}
+
+impl Command {
+    fn target_mut(&mut self) -> Option<&mut Entity> {
+        match self {
+            Command::Attack(target)
+            | Command::Gather(target)
+            | Command::Deposit(target)
+            | Command::Clear(target) => Some(target),
+            _ => None,
+        }
+    }
+}
+
+// units that are not part of the map are left out, a target that isn't fails the whole order
+impl MapEntities for CommandEvent {
+    fn map_entities(&mut self, entity_map: &EntityMap) -> Result<(), MapEntitiesError> {
+        self.units = self
+            .units
+            .iter()
+            .filter_map(|unit| entity_map.get(*unit).ok())
+            .collect();
+        if let Some(target) = self.command.target_mut() {
+            *target = entity_map.get(*target)?;
+        }
+        Ok(())
+    }
+}
+
+// orders for targets that are not part of the map (e.g. an already felled tree) are dropped
+impl MapEntities for OrderQueue {
+    fn map_entities(&mut self, entity_map: &EntityMap) -> Result<(), MapEntitiesError> {
+        let map = |command: &mut Command| match command.target_mut() {
+            Some(target) => match entity_map.get(*target) {
+                Ok(mapped) => {
+                    *target = mapped;
+                    true
+                }
+                Err(_) => false,
+            },
+            None => true,
+        };
+        if let Some(mut current) = self.current {
+            self.current = map(&mut current).then_some(current);
+        }
+        self.orders.retain_mut(map);
+        Ok(())
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub(crate) fn command_input(
+    actions: Res<Input<InputAction>>,
+    targets: CursorTargets,
+    selected_query: Query<
+        (Entity, &UnitKind, Option<&Lumberjack>, Option<&Soldier>),
+        (With<SelectedMark>, With<OrderQueue>),
+    >,
+    mut pending: ResMut<PendingOrder>,
+    subgroup: Res<ActiveSubgroup>,
+    team: Res<LocalTeam>,
+    mut inputs: ResMut<LocalInputs>,
+) {
+    if actions.just_pressed(InputAction::Select) {
+        pending.0 = None;
+    }
+    if !actions.just_pressed(InputAction::Command) {
+        return;
+    }
+    let selected = || {
+        selected_query
+            .iter()
+            .filter(|(_, kind, _, _)| subgroup.includes(**kind))
+    };
+    if let Some(command) = pending.0.take() {
+        inputs.push(PlayerInput::Command(CommandEvent {
+            team: **team,
+            units: selected().map(|(entity, _, _, _)| entity).collect(),
+            command: command(**targets.cursor),
+            queue: actions.pressed(InputAction::Queue),
+        }));
+        return;
+    }
+    let soldiers = selected().any(|(_, _, _, soldier)| soldier.is_some());
+    let target = targets.resolve(**team, soldiers);
+    // each role does what it can with the target, e.g. soldiers attack while lumberjacks move
+    let mut groups: Vec<(Command, Vec<Entity>)> = vec![];
+    for (entity, _, lumberjack, soldier) in selected() {
+        let command = target.command(**targets.cursor, lumberjack, soldier);
+        match groups.iter_mut().find(|(c, _)| *c == command) {
+            Some((_, units)) => units.push(entity),
+            None => groups.push((command, vec![entity])),
+        }
+    }
+    for (command, units) in groups {
+        inputs.push(PlayerInput::Command(CommandEvent {
+            team: **team,
+            units,
+            command,
+            queue: actions.pressed(InputAction::Queue),
+        }));
+    }
+}
+
+// hints what a right click would order the selected units to do
+#[allow(clippy::type_complexity)]
+pub(crate) fn cursor_icon(
+    targets: CursorTargets,
+    pending: Res<PendingOrder>,
+    selected_query: Query<(&UnitKind, Option<&Lumberjack>, Option<&Soldier>), With<SelectedMark>>,
+    subgroup: Res<ActiveSubgroup>,
+    team: Res<LocalTeam>,
+    mut window_query: Query<&mut Window, With<PrimaryWindow>>,
+) {
+    let Ok(mut window) = window_query.get_single_mut() else {
+        return;
+    };
+    let selected = || {
+        selected_query
+            .iter()
+            .filter(|(kind, _, _)| subgroup.includes(**kind))
+    };
+    let soldiers = selected().any(|(_, _, soldier)| soldier.is_some());
+    let target = targets.resolve(**team, soldiers);
+    let cursor = **targets.cursor;
+    let commands =
+        selected().map(|(_, lumberjack, soldier)| target.command(cursor, lumberjack, soldier));
+    let mut icon = CursorIcon::Default;
+    if pending.0.is_some() {
+        icon = CursorIcon::Crosshair;
+    }
+    for command in commands {
+        match command {
+            Command::Attack(_) => {
+                icon = CursorIcon::Crosshair;
+                break;
+            }
+            Command::Gather(_) | Command::Deposit(_) | Command::Clear(_) => icon = CursorIcon::Hand,
+            _ => {}
+        }
+    }
+    if window.cursor.icon != icon {
+        window.cursor.icon = icon;
+    }
+}
+
+pub(crate) fn order_hotkeys(
+    actions: Res<Input<InputAction>>,
+    mut panel_orders: EventWriter<PanelOrderEvent>,
+) {
+    let hotkeys = [
+        (InputAction::Stop, PanelOrder::Stop),
+        (InputAction::HoldPosition, PanelOrder::Hold),
+        (InputAction::AttackMove, PanelOrder::AttackMove),
+        (InputAction::Patrol, PanelOrder::Patrol),
+        (InputAction::CycleStance, PanelOrder::CycleStance),
+        (
+            InputAction::BuildBarrack,
+            PanelOrder::Build(BuildingKind::Barrack),
+        ),
+        (
+            InputAction::BuildArmory,
+            PanelOrder::Build(BuildingKind::Armory),
+        ),
+    ];
+    for (action, order) in hotkeys {
+        if actions.just_pressed(action) {
+            panel_orders.send(PanelOrderEvent(order));
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub(crate) fn panel_orders(
+    mut panel_orders: EventReader<PanelOrderEvent>,
+    actions: Res<Input<InputAction>>,
+    selected_query: Query<
+        (Entity, &UnitKind, Option<&Stance>),
+        (With<SelectedMark>, With<OrderQueue>),
+    >,
+    mut pending: ResMut<PendingOrder>,
+    subgroup: Res<ActiveSubgroup>,
+    team: Res<LocalTeam>,
+    mut inputs: ResMut<LocalInputs>,
+) {
+    let selected = || {
+        selected_query
+            .iter()
+            .filter(|(_, kind, _)| subgroup.includes(**kind))
+    };
+    for PanelOrderEvent(order) in panel_orders.iter() {
+        let command = match *order {
+            PanelOrder::Stop => Command::Stop,
+            PanelOrder::Hold => Command::Hold,
+            PanelOrder::AttackMove => {
+                pending.0 = Some(Command::AttackMove);
+                continue;
+            }
+            PanelOrder::Patrol => {
+                pending.0 = Some(Command::Patrol);
+                continue;
+            }
+            PanelOrder::Build(kind) => {
+                pending.0 = Some(match kind {
+                    BuildingKind::Barrack => |pos| Command::Build(BuildingKind::Barrack, pos),
+                    BuildingKind::Armory => |pos| Command::Build(BuildingKind::Armory, pos),
+                });
+                continue;
+            }
+            PanelOrder::Stance(stance) => Command::SetStance(stance),
+            // every selected soldier gets the stance after the one of the first soldier
+            PanelOrder::CycleStance => {
+                let current = selected().find_map(|(_, _, stance)| stance.copied());
+                Command::SetStance(current.unwrap_or_default().next())
+            }
+        };
+        pending.0 = None;
+        let units = selected().map(|(entity, _, _)| entity).collect::<Vec<_>>();
+        if !units.is_empty() {
+            inputs.push(PlayerInput::Command(CommandEvent {
+                team: **team,
+                units,
+                command,
+                queue: actions.pressed(InputAction::Queue),
+            }));
+        }
+    }
+}
+
+fn command_dispatch(
+    mut command_events: EventReader<CommandEvent>,
+    mut query: Query<(&mut OrderQueue, Option<&mut Stance>, &Team)>,
+) {
+    for event in command_events.iter() {
+        for entity in event.units.iter() {
+            let Ok((mut orders, stance, team)) = query.get_mut(*entity) else {
+                continue;
+            };
+            if *team != event.team {
+                continue;
+            }
+            match (event.command, stance) {
+                (Command::SetStance(new), Some(mut stance)) => *stance = new,
+                (Command::SetStance(_), None) => {}
+                (command, _) => orders.push(command, event.queue),
+            }
+        }
+    }
+}
+
+// shows where the selected units are headed, one marker per distinct waypoint. Orders given
+// while the game is paused are shown before they reach the units.
+pub(crate) fn waypoint_markers(
+    unit_query: Query<&OrderQueue, With<SelectedMark>>,
+    inputs: Res<LocalInputs>,
+    target_query: Query<&Transform, Without<WaypointMarker>>,
+    mut marker_query: Query<(&mut Transform, &mut Visibility, &mut Sprite), With<WaypointMarker>>,
+    mut commands: Commands,
+) {
+    let mut seen = HashSet::new();
+    let mut waypoints = vec![];
+    let pending = inputs.iter().filter_map(|input| match input {
+        PlayerInput::Command(event) => Some(&event.command),
+        _ => None,
+    });
+    for command in unit_query.iter().flat_map(OrderQueue::iter).chain(pending) {
+        let (pos, color) = match *command {
+            Command::Move(pos) => (pos, Color::GREEN),
+            Command::AttackMove(pos) => (pos, Color::RED),
+            Command::Patrol(pos) => (pos, Color::CYAN),
+            Command::Build(_, pos) => (pos, Color::ORANGE),
+            Command::Attack(target)
+            | Command::Gather(target)
+            | Command::Deposit(target)
+            | Command::Clear(target) => {
+                let Ok(transform) = target_query.get(target) else {
+                    continue;
+                };
+                let color = match command {
+                    Command::Attack(_) => Color::RED,
+                    Command::Gather(_) | Command::Clear(_) => Color::YELLOW,
+                    _ => Color::ORANGE,
+                };
+                (transform.translation.truncate(), color)
+            }
+            Command::Stop | Command::Hold | Command::SetStance(_) => continue,
+        };
+        if seen.insert(pos.round().as_ivec2()) {
+            waypoints.push((pos, color));
+        }
+    }
+
+    let mut markers = marker_query.iter_mut();
+    for (pos, color) in waypoints {
+        match markers.next() {
+            Some((mut transform, mut visibility, mut sprite)) => {
+                transform.translation = pos.extend(transform.translation.z);
+                *visibility = Visibility::Inherited;
+                sprite.color = color;
+            }
+            // shows up next frame
+            None => {
+                commands
+                    .spawn(SpriteBundle {
+                        sprite: Sprite {
+                            color,
+                            custom_size: Some(Vec2::splat(WAYPOINT_SIZE)),
+                            ..default()
+                        },
+                        transform: Transform::from_translation(pos.extend(150.0)),
+                        ..default()
+                    })
+                    .insert(Name::new("Waypoint"))
+                    .insert(WaypointMarker);
+            }
+        }
+    }
+    for (_, mut visibility, _) in markers {
+        if *visibility != Visibility::Hidden {
+            *visibility = Visibility::Hidden;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::SystemState;
+
+    use super::*;
+
+    const ENEMY: Team = Team(1);
+
+    // an enemy right next to a tree, a stump, the own barrack and open ground
+    fn world() -> World {
+        let mut world = World::new();
+        world.init_resource::<Cursor>();
+        let at = |x, y| Transform::from_xyz(x, y, 0.0);
+        world.spawn((at(0.0, 4.0), ENEMY, Unit::default()));
+        world.spawn((at(0.0, 0.0), Tree { resource: 100 }));
+        world.spawn((at(100.0, 0.0), Tree { resource: 0 }, Stump::default()));
+        world.spawn((at(200.0, 0.0), Team::PLAYER, Barrack));
+        world
+    }
+
+    fn resolve(world: &mut World, cursor: Vec2, soldiers: bool) -> CursorTarget {
+        world.resource_mut::<Cursor>().0 = cursor;
+        let mut state = SystemState::<CursorTargets>::new(world);
+        state.get(world).resolve(Team::PLAYER, soldiers)
+    }
+
+    fn entity_at(world: &mut World, pos: Vec2) -> Entity {
+        world
+            .query::<(Entity, &Transform)>()
+            .iter(world)
+            .find(|(_, transform)| transform.translation.truncate() == pos)
+            .map(|(entity, _)| entity)
+            .unwrap()
+    }
+
+    #[test]
+    fn enemies_are_targets_only_for_soldiers() {
+        let mut world = world();
+        let enemy = entity_at(&mut world, Vec2::new(0.0, 4.0));
+        let tree = entity_at(&mut world, Vec2::ZERO);
+        assert_eq!(
+            resolve(&mut world, Vec2::ZERO, true),
+            CursorTarget::Enemy(enemy)
+        );
+        assert_eq!(
+            resolve(&mut world, Vec2::ZERO, false),
+            CursorTarget::Tree(tree)
+        );
+    }
+
+    #[test]
+    fn targets_give_the_orders_of_each_role() {
+        let mut world = world();
+        let enemy = entity_at(&mut world, Vec2::new(0.0, 4.0));
+        let tree = entity_at(&mut world, Vec2::ZERO);
+        let stump = entity_at(&mut world, Vec2::new(100.0, 0.0));
+        let barrack = entity_at(&mut world, Vec2::new(200.0, 0.0));
+        let lumberjack = Lumberjack::default();
+        let loaded =
+            ron::from_str::<Lumberjack>("(action: Idle, wood: 5, animation_timer: 0.0)").unwrap();
+        let soldier = Soldier::default();
+
+        let cursor = Vec2::ZERO;
+        let target = resolve(&mut world, cursor, false);
+        assert_eq!(
+            target.command(cursor, Some(&lumberjack), None),
+            Command::Gather(tree)
+        );
+        let target = resolve(&mut world, cursor, true);
+        assert_eq!(
+            target.command(cursor, None, Some(&soldier)),
+            Command::Attack(enemy)
+        );
+        assert_eq!(
+            target.command(cursor, Some(&lumberjack), None),
+            Command::Move(cursor)
+        );
+
+        let cursor = Vec2::new(100.0, 0.0);
+        let target = resolve(&mut world, cursor, false);
+        assert_eq!(
+            target.command(cursor, Some(&lumberjack), None),
+            Command::Clear(stump)
+        );
+
+        let cursor = Vec2::new(200.0, 0.0);
+        let target = resolve(&mut world, cursor, false);
+        assert_eq!(
+            target.command(cursor, Some(&loaded), None),
+            Command::Deposit(barrack)
+        );
+        // nothing to bring back, so they just walk there
+        assert_eq!(
+            target.command(cursor, Some(&lumberjack), None),
+            Command::Move(cursor)
+        );
+
+        let cursor = Vec2::new(0.0, 100.0);
+        let target = resolve(&mut world, cursor, true);
+        assert_eq!(target, CursorTarget::Ground(cursor));
+        assert_eq!(
+            target.command(cursor, None, Some(&soldier)),
+            Command::Move(cursor)
+        );
+    }
+}
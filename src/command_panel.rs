@@ -0,0 +1,107 @@
+use bevy::prelude::*;
+
+use crate::command::{PanelOrder, PanelOrderEvent};
+use crate::presentation::NORMAL_BUTTON;
+use crate::soldier::{Soldier, Stance};
+use crate::unit::SelectedMark;
+
+pub struct CommandPanelPlugin;
+
+impl Plugin for CommandPanelPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(setup_command_panel)
+            .add_system(command_panel_buttons)
+            .add_system(command_panel_visibility);
+    }
+}
+
+const PANEL_BUTTONS: [(PanelOrder, &str); 7] = [
+    (PanelOrder::Stop, "Stop (S)"),
+    (PanelOrder::Hold, "Hold (H)"),
+    (PanelOrder::AttackMove, "Attack Move (A)"),
+    (PanelOrder::Patrol, "Patrol (P)"),
+    (PanelOrder::Stance(Stance::Aggressive), "Aggressive"),
+    (PanelOrder::Stance(Stance::Defensive), "Defensive"),
+    (PanelOrder::Stance(Stance::Passive), "Passive"),
+];
+
+// shown while soldiers are selected
+#[derive(Component)]
+struct CommandPanel;
+
+#[derive(Component)]
+struct CommandButton(PanelOrder);
+
+fn setup_command_panel(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = asset_server.load("fonts/roboto_regular.ttf");
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    right: Val::Px(16.0),
+                    bottom: Val::Px(116.0),
+                    ..default()
+                },
+                flex_direction: FlexDirection::Column,
+                gap: Size::all(Val::Px(4.0)),
+                ..default()
+            },
+            visibility: Visibility::Hidden,
+            ..default()
+        })
+        .insert(Name::new("Command Panel"))
+        .insert(CommandPanel)
+        .with_children(|parent| {
+            for (order, label) in PANEL_BUTTONS {
+                parent
+                    .spawn(ButtonBundle {
+                        style: Style {
+                            padding: UiRect::all(Val::Px(8.0)),
+                            justify_content: JustifyContent::Center,
+                            ..default()
+                        },
+                        background_color: NORMAL_BUTTON.into(),
+                        ..default()
+                    })
+                    .with_children(|parent| {
+                        parent.spawn(TextBundle::from_section(
+                            label,
+                            TextStyle {
+                                font: font.clone(),
+                                font_size: 20.0,
+                                color: Color::WHITE,
+                            },
+                        ));
+                    })
+                    .insert(CommandButton(order));
+            }
+        });
+}
+
+fn command_panel_buttons(
+    query: Query<(&Interaction, &CommandButton), Changed<Interaction>>,
+    mut panel_orders: EventWriter<PanelOrderEvent>,
+) {
+    for (interaction, button) in query.iter() {
+        if *interaction == Interaction::Clicked {
+            panel_orders.send(PanelOrderEvent(button.0));
+        }
+    }
+}
+
+fn command_panel_visibility(
+    selected_query: Query<(), (With<SelectedMark>, With<Soldier>)>,
+    mut panel_query: Query<&mut Visibility, With<CommandPanel>>,
+) {
+    let visibility = if selected_query.is_empty() {
+        Visibility::Hidden
+    } else {
+        Visibility::Inherited
+    };
+    for mut panel_visibility in panel_query.iter_mut() {
+        if *panel_visibility != visibility {
+            *panel_visibility = visibility;
+        }
+    }
+}
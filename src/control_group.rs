@@ -0,0 +1,140 @@
+use bevy::prelude::*;
+
+use crate::input_action::InputAction;
+use crate::unit::{SelectedMark, SelectionBox, Unit, UnitKind};
+
+pub struct ControlGroupPlugin;
+
+impl Plugin for ControlGroupPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ControlGroups>()
+            .init_resource::<ActiveSubgroup>()
+            .add_system(control_group_input)
+            .add_system(subgroup_cycle)
+            .add_system(subgroup_visual);
+    }
+}
+
+const DOUBLE_TAP_TIME: f64 = 0.3;
+
+#[derive(Resource, Default)]
+pub struct ControlGroups {
+    groups: [Vec<Entity>; 9],
+    last_recall: Option<(usize, f64)>,
+}
+
+// the unit kind inside the current selection that hotkeys and buttons act on
+#[derive(Resource, Default, Deref)]
+pub struct ActiveSubgroup(pub Option<UnitKind>);
+
+impl ActiveSubgroup {
+    /// Whether orders go to selected units of `kind`, all of them without a subgroup.
+    pub fn includes(&self, kind: UnitKind) -> bool {
+        self.0.is_none_or(|active| active == kind)
+    }
+}
+
+fn control_group_input(
+    actions: Res<Input<InputAction>>,
+    time: Res<Time>,
+    mut control_groups: ResMut<ControlGroups>,
+    unit_query: Query<(Entity, &Transform, Option<&SelectedMark>), With<Unit>>,
+    mut camera_query: Query<&mut Transform, (With<Camera2d>, Without<Unit>)>,
+    mut commands: Commands,
+) {
+    let Some(index) = (0..9).find(|i| actions.just_pressed(InputAction::ControlGroup(i + 1)))
+    else {
+        return;
+    };
+
+    if actions.pressed(InputAction::GroupModifier) {
+        // assign
+        control_groups.groups[index] = unit_query
+            .iter()
+            .filter(|(_, _, selected)| selected.is_some())
+            .map(|(entity, _, _)| entity)
+            .collect();
+        control_groups.last_recall = None;
+        return;
+    }
+
+    // recall, dropping units that no longer exist
+    let group = &mut control_groups.groups[index];
+    group.retain(|entity| unit_query.contains(*entity));
+    if group.is_empty() {
+        return;
+    }
+    for (entity, _, selected) in unit_query.iter() {
+        match (group.contains(&entity), selected.is_some()) {
+            (true, false) => {
+                commands.entity(entity).insert(SelectedMark);
+            }
+            (false, true) => {
+                commands.entity(entity).remove::<SelectedMark>();
+            }
+            _ => {}
+        }
+    }
+
+    // double tap centers the camera on the group, also while the game is paused
+    let now = time.raw_elapsed_seconds_f64();
+    if matches!(control_groups.last_recall, Some((last, at)) if last == index && now - at < DOUBLE_TAP_TIME)
+    {
+        let group = &control_groups.groups[index];
+        let center = group
+            .iter()
+            .filter_map(|entity| unit_query.get(*entity).ok())
+            .map(|(_, transform, _)| transform.translation.truncate())
+            .sum::<Vec2>()
+            / group.len() as f32;
+        let mut camera_transform = camera_query.single_mut();
+        camera_transform.translation = center.round().extend(camera_transform.translation.z);
+        control_groups.last_recall = None;
+    } else {
+        control_groups.last_recall = Some((index, now));
+    }
+}
+
+fn subgroup_cycle(
+    actions: Res<Input<InputAction>>,
+    query: Query<&UnitKind, With<SelectedMark>>,
+    mut active_subgroup: ResMut<ActiveSubgroup>,
+) {
+    let mut kinds = query.iter().copied().collect::<Vec<_>>();
+    kinds.sort();
+    kinds.dedup();
+
+    // forget the subgroup once the selection no longer contains it
+    if matches!(**active_subgroup, Some(kind) if !kinds.contains(&kind)) {
+        active_subgroup.0 = None;
+    }
+
+    if actions.just_pressed(InputAction::CycleSubgroup) && !kinds.is_empty() {
+        active_subgroup.0 = match **active_subgroup {
+            Some(kind) => {
+                let index = kinds.iter().position(|k| *k == kind).unwrap_or_default();
+                Some(kinds[(index + 1) % kinds.len()])
+            }
+            None => Some(kinds[0]),
+        };
+    }
+}
+
+fn subgroup_visual(
+    active_subgroup: Res<ActiveSubgroup>,
+    unit_query: Query<(&Children, &UnitKind), With<SelectedMark>>,
+    mut child_query: Query<&mut TextureAtlasSprite, With<SelectionBox>>,
+) {
+    for (children, kind) in unit_query.iter() {
+        let index = if **active_subgroup == Some(*kind) {
+            1
+        } else {
+            0
+        };
+        for child in children.iter() {
+            if let Ok(mut sprite) = child_query.get_mut(*child) {
+                sprite.index = index;
+            }
+        }
+    }
+}
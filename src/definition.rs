@@ -0,0 +1,557 @@
+use std::fmt;
+use std::marker::PhantomData;
+use std::path::Path;
+
+use bevy::asset::{AssetLoader, AssetPath, BoxedFuture, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::utils::HashMap;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::soldier::{Armor, Weapon};
+use crate::unit::UnitKind;
+use crate::{BuildingKind, Resources};
+
+pub struct DefinitionPlugin;
+
+impl Plugin for DefinitionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<UnitDefinition>()
+            .add_asset::<BuildingDefinition>()
+            .add_asset::<WeaponDefinitions>()
+            .add_asset::<ArmorDefinitions>()
+            .add_asset::<TechTree>()
+            .add_asset_loader(DefinitionLoader::<UnitDefinition>::default())
+            .add_asset_loader(DefinitionLoader::<BuildingDefinition>::default())
+            .add_asset_loader(DefinitionLoader::<WeaponDefinitions>::default())
+            .add_asset_loader(DefinitionLoader::<ArmorDefinitions>::default())
+            .add_asset_loader(DefinitionLoader::<TechTree>::default())
+            .init_resource::<DefinitionHandles>()
+            .add_system(unit_definitions_update)
+            .add_system(building_definitions_update)
+            .add_system(weapon_definitions_update)
+            .add_system(armor_definitions_update)
+            .add_system(tech_tree_update);
+    }
+}
+
+pub const UNIT_DEFINITIONS: [(UnitKind, &str); 2] = [
+    (UnitKind::Lumberjack, "definitions/lumberjack.unit.ron"),
+    (UnitKind::Soldier, "definitions/soldier.unit.ron"),
+];
+pub const BUILDING_DEFINITIONS: [(BuildingKind, &str); 2] = [
+    (BuildingKind::Barrack, "definitions/barrack.building.ron"),
+    (BuildingKind::Armory, "definitions/armory.building.ron"),
+];
+pub const WEAPON_DEFINITIONS: &str = "definitions/default.weapons.ron";
+pub const ARMOR_DEFINITIONS: &str = "definitions/default.armor.ron";
+pub const TECH_TREE: &str = "definitions/default.tech.ron";
+
+/// A grid of equally sized frames in an image below `assets`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct SpriteSheetDefinition {
+    pub path: String,
+    pub tile_size: Vec2,
+    pub columns: usize,
+    pub rows: usize,
+}
+
+// units aren't produced yet, buildings, research and equipment pay their cost
+#[derive(Clone, Copy, Default, Debug, Serialize, Deserialize)]
+pub struct Cost {
+    #[serde(default)]
+    pub wood: u32,
+    #[serde(default)]
+    pub metal: u32,
+}
+
+impl Cost {
+    pub fn affordable(&self, resources: &Resources) -> bool {
+        resources.wood >= self.wood && resources.metal >= self.metal
+    }
+
+    pub fn pay(&self, resources: &mut Resources) {
+        resources.wood -= self.wood;
+        resources.metal -= self.metal;
+    }
+}
+
+impl fmt::Display for Cost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.wood, self.metal) {
+            (wood, 0) => write!(f, "{wood} wood"),
+            (0, metal) => write!(f, "{metal} metal"),
+            (wood, metal) => write!(f, "{wood} wood, {metal} metal"),
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Debug, Deserialize, TypeUuid)]
+#[uuid = "55b3d4a7-44e1-4d01-8d3f-6d7abb13c8b4"]
+pub struct UnitDefinition {
+    pub kind: UnitKind,
+    pub sprite_sheet: SpriteSheetDefinition,
+    pub speed: f32,
+    pub acceleration: f32,
+    // units closer than this push each other apart
+    pub size: f32,
+    pub hp: f32,
+    #[serde(default)]
+    pub weapon: Option<Weapon>,
+    #[serde(default)]
+    pub armor: Option<Armor>,
+    // wood a worker carries before it returns to a barrack
+    #[serde(default)]
+    pub carry_capacity: u32,
+    // seconds a worker needs for one chop
+    #[serde(default)]
+    pub chop_time: f32,
+    // soldiers rise through them in order as they gain experience
+    #[serde(default)]
+    pub ranks: Vec<RankDefinition>,
+    pub cost: Cost,
+    // seconds
+    pub build_time: f32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RankDefinition {
+    pub name: String,
+    // experience needed for the rank, damage dealt counts and kills give a bonus
+    pub experience: f32,
+    // added to the max hp of the unit
+    pub hp: f32,
+    // added to the chance to hit
+    pub accuracy: f32,
+    // multiplies how often the unit strikes
+    pub attack_speed: f32,
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Debug, Deserialize, TypeUuid)]
+#[uuid = "997b0163-49f7-45a3-bee3-fac825bbabbe"]
+pub struct BuildingDefinition {
+    pub kind: BuildingKind,
+    pub sprite_sheet: SpriteSheetDefinition,
+    // buildings share sheets, this is the frame of the building
+    #[serde(default)]
+    pub sprite_index: usize,
+    // workers this close can deposit their resources
+    pub size: f32,
+    pub hp: f32,
+    pub cost: Cost,
+    // seconds
+    pub build_time: f32,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct WeaponDefinition {
+    pub damage: f32,
+    pub range: f32,
+    // seconds between two strikes
+    pub cooldown: f32,
+    // chance that a strike hits
+    pub accuracy: f32,
+    // paid when an armory starts to craft it
+    pub cost: Cost,
+    // seconds
+    pub craft_time: f32,
+    // first frame of the strike animation in the soldier sheet, the direction is added to it
+    pub strike_frame: usize,
+    // rgb the soldier sprite is tinted with
+    #[serde(default)]
+    pub tint: Option<[f32; 3]>,
+}
+
+#[derive(Clone, Debug, Deserialize, TypeUuid)]
+#[uuid = "08ec9984-288a-4507-a3d3-d78839b8e123"]
+pub struct WeaponDefinitions(HashMap<Weapon, WeaponDefinition>);
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct ArmorDefinition {
+    // share of the damage that is absorbed
+    pub protection: f32,
+    // multiplies the speed of the soldier wearing it
+    pub speed: f32,
+    pub cost: Cost,
+    // seconds
+    pub craft_time: f32,
+}
+
+#[derive(Clone, Debug, Deserialize, TypeUuid)]
+#[uuid = "5c1e8e7e-3b7a-4f0b-9a4e-2f6d8c0b7a31"]
+pub struct ArmorDefinitions(HashMap<Armor, ArmorDefinition>);
+
+/// Something a building can research once, see `ResearchState`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ResearchDefinition {
+    pub id: String,
+    pub name: String,
+    // the kind of building that researches it
+    pub building: BuildingKind,
+    pub cost: Cost,
+    // seconds
+    pub time: f32,
+    // ids of the research that has to be finished first
+    #[serde(default)]
+    pub requires: Vec<String>,
+    pub effect: ResearchEffect,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize)]
+pub enum ResearchEffect {
+    // armories can craft the weapon or armor once it is researched
+    Weapon(Weapon),
+    Armor(Armor),
+    // multiplies how fast workers chop
+    ChopSpeed(f32),
+    // adds to the wood a worker carries
+    CarryCapacity(u32),
+}
+
+/// All research in the order the research panel lists it.
+#[derive(Clone, Debug, Deserialize, TypeUuid)]
+#[uuid = "d0b0f3a4-6f0e-4a8e-9c57-1f3b5c1ad2e7"]
+pub struct TechTree(Vec<ResearchDefinition>);
+
+impl TechTree {
+    // ids are unique and prerequisites exist and come first, so the tree has no cycles
+    fn validate(&self) -> Result<(), String> {
+        for (index, research) in self.0.iter().enumerate() {
+            let earlier = &self.0[..index];
+            if earlier.iter().any(|other| other.id == research.id) {
+                return Err(format!("research {} is defined twice", research.id));
+            }
+            if let Some(missing) = research
+                .requires
+                .iter()
+                .find(|id| !earlier.iter().any(|other| other.id == **id))
+            {
+                return Err(format!(
+                    "research {} requires {missing}, which is not defined before it",
+                    research.id
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An asset type read from a RON file with the extension `EXTENSION`.
+trait Definition: DeserializeOwned + TypeUuid + Send + Sync + 'static {
+    const EXTENSION: &'static str;
+
+    fn sprite_sheet(&self) -> Option<&SpriteSheetDefinition> {
+        None
+    }
+}
+
+impl Definition for UnitDefinition {
+    const EXTENSION: &'static str = "unit.ron";
+
+    fn sprite_sheet(&self) -> Option<&SpriteSheetDefinition> {
+        Some(&self.sprite_sheet)
+    }
+}
+
+impl Definition for BuildingDefinition {
+    const EXTENSION: &'static str = "building.ron";
+
+    fn sprite_sheet(&self) -> Option<&SpriteSheetDefinition> {
+        Some(&self.sprite_sheet)
+    }
+}
+
+impl Definition for WeaponDefinitions {
+    const EXTENSION: &'static str = "weapons.ron";
+}
+
+impl Definition for ArmorDefinitions {
+    const EXTENSION: &'static str = "armor.ron";
+}
+
+impl Definition for TechTree {
+    const EXTENSION: &'static str = "tech.ron";
+}
+
+// the sprite sheet of a definition becomes a `TextureAtlas` labeled "atlas", so
+// "definitions/soldier.unit.ron#atlas" is the atlas of the soldier
+struct DefinitionLoader<T>(PhantomData<fn() -> T>);
+
+impl<T> Default for DefinitionLoader<T> {
+    fn default() -> Self {
+        DefinitionLoader(PhantomData)
+    }
+}
+
+impl<T: Definition> AssetLoader for DefinitionLoader<T> {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let definition = ron::de::from_bytes::<T>(bytes)?;
+            if let Some(sheet) = definition.sprite_sheet() {
+                let image_path = AssetPath::new(Path::new(&sheet.path).to_owned(), None);
+                let texture_atlas = TextureAtlas::from_grid(
+                    load_context.get_handle(image_path.get_id()),
+                    sheet.tile_size,
+                    sheet.columns,
+                    sheet.rows,
+                    None,
+                    None,
+                );
+                load_context.set_labeled_asset(
+                    "atlas",
+                    LoadedAsset::new(texture_atlas).with_dependency(image_path),
+                );
+            }
+            load_context.set_default_asset(LoadedAsset::new(definition));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        std::slice::from_ref(&T::EXTENSION)
+    }
+}
+
+/// The atlas of the sprite sheet in the definition at `path`, it follows the file as it is
+/// edited.
+pub fn load_definition_atlas(world: &mut World, path: &str) -> Handle<TextureAtlas> {
+    world
+        .resource::<AssetServer>()
+        .load(format!("{path}#atlas"))
+}
+
+/// The definitions the simulation reads. They start out as the files shipped in `assets`
+/// and follow the asset files as they are edited.
+#[derive(Resource, Clone)]
+pub struct Definitions {
+    units: HashMap<UnitKind, UnitDefinition>,
+    buildings: HashMap<BuildingKind, BuildingDefinition>,
+    weapons: WeaponDefinitions,
+    armor: ArmorDefinitions,
+    tech_tree: TechTree,
+}
+
+impl Definitions {
+    pub fn unit(&self, kind: UnitKind) -> &UnitDefinition {
+        &self.units[&kind]
+    }
+
+    pub fn building(&self, kind: BuildingKind) -> &BuildingDefinition {
+        &self.buildings[&kind]
+    }
+
+    pub fn weapon(&self, weapon: Weapon) -> WeaponDefinition {
+        self.weapons.0[&weapon]
+    }
+
+    pub fn armor(&self, armor: Armor) -> ArmorDefinition {
+        self.armor.0[&armor]
+    }
+
+    pub fn tech_tree(&self) -> &[ResearchDefinition] {
+        &self.tech_tree.0
+    }
+
+    pub fn research(&self, id: &str) -> Option<&ResearchDefinition> {
+        self.tech_tree.0.iter().find(|research| research.id == id)
+    }
+}
+
+impl Default for Definitions {
+    // the shipped files are part of the build, so a broken one is a bug and not a user error
+    fn default() -> Self {
+        let units = [
+            include_str!("../assets/definitions/lumberjack.unit.ron"),
+            include_str!("../assets/definitions/soldier.unit.ron"),
+        ]
+        .into_iter()
+        .map(|source| {
+            let definition: UnitDefinition = ron::from_str(source).expect("valid unit definition");
+            (definition.kind, definition)
+        })
+        .collect::<HashMap<_, _>>();
+        let buildings = [
+            include_str!("../assets/definitions/barrack.building.ron"),
+            include_str!("../assets/definitions/armory.building.ron"),
+        ]
+        .into_iter()
+        .map(|source| {
+            let definition: BuildingDefinition =
+                ron::from_str(source).expect("valid building definition");
+            (definition.kind, definition)
+        })
+        .collect::<HashMap<_, _>>();
+        let weapons: WeaponDefinitions =
+            ron::from_str(include_str!("../assets/definitions/default.weapons.ron"))
+                .expect("valid weapon definitions");
+        let armor: ArmorDefinitions =
+            ron::from_str(include_str!("../assets/definitions/default.armor.ron"))
+                .expect("valid armor definitions");
+        for (kind, _) in UNIT_DEFINITIONS {
+            assert!(units.contains_key(&kind), "{kind:?} has no definition");
+        }
+        for (kind, _) in BUILDING_DEFINITIONS {
+            assert!(buildings.contains_key(&kind), "{kind:?} has no definition");
+        }
+        for armor_kind in Armor::ALL {
+            assert!(
+                armor.0.contains_key(&armor_kind),
+                "{armor_kind:?} has no definition"
+            );
+        }
+        for weapon in Weapon::ALL {
+            assert!(
+                weapons.0.contains_key(&weapon),
+                "{weapon:?} has no definition"
+            );
+        }
+        let tech_tree: TechTree =
+            ron::from_str(include_str!("../assets/definitions/default.tech.ron"))
+                .expect("valid tech tree");
+        if let Err(e) = tech_tree.validate() {
+            panic!("{e}");
+        }
+        Definitions {
+            units,
+            buildings,
+            weapons,
+            armor,
+            tech_tree,
+        }
+    }
+}
+
+// keeps the definition assets loaded and watched for changes
+#[derive(Resource)]
+struct DefinitionHandles {
+    _units: Vec<Handle<UnitDefinition>>,
+    _buildings: Vec<Handle<BuildingDefinition>>,
+    _weapons: Handle<WeaponDefinitions>,
+    _armor: Handle<ArmorDefinitions>,
+    _tech_tree: Handle<TechTree>,
+}
+
+impl FromWorld for DefinitionHandles {
+    fn from_world(world: &mut World) -> Self {
+        let asset_server = world.resource::<AssetServer>();
+        DefinitionHandles {
+            _units: UNIT_DEFINITIONS
+                .iter()
+                .map(|(_, path)| asset_server.load(*path))
+                .collect(),
+            _buildings: BUILDING_DEFINITIONS
+                .iter()
+                .map(|(_, path)| asset_server.load(*path))
+                .collect(),
+            _weapons: asset_server.load(WEAPON_DEFINITIONS),
+            _armor: asset_server.load(ARMOR_DEFINITIONS),
+            _tech_tree: asset_server.load(TECH_TREE),
+        }
+    }
+}
+
+fn unit_definitions_update(
+    mut events: EventReader<AssetEvent<UnitDefinition>>,
+    assets: Res<Assets<UnitDefinition>>,
+    mut definitions: ResMut<Definitions>,
+) {
+    for event in events.iter() {
+        if let AssetEvent::Created { handle } | AssetEvent::Modified { handle } = event {
+            if let Some(definition) = assets.get(handle) {
+                info!("updated the definition of {:?}", definition.kind);
+                definitions
+                    .units
+                    .insert(definition.kind, definition.clone());
+            }
+        }
+    }
+}
+
+fn building_definitions_update(
+    mut events: EventReader<AssetEvent<BuildingDefinition>>,
+    assets: Res<Assets<BuildingDefinition>>,
+    mut definitions: ResMut<Definitions>,
+) {
+    for event in events.iter() {
+        if let AssetEvent::Created { handle } | AssetEvent::Modified { handle } = event {
+            if let Some(definition) = assets.get(handle) {
+                info!("updated the definition of {:?}", definition.kind);
+                definitions
+                    .buildings
+                    .insert(definition.kind, definition.clone());
+            }
+        }
+    }
+}
+
+fn weapon_definitions_update(
+    mut events: EventReader<AssetEvent<WeaponDefinitions>>,
+    assets: Res<Assets<WeaponDefinitions>>,
+    mut definitions: ResMut<Definitions>,
+) {
+    for event in events.iter() {
+        if let AssetEvent::Created { handle } | AssetEvent::Modified { handle } = event {
+            if let Some(weapons) = assets.get(handle) {
+                // a weapon missing from the file would panic on its next strike
+                match Weapon::ALL.iter().find(|w| !weapons.0.contains_key(w)) {
+                    Some(weapon) => {
+                        error!("{weapon:?} has no definition, keeping previous weapons")
+                    }
+                    None => {
+                        info!("updated the weapon definitions");
+                        definitions.weapons = weapons.clone();
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn armor_definitions_update(
+    mut events: EventReader<AssetEvent<ArmorDefinitions>>,
+    assets: Res<Assets<ArmorDefinitions>>,
+    mut definitions: ResMut<Definitions>,
+) {
+    for event in events.iter() {
+        if let AssetEvent::Created { handle } | AssetEvent::Modified { handle } = event {
+            if let Some(armor) = assets.get(handle) {
+                match Armor::ALL.iter().find(|a| !armor.0.contains_key(a)) {
+                    Some(missing) => {
+                        error!("{missing:?} has no definition, keeping previous armor")
+                    }
+                    None => {
+                        info!("updated the armor definitions");
+                        definitions.armor = armor.clone();
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn tech_tree_update(
+    mut events: EventReader<AssetEvent<TechTree>>,
+    assets: Res<Assets<TechTree>>,
+    mut definitions: ResMut<Definitions>,
+) {
+    for event in events.iter() {
+        if let AssetEvent::Created { handle } | AssetEvent::Modified { handle } = event {
+            if let Some(tech_tree) = assets.get(handle) {
+                match tech_tree.validate() {
+                    Err(e) => error!("{e}, keeping previous tech tree"),
+                    Ok(()) => {
+                        info!("updated the tech tree");
+                        definitions.tech_tree = tech_tree.clone();
+                    }
+                }
+            }
+        }
+    }
+}
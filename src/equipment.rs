@@ -0,0 +1,213 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::definition::{Cost, Definitions, ResearchEffect};
+use crate::research::ResearchState;
+use crate::simulation::{tick_seconds, SimId, SimulationAppExt, SimulationSet};
+use crate::soldier::{next_action, Armor, Soldier, SoldierAction, Weapon};
+use crate::unit::Team;
+use crate::{BuildingKind, Stats};
+
+pub struct EquipmentPlugin;
+
+impl Plugin for EquipmentPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_simulation_event::<CraftEvent>()
+            .add_system(
+                start_crafting
+                    .in_set(SimulationSet::Input)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            )
+            .add_systems(
+                (armory_crafting, soldier_pick_up)
+                    .after(next_action)
+                    .in_set(SimulationSet::Actions)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            );
+    }
+}
+
+/// A weapon or armor an armory crafts and a soldier carries.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum Equipment {
+    Weapon(Weapon),
+    Armor(Armor),
+}
+
+impl Equipment {
+    pub fn all() -> impl Iterator<Item = Equipment> {
+        Weapon::ALL
+            .into_iter()
+            .map(Equipment::Weapon)
+            .chain(Armor::ALL.into_iter().map(Equipment::Armor))
+    }
+
+    pub fn cost(self, definitions: &Definitions) -> Cost {
+        match self {
+            Equipment::Weapon(weapon) => definitions.weapon(weapon).cost,
+            Equipment::Armor(armor) => definitions.armor(armor).cost,
+        }
+    }
+
+    // seconds
+    pub fn craft_time(self, definitions: &Definitions) -> f32 {
+        match self {
+            Equipment::Weapon(weapon) => definitions.weapon(weapon).craft_time,
+            Equipment::Armor(armor) => definitions.armor(armor).craft_time,
+        }
+    }
+
+    // soldiers swap for gear that costs more than what they carry
+    pub fn value(self, definitions: &Definitions) -> u32 {
+        let cost = self.cost(definitions);
+        cost.wood + cost.metal
+    }
+
+    /// Equipment that some research unlocks can only be crafted once `team` has done it.
+    pub fn unlocked(self, team: Team, research: &ResearchState, definitions: &Definitions) -> bool {
+        let effect = match self {
+            Equipment::Weapon(weapon) => ResearchEffect::Weapon(weapon),
+            Equipment::Armor(armor) => ResearchEffect::Armor(armor),
+        };
+        let mut unlocked_by = definitions
+            .tech_tree()
+            .iter()
+            .filter(|research| research.effect == effect)
+            .peekable();
+        unlocked_by.peek().is_none() || unlocked_by.any(|r| research.is_done(team, &r.id))
+    }
+}
+
+/// Queues the equipment at the armory of the team with the shortest queue.
+pub struct CraftEvent(pub Equipment, pub Team);
+
+/// A building that crafts equipment one piece at a time and keeps it until soldiers pick it
+/// up.
+#[derive(Component, Default, Clone, Debug, Serialize, Deserialize)]
+pub struct Armory {
+    pub queue: VecDeque<Equipment>,
+    // seconds spent on the first equipment in the queue
+    pub progress: f32,
+    pub stock: Vec<Equipment>,
+}
+
+impl Armory {
+    /// Index of the weapon in stock that is worth more than `current`, the best one first.
+    pub fn better_weapon(
+        &self,
+        current: Option<Weapon>,
+        definitions: &Definitions,
+    ) -> Option<usize> {
+        let current = current.map(Equipment::Weapon);
+        self.better(current, definitions, |e| matches!(e, Equipment::Weapon(_)))
+    }
+
+    pub fn better_armor(&self, current: Option<Armor>, definitions: &Definitions) -> Option<usize> {
+        let current = current.map(Equipment::Armor);
+        self.better(current, definitions, |e| matches!(e, Equipment::Armor(_)))
+    }
+
+    fn better(
+        &self,
+        current: Option<Equipment>,
+        definitions: &Definitions,
+        slot: fn(Equipment) -> bool,
+    ) -> Option<usize> {
+        let current = current.map_or(0, |equipment| equipment.value(definitions));
+        self.stock
+            .iter()
+            .enumerate()
+            .filter(|(_, equipment)| slot(**equipment) && equipment.value(definitions) > current)
+            // the oldest piece among equally good ones
+            .max_by_key(|(index, equipment)| (equipment.value(definitions), usize::MAX - index))
+            .map(|(index, _)| index)
+    }
+}
+
+pub(crate) fn start_crafting(
+    mut events: EventReader<CraftEvent>,
+    mut armory_query: Query<(&mut Armory, &Transform, &Team)>,
+    research: Res<ResearchState>,
+    mut stats: ResMut<Stats>,
+    definitions: Res<Definitions>,
+) {
+    for CraftEvent(equipment, team) in events.iter() {
+        if !equipment.unlocked(*team, &research, &definitions) {
+            info!("{equipment:?} needs research first");
+            continue;
+        }
+        let cost = equipment.cost(&definitions);
+        if !cost.affordable(&stats.team(*team)) {
+            info!("not enough resources for {equipment:?}");
+            continue;
+        }
+        // ties go to the leftmost armory, so every machine picks the same one
+        let Some((mut armory, _, _)) = armory_query
+            .iter_mut()
+            .filter(|(_, _, armory_team)| *armory_team == team)
+            .min_by(|(a, a_transform, _), (b, b_transform, _)| {
+                let a_pos = a_transform.translation.truncate();
+                let b_pos = b_transform.translation.truncate();
+                (a.queue.len(), a_pos.x, a_pos.y)
+                    .partial_cmp(&(b.queue.len(), b_pos.x, b_pos.y))
+                    .expect("finite armory positions")
+            })
+        else {
+            info!("there is no armory to craft {equipment:?}");
+            continue;
+        };
+        cost.pay(stats.team_mut(*team));
+        armory.queue.push_back(*equipment);
+    }
+}
+
+pub(crate) fn armory_crafting(mut query: Query<&mut Armory>, definitions: Res<Definitions>) {
+    for mut armory in query.iter_mut() {
+        let Some(equipment) = armory.queue.front().copied() else {
+            continue;
+        };
+        armory.progress += tick_seconds();
+        if armory.progress >= equipment.craft_time(&definitions) {
+            armory.progress = 0.0;
+            armory.queue.pop_front();
+            armory.stock.push(equipment);
+        }
+    }
+}
+
+// soldiers that reached an armory take the best gear in stock, in `SimId` order so every
+// machine hands out the same pieces
+pub(crate) fn soldier_pick_up(
+    mut soldier_query: Query<(&SimId, &mut Soldier, &Transform)>,
+    mut armory_query: Query<(&mut Armory, &Transform)>,
+    definitions: Res<Definitions>,
+) {
+    let reach = definitions.building(BuildingKind::Armory).size;
+    let mut arrived = soldier_query
+        .iter_mut()
+        .filter_map(|(id, soldier, transform)| {
+            let SoldierAction::Equip(armory) = soldier.action() else {
+                return None;
+            };
+            let (_, armory_transform) = armory_query.get(*armory).ok()?;
+            let distance = armory_transform
+                .translation
+                .truncate()
+                .distance(transform.translation.truncate());
+            (distance <= reach).then_some((*id, *armory, soldier))
+        })
+        .collect::<Vec<_>>();
+    arrived.sort_by_key(|(id, _, _)| *id);
+    for (_, armory, mut soldier) in arrived {
+        let (mut armory, _) = armory_query.get_mut(armory).expect("armory checked above");
+        if let Some(index) = armory.better_weapon(soldier.weapon(), &definitions) {
+            soldier.equip(armory.stock.remove(index));
+        }
+        if let Some(index) = armory.better_armor(soldier.armor(), &definitions) {
+            soldier.equip(armory.stock.remove(index));
+        }
+        soldier.stop();
+    }
+}
@@ -0,0 +1,337 @@
+use std::f32::consts::FRAC_PI_2;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use bevy_tweening::lens::TransformRotateZLens;
+use bevy_tweening::{Animator, EaseFunction, Sequence, Tween, TweenCompleted};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::simulation::{
+    seconds_to_ticks, tick_seconds, SimulationAppExt, SimulationSet, SimulationTick,
+};
+use crate::terrain::{Terrain, TerrainMap};
+use crate::util::random_vec2_from;
+use crate::world::{terrain_height, TREE_HEIGHT};
+use crate::{SpriteSheets, Tree, TreeChopEvent, TreeSpawnEvent, WorldSeed, YSort};
+
+pub struct ForestPlugin;
+
+impl Plugin for ForestPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_simulation_event::<TreeFelledEvent>()
+            .add_simulation_event::<ClearStumpEvent>()
+            .add_systems(
+                (stump_decay, forest_spread, sapling_growth)
+                    .in_set(SimulationSet::Actions)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            )
+            .add_systems(
+                (tree_death, stump_clearing)
+                    .in_set(SimulationSet::Effects)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            );
+    }
+}
+
+// every mature tree gets a chance to sprout a sapling this often
+const SPREAD_INTERVAL: f32 = 10.0;
+const SPREAD_CHANCE: f64 = 0.05;
+const SPREAD_MIN_DISTANCE: f32 = 12.0;
+const SPREAD_MAX_DISTANCE: f32 = 48.0;
+// saplings don't sprout closer than this to another tree
+const TREE_SPACING: f32 = 10.0;
+// seconds a sapling shows each frame of the `trees` atlas
+const GROWTH_STAGE_TIME: f32 = 60.0;
+// saplings start at frame 1 and are mature at the last frame
+pub const MATURE_INDEX: usize = 3;
+
+// trees show their damaged frame at or below this much resource
+const DAMAGED_RESOURCE: i32 = 50;
+// the thinned out crown of the frame before the mature one, stumps show frame 0
+const DAMAGED_INDEX: usize = MATURE_INDEX - 1;
+// stumps rot away after this many seconds, until then nothing can be placed on them
+const STUMP_LIFETIME: f32 = 120.0;
+const SHAKE_ANGLE: f32 = 0.08;
+// `TweenCompleted::user_data` of the tween that fells a tree
+const TREE_FELLED: u64 = 1;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TreeState {
+    Full,
+    Damaged,
+    Stump,
+}
+
+impl Tree {
+    pub fn state(&self) -> TreeState {
+        match self.resource {
+            r if r > DAMAGED_RESOURCE => TreeState::Full,
+            r if r > 0 => TreeState::Damaged,
+            _ => TreeState::Stump,
+        }
+    }
+
+    /// The frame of the `trees` atlas for the state of the tree, full trees keep `grown`.
+    pub fn frame(&self, grown: usize) -> usize {
+        match self.state() {
+            TreeState::Full => grown,
+            TreeState::Damaged => DAMAGED_INDEX,
+            TreeState::Stump => 0,
+        }
+    }
+}
+
+/// What is left of a felled tree. The entity keeps its `Tree` with no resource left.
+#[derive(Component, Default, Clone, Serialize, Deserialize)]
+pub struct Stump {
+    age: f32,
+}
+
+/// A tree that is still growing, it can't be chopped until it is mature.
+#[derive(Component, Default, Clone, Serialize, Deserialize)]
+pub struct Sapling {
+    age: f32,
+}
+
+// sent when the last chop turns a tree into a stump
+pub struct TreeFelledEvent {
+    pub tree: Entity,
+    // frame of the `trees` atlas the tree showed before it was felled
+    pub index: usize,
+}
+
+// a lumberjack finished clearing a stump
+pub struct ClearStumpEvent(pub Entity);
+
+// the falling tree is a copy of the felled one, so the tree entity can stay as the stump
+#[derive(Component)]
+pub(crate) struct FallingTree;
+
+pub(crate) fn tree_death(
+    mut tree_chop_event: EventReader<TreeChopEvent>,
+    mut tree_felled_events: EventWriter<TreeFelledEvent>,
+    mut query: Query<(&mut Tree, &mut TextureAtlasSprite)>,
+    mut commands: Commands,
+) {
+    for event in tree_chop_event.iter() {
+        let Ok((mut tree, mut sprite)) = query.get_mut(event.0) else {
+            continue;
+        };
+        // several lumberjacks can finish a chop on the same tree in one frame
+        if tree.state() == TreeState::Stump {
+            continue;
+        }
+        let before = sprite.index;
+        tree.resource -= 1;
+        let frame = tree.frame(sprite.index);
+        if sprite.index != frame {
+            sprite.index = frame;
+        }
+        if tree.state() == TreeState::Stump {
+            tree_felled_events.send(TreeFelledEvent {
+                tree: event.0,
+                index: before,
+            });
+            commands.entity(event.0).insert(Stump::default());
+        }
+    }
+}
+
+// the frame follows the resource, so restored and streamed in trees show it too
+pub(crate) fn tree_frame(mut query: Query<(&Tree, &mut TextureAtlasSprite), Changed<Tree>>) {
+    for (tree, mut sprite) in query.iter_mut() {
+        let frame = tree.frame(sprite.index);
+        if sprite.index != frame {
+            sprite.index = frame;
+        }
+    }
+}
+
+// trees shake while they are chopped, the last chop fells them
+pub(crate) fn tree_shake(
+    mut tree_chop_event: EventReader<TreeChopEvent>,
+    query: Query<&Tree>,
+    mut commands: Commands,
+) {
+    for event in tree_chop_event.iter() {
+        if query
+            .get(event.0)
+            .is_ok_and(|tree| tree.state() != TreeState::Stump)
+        {
+            commands
+                .entity(event.0)
+                .insert(Animator::new(shake_tween()));
+        }
+    }
+}
+
+pub(crate) fn tree_fall(
+    mut tree_felled_events: EventReader<TreeFelledEvent>,
+    query: Query<&Transform>,
+    sprite_sheets: Res<SpriteSheets>,
+    mut commands: Commands,
+) {
+    for event in tree_felled_events.iter() {
+        let Ok(transform) = query.get(event.tree) else {
+            continue;
+        };
+        let side = if rand::random() { 1.0 } else { -1.0 };
+        commands
+            .spawn(SpriteSheetBundle {
+                texture_atlas: sprite_sheets.trees.clone(),
+                transform: transform.with_rotation(Quat::IDENTITY),
+                sprite: TextureAtlasSprite {
+                    index: event.index,
+                    ..default()
+                },
+                ..default()
+            })
+            .insert(Name::new("Falling Tree"))
+            .insert(YSort)
+            .insert(FallingTree)
+            .insert(Animator::new(
+                Tween::new(
+                    EaseFunction::QuadraticIn,
+                    Duration::from_millis(600),
+                    TransformRotateZLens {
+                        start: 0.0,
+                        end: side * FRAC_PI_2,
+                    },
+                )
+                .with_completed_event(TREE_FELLED),
+            ));
+        commands
+            .entity(event.tree)
+            .remove::<Animator<Transform>>()
+            .insert(transform.with_rotation(Quat::IDENTITY));
+    }
+}
+
+fn shake_tween() -> Sequence<Transform> {
+    let step = |start, end| {
+        Tween::new(
+            EaseFunction::SineInOut,
+            Duration::from_millis(60),
+            TransformRotateZLens { start, end },
+        )
+    };
+    step(0.0, SHAKE_ANGLE)
+        .then(step(SHAKE_ANGLE, -SHAKE_ANGLE))
+        .then(step(-SHAKE_ANGLE, 0.0))
+}
+
+pub(crate) fn fallen_tree_cleanup(
+    mut tween_completed: EventReader<TweenCompleted>,
+    query: Query<(), With<FallingTree>>,
+    mut commands: Commands,
+) {
+    for event in tween_completed.iter() {
+        if event.user_data == TREE_FELLED && query.contains(event.entity) {
+            commands.entity(event.entity).despawn_recursive();
+        }
+    }
+}
+
+fn stump_decay(mut query: Query<(Entity, &mut Stump)>, mut commands: Commands) {
+    for (entity, mut stump) in query.iter_mut() {
+        stump.age += tick_seconds();
+        if stump.age >= STUMP_LIFETIME {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+// several lumberjacks can finish clearing the same stump in one tick
+fn stump_clearing(
+    mut events: EventReader<ClearStumpEvent>,
+    query: Query<(), With<Stump>>,
+    mut commands: Commands,
+) {
+    let mut cleared = HashSet::new();
+    for ClearStumpEvent(stump) in events.iter() {
+        if query.contains(*stump) && cleared.insert(*stump) {
+            commands.entity(*stump).despawn_recursive();
+        }
+    }
+}
+
+/// Whether a stump stands within `radius` of `pos`, nothing can be placed there until it is
+/// cleared or has rotted away.
+pub fn stump_near(stumps: &Query<&Transform, With<Stump>>, pos: Vec2, radius: f32) -> bool {
+    stumps
+        .iter()
+        .any(|transform| transform.translation.truncate().distance(pos) < radius)
+}
+
+fn forest_spread(
+    tick: Res<SimulationTick>,
+    seed: Res<WorldSeed>,
+    terrain_map: Option<Res<TerrainMap>>,
+    tree_query: Query<(&Transform, &Tree, Option<&Sapling>)>,
+    mut spawn_tree_events: EventWriter<TreeSpawnEvent>,
+) {
+    if !tick.is_multiple_of(seconds_to_ticks(SPREAD_INTERVAL)) {
+        return;
+    }
+    let Some(terrain_map) = terrain_map else {
+        return;
+    };
+
+    let noise_offset = seed.noise_offset();
+    let mut rng = seed.tick_rng(**tick);
+    // stumps and saplings also block the spot they stand on
+    let mut trees = tree_query
+        .iter()
+        .map(|(transform, _, _)| transform.translation.truncate())
+        .collect::<Vec<_>>();
+    let mut parents = tree_query
+        .iter()
+        .filter(|(_, tree, sapling)| sapling.is_none() && tree.state() != TreeState::Stump)
+        .map(|(transform, _, _)| transform.translation.truncate())
+        .collect::<Vec<_>>();
+    // the random numbers go to the same trees however the entities are stored
+    parents.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+    for parent in parents {
+        if !rng.gen_bool(SPREAD_CHANCE) {
+            continue;
+        }
+        let direction = random_vec2_from(&mut rng).normalize_or_zero();
+        let distance = rng.gen_range(SPREAD_MIN_DISTANCE..SPREAD_MAX_DISTANCE);
+        let pos = (parent + direction * distance).round();
+        // same rules as the trees placed by world generation
+        let fertile = matches!(
+            terrain_map.terrain_at(pos),
+            Some(Terrain::Grass | Terrain::DeadGrass)
+        ) && TREE_HEIGHT.contains(&terrain_height(pos, noise_offset));
+        if fertile
+            && trees
+                .iter()
+                .all(|tree| tree.distance_squared(pos) > TREE_SPACING * TREE_SPACING)
+        {
+            trees.push(pos);
+            spawn_tree_events.send(TreeSpawnEvent {
+                pos,
+                index: 1,
+                sapling: true,
+            });
+        }
+    }
+}
+
+fn sapling_growth(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut Sapling, &mut TextureAtlasSprite)>,
+) {
+    for (entity, mut sapling, mut sprite) in query.iter_mut() {
+        sapling.age += tick_seconds();
+        let index = (1 + (sapling.age / GROWTH_STAGE_TIME) as usize).min(MATURE_INDEX);
+        if sprite.index != index {
+            sprite.index = index;
+        }
+        if index == MATURE_INDEX {
+            commands.entity(entity).remove::<Sapling>();
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use bevy::diagnostic::{Diagnostics, FrameTimeDiagnosticsPlugin};
+use bevy::diagnostic::{Diagnostics, EntityCountDiagnosticsPlugin, FrameTimeDiagnosticsPlugin};
 use bevy::prelude::*;
 
 pub struct FpsPlugin;
@@ -9,6 +9,7 @@ struct FpsMarker;
 impl Plugin for FpsPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugin(FrameTimeDiagnosticsPlugin)
+            .add_plugin(EntityCountDiagnosticsPlugin)
             .add_startup_system(plugin_init)
             .add_system(text_change);
     }
@@ -41,11 +42,19 @@ fn plugin_init(mut commands: Commands, asset_server: Res<AssetServer>) {
 }
 
 fn text_change(diagnostics: Res<Diagnostics>, mut query: Query<&mut Text, With<FpsMarker>>) {
-    let fps = diagnostics
-        .get(FrameTimeDiagnosticsPlugin::FPS)
-        .and_then(|fps_diagnostic| fps_diagnostic.average())
-        .unwrap_or(0.0);
+    let average = |id| {
+        diagnostics
+            .get(id)
+            .and_then(|diagnostic| diagnostic.average())
+            .unwrap_or(0.0)
+    };
+    let fps = average(FrameTimeDiagnosticsPlugin::FPS);
+    let frame_time = average(FrameTimeDiagnosticsPlugin::FRAME_TIME);
+    let entities = average(EntityCountDiagnosticsPlugin::ENTITY_COUNT);
 
     // println!("fps={:.1}", fps);
-    query.single_mut().sections[0].value = format!("fps = {:.1}", fps)
+    query.single_mut().sections[0].value = format!(
+        "fps = {:.1}\nframe = {:.2} ms\nentities = {:.0}",
+        fps, frame_time, entities
+    )
 }
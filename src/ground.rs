@@ -0,0 +1,187 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::render::mesh::Indices;
+use bevy::render::render_resource::PrimitiveTopology;
+use bevy::sprite::MaterialMesh2dBundle;
+use bevy::utils::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::terrain::{GroundSheet, TILE_SIZE};
+use crate::{Cull2D, Ground, SpriteSheets};
+
+// tiles per chunk side
+pub const CHUNK_SIZE: i32 = 32;
+
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum WorldSize {
+    // width and height in tiles, centered on the origin
+    Fixed(UVec2),
+    // sandbox mode, chunks are generated around the camera as it moves
+    Infinite,
+}
+
+impl Default for WorldSize {
+    fn default() -> Self {
+        WorldSize::Fixed(UVec2::new(100, 100))
+    }
+}
+
+#[derive(Component)]
+pub struct GroundChunk(pub IVec2);
+
+struct Quad {
+    pos: Vec2,
+    index: usize,
+    flip_x: bool,
+}
+
+/// The tiles of one chunk, grouped by atlas and layer so each group becomes one mesh. Stays
+/// on the chunk until `ground_meshes` has built them.
+#[derive(Component, Default)]
+pub struct ChunkLayers {
+    groups: HashMap<(GroundSheet, usize), Vec<Quad>>,
+}
+
+impl ChunkLayers {
+    // `pos` is relative to the chunk origin
+    pub fn add(&mut self, sheet: GroundSheet, layer: usize, pos: Vec2, index: usize, flip_x: bool) {
+        self.groups
+            .entry((sheet, layer))
+            .or_default()
+            .push(Quad { pos, index, flip_x });
+    }
+}
+
+pub fn spawn_chunk(
+    commands: &mut Commands,
+    chunk: IVec2,
+    origin: Vec2,
+    layers: ChunkLayers,
+) -> Entity {
+    commands
+        .spawn(SpatialBundle::from_transform(Transform::from_translation(
+            origin.extend(0.0),
+        )))
+        .insert(Name::new("Ground Chunk"))
+        .insert(Ground)
+        .insert(GroundChunk(chunk))
+        .insert(layers)
+        .id()
+}
+
+#[derive(SystemParam)]
+pub struct GroundBuilder<'w, 's> {
+    sprite_sheets: Res<'w, SpriteSheets>,
+    texture_atlases: Res<'w, Assets<TextureAtlas>>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+    materials: ResMut<'w, Assets<ColorMaterial>>,
+    material_cache: Local<'s, HashMap<Handle<TextureAtlas>, Handle<ColorMaterial>>>,
+}
+
+/// Spawns one mesh per atlas and layer of every new chunk. Bevy culls them per chunk, so
+/// there is no need for `Cull2D` here.
+pub fn ground_meshes(
+    mut query: Query<(Entity, &mut ChunkLayers), Added<ChunkLayers>>,
+    mut ground: GroundBuilder,
+    mut commands: Commands,
+) {
+    for (entity, mut layers) in query.iter_mut() {
+        let groups = std::mem::take(&mut layers.groups);
+        commands
+            .entity(entity)
+            .remove::<ChunkLayers>()
+            .with_children(|builder| {
+                for ((sheet, layer), quads) in groups {
+                    let texture_atlas = ground.sprite_sheets.ground(sheet);
+                    let atlas = ground
+                        .texture_atlases
+                        .get(&texture_atlas)
+                        .expect("a ground texture atlas");
+                    let mesh = ground.meshes.add(chunk_mesh(atlas, &quads));
+                    let material = ground
+                        .material_cache
+                        .entry(texture_atlas)
+                        .or_insert_with(|| ground.materials.add(atlas.texture.clone().into()))
+                        .clone();
+                    builder
+                        .spawn(MaterialMesh2dBundle {
+                            mesh: mesh.into(),
+                            material,
+                            transform: Transform::from_xyz(0.0, 0.0, layer as f32 * 0.1),
+                            ..default()
+                        })
+                        .insert(Ground);
+                }
+            });
+    }
+}
+
+/// The ground as it was drawn before chunk meshes, one culled sprite per tile and layer.
+/// Only the benchmark uses it, to compare against `ground_meshes`.
+pub fn ground_sprites(
+    mut query: Query<(Entity, &mut ChunkLayers), Added<ChunkLayers>>,
+    sprite_sheets: Res<SpriteSheets>,
+    mut commands: Commands,
+) {
+    for (entity, mut layers) in query.iter_mut() {
+        let groups = std::mem::take(&mut layers.groups);
+        commands
+            .entity(entity)
+            .remove::<ChunkLayers>()
+            .with_children(|builder| {
+                for ((sheet, layer), quads) in groups {
+                    for quad in quads {
+                        builder
+                            .spawn(SpriteSheetBundle {
+                                texture_atlas: sprite_sheets.ground(sheet),
+                                sprite: TextureAtlasSprite {
+                                    index: quad.index,
+                                    flip_x: quad.flip_x,
+                                    ..default()
+                                },
+                                transform: Transform::from_translation(
+                                    quad.pos.extend(layer as f32 * 0.1),
+                                ),
+                                ..default()
+                            })
+                            .insert(Ground)
+                            .insert(Cull2D);
+                    }
+                }
+            });
+    }
+}
+
+fn chunk_mesh(atlas: &TextureAtlas, quads: &[Quad]) -> Mesh {
+    let mut positions = Vec::with_capacity(quads.len() * 4);
+    let mut uvs = Vec::with_capacity(quads.len() * 4);
+    let mut indices = Vec::with_capacity(quads.len() * 6);
+    for quad in quads {
+        let rect = atlas.textures[quad.index];
+        let (min, max) = (rect.min / atlas.size, rect.max / atlas.size);
+        let (left, right) = if quad.flip_x {
+            (max.x, min.x)
+        } else {
+            (min.x, max.x)
+        };
+        let half = TILE_SIZE / 2.0;
+        let start = positions.len() as u32;
+        positions.extend([
+            [quad.pos.x - half, quad.pos.y - half, 0.0],
+            [quad.pos.x + half, quad.pos.y - half, 0.0],
+            [quad.pos.x + half, quad.pos.y + half, 0.0],
+            [quad.pos.x - half, quad.pos.y + half, 0.0],
+        ]);
+        // texture rows go down while world y goes up
+        uvs.extend([[left, max.y], [right, max.y], [right, min.y], [left, min.y]]);
+        indices.extend([start, start + 1, start + 2, start, start + 2, start + 3]);
+    }
+    let normals = vec![[0.0, 0.0, 1.0]; positions.len()];
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
@@ -0,0 +1,236 @@
+use std::f32::consts::FRAC_PI_2;
+use std::time::Duration;
+
+use bevy::ecs::entity::{EntityMap, MapEntities, MapEntitiesError};
+use bevy::prelude::*;
+use bevy::sprite::Anchor;
+use bevy_tweening::lens::TransformRotateZLens;
+use bevy_tweening::{Animator, Delay, EaseFunction, Tween, TweenCompleted};
+use serde::{Deserialize, Serialize};
+
+use crate::definition::Definitions;
+use crate::simulation::{SimulationAppExt, SimulationSet};
+use crate::soldier::Soldier;
+use crate::unit::{SelectedMark, Unit};
+use crate::util::TextureAtlasSpriteColorLens;
+use crate::{Cull2D, YSort};
+
+pub struct HealthPlugin;
+
+impl Plugin for HealthPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_simulation_event::<DamageEvent>()
+            .add_simulation_event::<UnitDeathEvent>()
+            .add_simulation_event::<DamageDealtEvent>()
+            .add_systems(
+                (apply_damage, unit_death)
+                    .chain()
+                    .in_set(SimulationSet::Effects)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            );
+    }
+}
+
+const HEALTH_BAR_WIDTH: f32 = 12.0;
+const HEALTH_BAR_HEIGHT: f32 = 2.0;
+// corpses lie around for a while before they fade out
+const CORPSE_TIME: Duration = Duration::from_secs(10);
+const CORPSE_FADE_TIME: Duration = Duration::from_secs(3);
+// `TweenCompleted::user_data` of the tween that fades out a corpse
+const CORPSE_FADED: u64 = 2;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DamageEvent {
+    pub target: Entity,
+    pub amount: f32,
+    // the unit that strikes, it gains experience
+    #[serde(default)]
+    pub source: Option<Entity>,
+}
+
+// a source that is gone by now gets no credit
+impl MapEntities for DamageEvent {
+    fn map_entities(&mut self, entity_map: &EntityMap) -> Result<(), MapEntitiesError> {
+        self.target = entity_map.get(self.target)?;
+        self.source = self.source.and_then(|source| entity_map.get(source).ok());
+        Ok(())
+    }
+}
+
+// the hp a strike actually took after armor, strikes on dead units and overkill don't count
+pub struct DamageDealtEvent {
+    pub source: Entity,
+    pub amount: f32,
+}
+
+// sent once when the hp of a unit drops to zero, the unit is despawned and leaves a corpse
+pub struct UnitDeathEvent {
+    pub unit: Entity,
+    pub killer: Option<Entity>,
+}
+
+#[derive(Component)]
+pub struct HealthBar;
+
+#[derive(Component)]
+pub(crate) struct Corpse;
+
+/// Spawns the health bar of a unit, call this inside `with_children` like the `SelectionBox`.
+pub fn spawn_health_bar(builder: &mut ChildBuilder) {
+    builder
+        .spawn(SpriteBundle {
+            sprite: Sprite {
+                color: Color::GREEN,
+                custom_size: Some(Vec2::new(HEALTH_BAR_WIDTH, HEALTH_BAR_HEIGHT)),
+                // shrinks towards the left as the unit loses hp
+                anchor: Anchor::CenterLeft,
+                ..default()
+            },
+            transform: Transform::from_xyz(-HEALTH_BAR_WIDTH / 2.0, 10.0, 0.1),
+            visibility: Visibility::Hidden,
+            ..default()
+        })
+        .insert(HealthBar);
+}
+
+pub(crate) fn apply_damage(
+    mut damage_events: EventReader<DamageEvent>,
+    mut death_events: EventWriter<UnitDeathEvent>,
+    mut dealt_events: EventWriter<DamageDealtEvent>,
+    mut query: Query<(&mut Unit, Option<&Soldier>)>,
+    definitions: Res<Definitions>,
+) {
+    for event in damage_events.iter() {
+        let Ok((mut unit, soldier)) = query.get_mut(event.target) else {
+            continue;
+        };
+        // already dead units wait for `unit_death` and don't die twice
+        if unit.hp <= 0.0 {
+            continue;
+        }
+        let protection = soldier.map_or(0.0, |soldier| soldier.loadout(&definitions).protection);
+        let dealt = (event.amount * (1.0 - protection)).min(unit.hp);
+        unit.hp -= dealt;
+        if let Some(source) = event.source {
+            dealt_events.send(DamageDealtEvent {
+                source,
+                amount: dealt,
+            });
+        }
+        if unit.hp <= 0.0 {
+            death_events.send(UnitDeathEvent {
+                unit: event.target,
+                killer: event.source,
+            });
+        }
+    }
+}
+
+fn unit_death(mut death_events: EventReader<UnitDeathEvent>, mut commands: Commands) {
+    for UnitDeathEvent { unit, .. } in death_events.iter() {
+        if let Some(entity) = commands.get_entity(*unit) {
+            entity.despawn_recursive();
+        }
+    }
+}
+
+// runs before the despawn of `unit_death` is applied, so the dead unit can still be copied
+pub(crate) fn spawn_corpse(
+    mut death_events: EventReader<UnitDeathEvent>,
+    query: Query<(
+        &Unit,
+        &Transform,
+        &Handle<TextureAtlas>,
+        &TextureAtlasSprite,
+    )>,
+    mut commands: Commands,
+) {
+    for UnitDeathEvent { unit: entity, .. } in death_events.iter() {
+        let Ok((unit, transform, texture_atlas, sprite)) = query.get(*entity) else {
+            continue;
+        };
+        // fall over backwards
+        let side = if unit.last_direction.x < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        commands
+            .spawn(SpriteSheetBundle {
+                texture_atlas: texture_atlas.clone(),
+                transform: *transform,
+                sprite: TextureAtlasSprite {
+                    index: sprite.index,
+                    ..default()
+                },
+                ..default()
+            })
+            .insert(Name::new("Corpse"))
+            .insert(YSort)
+            .insert(Cull2D)
+            .insert(Corpse)
+            .insert(Animator::new(Tween::new(
+                EaseFunction::QuadraticIn,
+                Duration::from_millis(300),
+                TransformRotateZLens {
+                    start: 0.0,
+                    end: side * FRAC_PI_2,
+                },
+            )))
+            .insert(Animator::new(
+                Delay::new(CORPSE_TIME).then(
+                    Tween::new(
+                        EaseFunction::QuadraticOut,
+                        CORPSE_FADE_TIME,
+                        TextureAtlasSpriteColorLens {
+                            start: Color::WHITE,
+                            end: Color::NONE,
+                        },
+                    )
+                    .with_completed_event(CORPSE_FADED),
+                ),
+            ));
+    }
+}
+
+pub(crate) fn corpse_cleanup(
+    mut tween_completed: EventReader<TweenCompleted>,
+    query: Query<(), With<Corpse>>,
+    mut commands: Commands,
+) {
+    for event in tween_completed.iter() {
+        if event.user_data == CORPSE_FADED && query.contains(event.entity) {
+            commands.entity(event.entity).despawn_recursive();
+        }
+    }
+}
+
+// shown while the unit is damaged or selected
+pub(crate) fn health_bar(
+    unit_query: Query<(&Unit, &Children, Option<&SelectedMark>)>,
+    mut bar_query: Query<(&mut Sprite, &mut Visibility), With<HealthBar>>,
+) {
+    for (unit, children, selected) in unit_query.iter() {
+        let ratio = (unit.hp / unit.max_hp).clamp(0.0, 1.0);
+        let visibility = if selected.is_some() || ratio < 1.0 {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+        let size = Some(Vec2::new(HEALTH_BAR_WIDTH * ratio, HEALTH_BAR_HEIGHT));
+        for child in children.iter() {
+            let Ok((mut sprite, mut bar_visibility)) = bar_query.get_mut(*child) else {
+                continue;
+            };
+            if *bar_visibility != visibility {
+                *bar_visibility = visibility;
+            }
+            if sprite.custom_size != size {
+                let red: Vec4 = Color::RED.into();
+                let green: Vec4 = Color::GREEN.into();
+                sprite.custom_size = size;
+                sprite.color = red.lerp(green, ratio).into();
+            }
+        }
+    }
+}
@@ -0,0 +1,368 @@
+use std::fmt;
+use std::fs;
+use std::time::SystemTime;
+
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use serde::{Deserialize, Serialize};
+
+pub struct InputActionPlugin;
+
+impl Plugin for InputActionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<InputBindings>()
+            .init_resource::<Input<InputAction>>()
+            .add_startup_system(input_config_load)
+            .add_system(input_config_reload)
+            .add_system(
+                input_action_update
+                    .in_base_set(CoreSet::PreUpdate)
+                    .after(InputSystem),
+            );
+    }
+}
+
+const INPUT_CONFIG_PATH: &str = "config/input.ron";
+const RELOAD_INTERVAL: f32 = 1.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum InputAction {
+    CameraPanLeft,
+    CameraPanRight,
+    CameraPanUp,
+    CameraPanDown,
+    CameraDrag,
+    Select,
+    Command,
+    // held while selecting or ordering to add instead of replace
+    Queue,
+    // held while clicking a unit or pressing a control group key
+    GroupModifier,
+    ControlGroup(usize),
+    CycleSubgroup,
+    Stop,
+    HoldPosition,
+    // the next command click picks the target
+    AttackMove,
+    // the next command click picks the patrol point
+    Patrol,
+    // the next command click picks the building site, lumberjacks build there
+    BuildBarrack,
+    BuildArmory,
+    CycleStance,
+    ToggleFullscreen,
+    QuickSave,
+    QuickLoad,
+    Debug,
+    Pause,
+    SpeedUp,
+    SpeedDown,
+    ToggleResearch,
+    ToggleArmory,
+    // only in the replay viewer
+    ReplaySpeed,
+    ReplayVision,
+}
+
+// actions that are expected to share a binding, e.g. right mouse both drags the camera and
+// issues a command
+const SHARED_BINDINGS: [(InputAction, InputAction); 1] =
+    [(InputAction::CameraDrag, InputAction::Command)];
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+#[derive(Resource, Serialize, Deserialize)]
+pub struct InputBindings {
+    bindings: HashMap<InputAction, Vec<Binding>>,
+}
+
+#[derive(Debug)]
+pub enum InputConfigError {
+    Io(std::io::Error),
+    Parse(ron::error::SpannedError),
+    Conflict(Binding, InputAction, InputAction),
+    InvalidControlGroup(usize),
+}
+
+impl fmt::Display for InputConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputConfigError::Io(e) => write!(f, "could not read input config: {e}"),
+            InputConfigError::Parse(e) => write!(f, "could not parse input config: {e}"),
+            InputConfigError::Conflict(binding, a, b) => {
+                write!(f, "{binding:?} is bound to both {a:?} and {b:?}")
+            }
+            InputConfigError::InvalidControlGroup(group) => {
+                write!(f, "control group {group} is out of range 1..=9")
+            }
+        }
+    }
+}
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        use Binding::*;
+        use InputAction::*;
+        let control_group_keys = [
+            KeyCode::Key1,
+            KeyCode::Key2,
+            KeyCode::Key3,
+            KeyCode::Key4,
+            KeyCode::Key5,
+            KeyCode::Key6,
+            KeyCode::Key7,
+            KeyCode::Key8,
+            KeyCode::Key9,
+        ];
+        let mut bindings = HashMap::from_iter([
+            (CameraPanLeft, vec![Key(KeyCode::Left)]),
+            (CameraPanRight, vec![Key(KeyCode::Right)]),
+            (CameraPanUp, vec![Key(KeyCode::Up)]),
+            (CameraPanDown, vec![Key(KeyCode::Down)]),
+            (CameraDrag, vec![Mouse(MouseButton::Right)]),
+            (Select, vec![Mouse(MouseButton::Left)]),
+            (Command, vec![Mouse(MouseButton::Right)]),
+            (Queue, vec![Key(KeyCode::LShift), Key(KeyCode::RShift)]),
+            (
+                GroupModifier,
+                vec![Key(KeyCode::LControl), Key(KeyCode::RControl)],
+            ),
+            (CycleSubgroup, vec![Key(KeyCode::Tab)]),
+            (Stop, vec![Key(KeyCode::S)]),
+            (HoldPosition, vec![Key(KeyCode::H)]),
+            (AttackMove, vec![Key(KeyCode::A)]),
+            (Patrol, vec![Key(KeyCode::P)]),
+            (BuildBarrack, vec![Key(KeyCode::C)]),
+            (BuildArmory, vec![Key(KeyCode::N)]),
+            (CycleStance, vec![Key(KeyCode::V)]),
+            (ToggleFullscreen, vec![Key(KeyCode::F11)]),
+            (QuickSave, vec![Key(KeyCode::F5)]),
+            (QuickLoad, vec![Key(KeyCode::F9)]),
+            (Debug, vec![Key(KeyCode::Space)]),
+            (Pause, vec![Key(KeyCode::Pause), Key(KeyCode::F1)]),
+            (SpeedUp, vec![Key(KeyCode::Equals), Key(KeyCode::NumpadAdd)]),
+            (
+                SpeedDown,
+                vec![Key(KeyCode::Minus), Key(KeyCode::NumpadSubtract)],
+            ),
+            (ToggleResearch, vec![Key(KeyCode::R)]),
+            (ToggleArmory, vec![Key(KeyCode::B)]),
+            (ReplaySpeed, vec![Key(KeyCode::F2)]),
+            (ReplayVision, vec![Key(KeyCode::F3)]),
+        ]);
+        for (i, key) in control_group_keys.into_iter().enumerate() {
+            bindings.insert(ControlGroup(i + 1), vec![Key(key)]);
+        }
+        InputBindings { bindings }
+    }
+}
+
+impl InputBindings {
+    pub fn from_ron(source: &str) -> Result<Self, InputConfigError> {
+        let mut config: InputBindings = ron::from_str(source).map_err(InputConfigError::Parse)?;
+        // actions missing from the file keep their default bindings
+        for (action, bindings) in InputBindings::default().bindings {
+            config.bindings.entry(action).or_insert(bindings);
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), InputConfigError> {
+        let mut seen = HashMap::<Binding, InputAction>::default();
+        for (action, bindings) in self.bindings.iter() {
+            if let InputAction::ControlGroup(group) = action {
+                if !(1..=9).contains(group) {
+                    return Err(InputConfigError::InvalidControlGroup(*group));
+                }
+            }
+            for binding in bindings {
+                match seen.get(binding) {
+                    Some(other) if !is_shared(*action, *other) => {
+                        return Err(InputConfigError::Conflict(*binding, *other, *action));
+                    }
+                    _ => {
+                        seen.insert(*binding, *action);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn is_active(
+        &self,
+        action: InputAction,
+        keys: &Input<KeyCode>,
+        mouse: &Input<MouseButton>,
+    ) -> bool {
+        self.bindings.get(&action).is_some_and(|bindings| {
+            bindings.iter().any(|binding| match binding {
+                Binding::Key(key) => keys.pressed(*key),
+                Binding::Mouse(button) => mouse.pressed(*button),
+            })
+        })
+    }
+}
+
+fn is_shared(a: InputAction, b: InputAction) -> bool {
+    a == b || SHARED_BINDINGS.contains(&(a, b)) || SHARED_BINDINGS.contains(&(b, a))
+}
+
+fn read_input_config() -> Result<InputBindings, InputConfigError> {
+    let source = fs::read_to_string(INPUT_CONFIG_PATH).map_err(InputConfigError::Io)?;
+    InputBindings::from_ron(&source)
+}
+
+fn input_config_load(mut bindings: ResMut<InputBindings>) {
+    match read_input_config() {
+        Ok(config) => *bindings = config,
+        Err(e) => warn!("{e}, using default bindings"),
+    }
+}
+
+fn input_config_reload(
+    mut bindings: ResMut<InputBindings>,
+    time: Res<Time>,
+    mut timer: Local<Option<Timer>>,
+    mut last_modified: Local<Option<SystemTime>>,
+    mut initialized: Local<bool>,
+) {
+    let timer =
+        timer.get_or_insert_with(|| Timer::from_seconds(RELOAD_INTERVAL, TimerMode::Repeating));
+    if !timer.tick(time.raw_delta()).just_finished() {
+        return;
+    }
+
+    let modified = fs::metadata(INPUT_CONFIG_PATH)
+        .and_then(|m| m.modified())
+        .ok();
+    if !*initialized {
+        // first check, the startup system already loaded this version
+        *initialized = true;
+        *last_modified = modified;
+        return;
+    }
+    if modified == *last_modified {
+        return;
+    }
+    *last_modified = modified;
+
+    match read_input_config() {
+        Ok(config) => {
+            info!("reloaded {INPUT_CONFIG_PATH}");
+            *bindings = config;
+        }
+        Err(e) => error!("{e}, keeping previous bindings"),
+    }
+}
+
+fn input_action_update(
+    bindings: Res<InputBindings>,
+    keys: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    mut actions: ResMut<Input<InputAction>>,
+) {
+    actions.clear();
+    for action in bindings.bindings.keys() {
+        let active = bindings.is_active(*action, &keys, &mouse);
+        if active && !actions.pressed(*action) {
+            actions.press(*action);
+        } else if !active && actions.pressed(*action) {
+            actions.release(*action);
+        }
+    }
+    // a reload can drop the binding of a held action, it would stay pressed forever
+    let unbound = actions
+        .get_pressed()
+        .filter(|action| !bindings.bindings.contains_key(action))
+        .copied()
+        .collect::<Vec<_>>();
+    for action in unbound {
+        actions.release(action);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_file_matches_defaults() {
+        let config = InputBindings::from_ron(include_str!("../config/input.ron")).unwrap();
+        assert_eq!(config.bindings, InputBindings::default().bindings);
+    }
+
+    #[test]
+    fn missing_actions_fall_back_to_defaults() {
+        let config = InputBindings::from_ron("(bindings: { Stop: [Key(X)] })").unwrap();
+        assert_eq!(
+            config.bindings[&InputAction::Stop],
+            vec![Binding::Key(KeyCode::X)]
+        );
+        assert_eq!(
+            config.bindings[&InputAction::HoldPosition],
+            vec![Binding::Key(KeyCode::H)]
+        );
+    }
+
+    #[test]
+    fn conflicting_bindings_are_rejected() {
+        // the default of `HoldPosition` is H
+        let result = InputBindings::from_ron("(bindings: { Stop: [Key(H)] })");
+        assert!(matches!(
+            result,
+            Err(InputConfigError::Conflict(Binding::Key(KeyCode::H), _, _))
+        ));
+        let result = InputBindings::from_ron("(bindings: { ControlGroup(10): [Key(Key0)] })");
+        assert!(matches!(
+            result,
+            Err(InputConfigError::InvalidControlGroup(10))
+        ));
+    }
+
+    #[test]
+    fn shared_bindings_are_allowed() {
+        let config = InputBindings::from_ron(
+            "(bindings: { CameraDrag: [Mouse(Middle)], Command: [Mouse(Middle)] })",
+        );
+        assert!(config.is_ok());
+        // only the listed pairs may share
+        let result = InputBindings::from_ron(
+            "(bindings: { CameraDrag: [Mouse(Middle)], Select: [Mouse(Middle)] })",
+        );
+        assert!(matches!(result, Err(InputConfigError::Conflict(..))));
+    }
+
+    #[test]
+    fn unbound_actions_are_released() {
+        let mut world = World::new();
+        world.insert_resource(InputBindings::default());
+        world.init_resource::<Input<KeyCode>>();
+        world.init_resource::<Input<MouseButton>>();
+        world.init_resource::<Input<InputAction>>();
+        world.resource_mut::<Input<KeyCode>>().press(KeyCode::S);
+        let mut schedule = Schedule::new();
+        schedule.add_system(input_action_update);
+
+        schedule.run(&mut world);
+        assert!(world
+            .resource::<Input<InputAction>>()
+            .pressed(InputAction::Stop));
+
+        // a reloaded config without `Stop`, while S is still held
+        world
+            .resource_mut::<InputBindings>()
+            .bindings
+            .remove(&InputAction::Stop);
+        schedule.run(&mut world);
+        let actions = world.resource::<Input<InputAction>>();
+        assert!(!actions.pressed(InputAction::Stop));
+        assert!(actions.just_released(InputAction::Stop));
+    }
+}
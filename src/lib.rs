@@ -0,0 +1,505 @@
+pub mod armory_panel;
+pub mod command;
+pub mod command_panel;
+pub mod control_group;
+pub mod definition;
+pub mod equipment;
+pub mod forest;
+pub mod fps_plugin;
+pub mod ground;
+pub mod health;
+pub mod input_action;
+pub mod lockstep;
+pub mod lumberjack;
+pub mod morale;
+pub mod presentation;
+pub mod replay;
+pub mod research;
+pub mod research_panel;
+pub mod save;
+pub mod scenario;
+pub mod scenario_panel;
+pub mod simulation;
+pub mod soldier;
+pub mod terrain;
+pub mod unit;
+pub mod util;
+pub mod veterancy;
+pub mod world;
+use crate::command::CommandPlugin;
+use crate::definition::{load_definition_atlas, Cost, Definitions};
+use crate::equipment::{Armory, EquipmentPlugin};
+use crate::forest::{ForestPlugin, Sapling};
+use crate::ground::WorldSize;
+use crate::health::HealthPlugin;
+use crate::lockstep::LockstepPlugin;
+use crate::lumberjack::*;
+use crate::morale::MoralePlugin;
+use crate::replay::ReplayPlugin;
+use crate::research::ResearchPlugin;
+use crate::save::SavePlugin;
+use crate::scenario::ScenarioPlugin;
+use crate::simulation::{configure_simulation_schedule, SimId, SimulationAppExt, SimulationSet};
+use crate::terrain::GroundSheet;
+use crate::unit::*;
+use crate::veterancy::VeterancyPlugin;
+use crate::world::WorldPlugin;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use std::collections::BTreeMap;
+
+use quadtree_rs::Quadtree;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use soldier::SoldierPlugin;
+
+use util::add_texture_atlas;
+use util::has_arg;
+use util::load_image;
+use util::{parse_arg, ArgError};
+
+/// The game rules: units, lumberjacks, soldiers, trees and the economy. Runs under
+/// `MinimalPlugins` without a window or GPU, `PresentationPlugin` draws it and takes input.
+/// Everything that changes the outcome of a match runs in fixed ticks, see `SimulationSet`.
+pub struct SimulationPlugin;
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut App) {
+        configure_simulation_schedule(app);
+        app.add_plugin(SoldierPlugin)
+            .add_plugin(UnitPlugin)
+            .add_plugin(LumberjackPlugin)
+            .add_plugin(SavePlugin)
+            .add_plugin(WorldPlugin)
+            .add_plugin(ForestPlugin)
+            .add_plugin(HealthPlugin)
+            .add_plugin(CommandPlugin)
+            .add_plugin(ResearchPlugin)
+            .add_plugin(EquipmentPlugin)
+            .add_plugin(VeterancyPlugin)
+            .add_plugin(MoralePlugin)
+            .add_plugin(ScenarioPlugin)
+            .add_plugin(ReplayPlugin)
+            .add_plugin(LockstepPlugin)
+            .init_resource::<WorldSeed>()
+            .init_resource::<WorldSize>()
+            .init_resource::<Definitions>()
+            // headless apps have no textures, `PresentationPlugin` loads the real ones
+            .init_resource::<SpriteSheets>()
+            .init_resource::<Stats>()
+            .init_resource::<UnitQuadTree>()
+            .add_simulation_event::<TreeChopEvent>()
+            .add_simulation_event::<DepositWoodEvent>()
+            .add_simulation_event::<TreeSpawnEvent>()
+            .register_type::<Lumberjack>()
+            .add_system(
+                deposit_wood_stat
+                    .in_set(SimulationSet::Effects)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            )
+            .add_system(
+                tree_spawning
+                    .in_set(SimulationSet::Input)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            );
+    }
+}
+
+// buildings
+#[derive(Component)]
+pub struct Barrack;
+
+#[derive(
+    Component, Clone, Copy, PartialEq, Eq, Hash, Debug, Reflect, FromReflect, Serialize, Deserialize,
+)]
+pub enum BuildingKind {
+    Barrack,
+    // crafts equipment for soldiers, see `Armory`
+    Armory,
+}
+
+// resources
+#[derive(Component, Clone, Serialize, Deserialize)]
+pub struct Tree {
+    resource: i32,
+}
+
+/// What every team has to spend. A team that shows up late, e.g. the second player of a
+/// lockstep match, starts with what the others were given.
+#[derive(Default, Clone, Resource, Serialize, Deserialize)]
+pub struct Stats {
+    start: Resources,
+    // ordered by team, so every machine saves and hashes the same state
+    teams: BTreeMap<Team, Resources>,
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Resources {
+    pub wood: u32,
+    pub metal: u32,
+}
+
+impl Stats {
+    pub fn team(&self, team: Team) -> Resources {
+        self.teams.get(&team).copied().unwrap_or_default()
+    }
+
+    pub fn team_mut(&mut self, team: Team) -> &mut Resources {
+        self.teams.entry(team).or_insert(self.start)
+    }
+
+    pub fn teams(&self) -> impl Iterator<Item = (Team, Resources)> + '_ {
+        self.teams
+            .iter()
+            .map(|(team, resources)| (*team, *resources))
+    }
+
+    /// Adds `amount` to every team, including the ones that show up later.
+    pub fn grant(&mut self, amount: Cost) {
+        for resources in self.teams.values_mut().chain([&mut self.start]) {
+            resources.wood += amount.wood;
+            resources.metal += amount.metal;
+        }
+    }
+}
+
+// render components
+#[derive(Component)]
+pub struct YSort;
+#[derive(Component)]
+pub struct Cull2D;
+
+/// Units by the 16 pixel cell they stand on. A quad tree only covers a fixed span, so the world
+/// is split into square regions with a tree each and an infinite world has no bounds.
+#[derive(Resource, Default)]
+pub struct UnitQuadTree {
+    trees: HashMap<IVec2, Quadtree<u32, Entity>>,
+    // cell and handle of every unit in `trees`, despawned units have no `Unit` to look them up
+    entries: HashMap<Entity, (IVec2, u64)>,
+}
+
+#[derive(Resource, Default)]
+pub struct SpriteSheets {
+    swordsman_red: Handle<TextureAtlas>,
+    box_selector: Handle<TextureAtlas>,
+    highlighted_boxes: Handle<TextureAtlas>,
+    trees: Handle<TextureAtlas>,
+    farmer_red: Handle<TextureAtlas>,
+    grass_deco: Handle<TextureAtlas>,
+    barracks_red: Handle<TextureAtlas>,
+    armory: Handle<TextureAtlas>,
+    shore: Handle<TextureAtlas>,
+    textured_grass: Handle<TextureAtlas>,
+    dead_grass: Handle<TextureAtlas>,
+    winter: Handle<TextureAtlas>,
+    cliff: Handle<TextureAtlas>,
+    cliff_water: Handle<TextureAtlas>,
+}
+
+// events
+pub struct TreeChopEvent(pub Entity);
+// wood a worker of the team brought to a barrack
+pub struct DepositWoodEvent(pub u32, pub Team);
+pub struct TreeSpawnEvent {
+    pub pos: Vec2,
+    pub index: usize,
+    // saplings grow into mature trees before they can be chopped
+    pub sapling: bool,
+}
+
+// world generation
+#[derive(Resource, Default, Clone, Copy, Deref, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct WorldSeed(pub u64);
+
+#[derive(Component)]
+pub struct Ground;
+
+impl WorldSeed {
+    /// Reads the seed from `--seed <u64>`, a random one is picked otherwise.
+    pub fn from_args() -> Result<Self, ArgError> {
+        let seed = parse_arg("--seed")?.unwrap_or_else(rand::random);
+        info!("world seed = {seed}");
+        Ok(WorldSeed(seed))
+    }
+
+    pub fn noise_offset(&self) -> Vec2 {
+        let mut rng = StdRng::seed_from_u64(self.0);
+        Vec2::new(
+            rng.gen_range(-1000.0..1000.0),
+            rng.gen_range(-1000.0..1000.0),
+        )
+    }
+
+    // every chunk gets its own generator so the order chunks are built in doesn't matter
+    pub fn chunk_rng(&self, chunk: IVec2) -> StdRng {
+        let chunk = (chunk.x as u32 as u64) << 32 | chunk.y as u32 as u64;
+        StdRng::seed_from_u64(self.0 ^ chunk.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+    }
+
+    // randomness during the match, the same tick of the same seed always rolls the same numbers
+    pub fn tick_rng(&self, tick: u64) -> StdRng {
+        StdRng::seed_from_u64(!self.0 ^ tick.wrapping_mul(0xC2B2_AE3D_27D4_EB4F))
+    }
+
+    // a generator per unit, so the rolls don't depend on the order units are visited in
+    pub fn unit_rng(&self, tick: u64, id: SimId) -> StdRng {
+        let id = (id.0 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        StdRng::seed_from_u64(!self.0 ^ tick.wrapping_mul(0xC2B2_AE3D_27D4_EB4F) ^ id)
+    }
+}
+
+impl WorldSize {
+    /// `--sandbox` streams an infinite world, otherwise the size in tiles is read from
+    /// `--world-size <u32>` and the default size is used if it is missing.
+    pub fn from_args() -> Result<Self, ArgError> {
+        if has_arg("--sandbox") {
+            return Ok(WorldSize::Infinite);
+        }
+        Ok(
+            parse_arg("--world-size")?.map_or_else(WorldSize::default, |size| {
+                WorldSize::Fixed(UVec2::splat(size))
+            }),
+        )
+    }
+}
+
+impl SpriteSheets {
+    pub fn load(world: &mut World) -> Self {
+        SpriteSheets {
+            swordsman_red: load_definition_atlas(world, "definitions/soldier.unit.ron"),
+            farmer_red: load_definition_atlas(world, "definitions/lumberjack.unit.ron"),
+            box_selector: {
+                let texture_atlas = TextureAtlas::from_grid(
+                    load_image(world, "box_selector.png"),
+                    Vec2::new(16.0, 16.0),
+                    2,
+                    1,
+                    None,
+                    None,
+                );
+                add_texture_atlas(world, texture_atlas)
+            },
+            highlighted_boxes: {
+                let texture_atlas = TextureAtlas::from_grid(
+                    load_image(world, "highlighted_boxes.png"),
+                    Vec2::new(16.0, 16.0),
+                    5,
+                    1,
+                    None,
+                    None,
+                );
+                add_texture_atlas(world, texture_atlas)
+            },
+            trees: {
+                let texture_atlas = TextureAtlas::from_grid(
+                    load_image(world, "trees.png"),
+                    Vec2::new(16.0, 16.0),
+                    4,
+                    1,
+                    None,
+                    None,
+                );
+                add_texture_atlas(world, texture_atlas)
+            },
+            grass_deco: {
+                let texture_atlas = TextureAtlas::from_grid(
+                    load_image(world, "ground/grass_deco.png"),
+                    Vec2::new(16.0, 16.0),
+                    2,
+                    2,
+                    None,
+                    None,
+                );
+                add_texture_atlas(world, texture_atlas)
+            },
+            barracks_red: load_definition_atlas(world, "definitions/barrack.building.ron"),
+            armory: load_definition_atlas(world, "definitions/armory.building.ron"),
+            shore: {
+                let texture_atlas = TextureAtlas::from_grid(
+                    load_image(world, "ground/shore.png"),
+                    Vec2::new(16.0, 16.0),
+                    5,
+                    1,
+                    None,
+                    None,
+                );
+                add_texture_atlas(world, texture_atlas)
+            },
+            textured_grass: {
+                let texture_atlas = TextureAtlas::from_grid(
+                    load_image(world, "ground/textured_grass.png"),
+                    Vec2::new(16.0, 16.0),
+                    3,
+                    2,
+                    None,
+                    None,
+                );
+                add_texture_atlas(world, texture_atlas)
+            },
+            dead_grass: {
+                let texture_atlas = TextureAtlas::from_grid(
+                    load_image(world, "ground/dead_grass.png"),
+                    Vec2::new(16.0, 16.0),
+                    3,
+                    2,
+                    None,
+                    None,
+                );
+                add_texture_atlas(world, texture_atlas)
+            },
+            winter: {
+                let texture_atlas = TextureAtlas::from_grid(
+                    load_image(world, "ground/winter.png"),
+                    Vec2::new(16.0, 16.0),
+                    8,
+                    1,
+                    None,
+                    None,
+                );
+                add_texture_atlas(world, texture_atlas)
+            },
+            cliff: {
+                let texture_atlas = TextureAtlas::from_grid(
+                    load_image(world, "ground/cliff.png"),
+                    Vec2::new(16.0, 16.0),
+                    7,
+                    9,
+                    None,
+                    None,
+                );
+                add_texture_atlas(world, texture_atlas)
+            },
+            cliff_water: {
+                let texture_atlas = TextureAtlas::from_grid(
+                    load_image(world, "ground/cliff_water.png"),
+                    Vec2::new(16.0, 16.0),
+                    5,
+                    6,
+                    None,
+                    None,
+                );
+                add_texture_atlas(world, texture_atlas)
+            },
+        }
+    }
+}
+
+impl SpriteSheets {
+    pub fn ground(&self, sheet: GroundSheet) -> Handle<TextureAtlas> {
+        match sheet {
+            GroundSheet::Shore => self.shore.clone(),
+            GroundSheet::TexturedGrass => self.textured_grass.clone(),
+            GroundSheet::DeadGrass => self.dead_grass.clone(),
+            GroundSheet::Winter => self.winter.clone(),
+            GroundSheet::Cliff => self.cliff.clone(),
+            GroundSheet::CliffWater => self.cliff_water.clone(),
+            GroundSheet::GrassDeco => self.grass_deco.clone(),
+        }
+    }
+}
+
+// spawning, what a match starts with comes from its `Scenario`
+
+pub fn spawn_barrack(
+    commands: &mut Commands,
+    sprite_sheets: &SpriteSheets,
+    pos: Vec2,
+    team: Team,
+) -> Entity {
+    commands
+        .spawn(SpriteSheetBundle {
+            texture_atlas: sprite_sheets.barracks_red.clone(),
+            transform: Transform::from_translation(pos.extend(0.0)),
+            ..default()
+        })
+        .insert(YSort)
+        .insert(Cull2D)
+        .insert(Barrack)
+        .insert(BuildingKind::Barrack)
+        .insert(team)
+        .id()
+}
+
+pub fn spawn_building(
+    commands: &mut Commands,
+    sprite_sheets: &SpriteSheets,
+    definitions: &Definitions,
+    kind: BuildingKind,
+    pos: Vec2,
+    team: Team,
+) -> Entity {
+    match kind {
+        BuildingKind::Barrack => spawn_barrack(commands, sprite_sheets, pos, team),
+        BuildingKind::Armory => spawn_armory(commands, sprite_sheets, definitions, pos, team),
+    }
+}
+
+pub fn spawn_armory(
+    commands: &mut Commands,
+    sprite_sheets: &SpriteSheets,
+    definitions: &Definitions,
+    pos: Vec2,
+    team: Team,
+) -> Entity {
+    commands
+        .spawn(SpriteSheetBundle {
+            texture_atlas: sprite_sheets.armory.clone(),
+            sprite: TextureAtlasSprite::new(
+                definitions.building(BuildingKind::Armory).sprite_index,
+            ),
+            transform: Transform::from_translation(pos.extend(0.0)),
+            ..default()
+        })
+        .insert(Name::new("Armory"))
+        .insert(YSort)
+        .insert(Cull2D)
+        .insert(Armory::default())
+        .insert(BuildingKind::Armory)
+        .insert(team)
+        .id()
+}
+
+fn tree_spawning(
+    mut events: EventReader<TreeSpawnEvent>,
+    mut commands: Commands,
+    sprite_sheets: Res<SpriteSheets>,
+) {
+    for TreeSpawnEvent {
+        pos,
+        index,
+        sapling,
+    } in events.iter()
+    {
+        let entity = spawn_tree(&mut commands, &sprite_sheets, *pos, *index);
+        if *sapling {
+            commands.entity(entity).insert(Sapling::default());
+        }
+    }
+}
+
+pub fn spawn_tree(
+    commands: &mut Commands,
+    sprite_sheets: &SpriteSheets,
+    pos: Vec2,
+    index: usize,
+) -> Entity {
+    commands
+        .spawn(SpriteSheetBundle {
+            texture_atlas: sprite_sheets.trees.clone(),
+            transform: Transform::from_translation(pos.extend(1.0)),
+            sprite: TextureAtlasSprite { index, ..default() },
+            ..default()
+        })
+        .insert(Name::new("Tree"))
+        .insert(YSort)
+        .insert(Cull2D)
+        .insert(Tree { resource: 100 })
+        .id()
+}
+
+fn deposit_wood_stat(mut deposit_wood: EventReader<DepositWoodEvent>, mut stats: ResMut<Stats>) {
+    for event in deposit_wood.iter() {
+        stats.team_mut(event.1).wood += event.0
+    }
+}
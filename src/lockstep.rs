@@ -0,0 +1,534 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::ground::WorldSize;
+use crate::scenario::Scenario;
+use crate::simulation::{
+    assign_sim_ids, local_inputs, state_hash, InputSource, LocalInputs, LocalTeam, PlayerInput,
+    SimIds, SimulationSet, SimulationTick, TickInputs, TickReady,
+};
+use crate::unit::Team;
+use crate::WorldSeed;
+
+/// Deterministic lockstep: every peer runs the whole simulation and only the inputs are sent.
+/// The inputs of a tick are applied once all peers have sent theirs, so peers wait for the
+/// slowest one. Active while a `LockstepSession` exists.
+pub struct LockstepPlugin;
+
+impl Plugin for LockstepPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(
+            lockstep_receive
+                .run_if(resource_exists::<LockstepSession>())
+                .before(SimulationSet::Receive)
+                .in_schedule(CoreSchedule::FixedUpdate),
+        )
+        .add_systems(
+            (lockstep_checksum, lockstep_send)
+                .chain()
+                .distributive_run_if(resource_exists::<LockstepSession>())
+                .after(assign_sim_ids)
+                .before(local_inputs)
+                .in_set(SimulationSet::Receive)
+                .in_schedule(CoreSchedule::FixedUpdate),
+        );
+    }
+}
+
+// inputs of tick `t` are applied at tick `t + INPUT_DELAY`, this hides the round trip to the
+// other peers as long as it is shorter
+pub const INPUT_DELAY: u64 = 3;
+// ticks between two state checksums
+const CHECKSUM_INTERVAL: u64 = 20;
+// how long a checksum is kept for a peer that is behind
+const CHECKSUM_HISTORY: u64 = 10 * CHECKSUM_INTERVAL;
+// large enough for a batch window of a busy match
+const MAX_DATAGRAM: usize = 65_507;
+const LOBBY_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Serialize, Deserialize)]
+enum Message {
+    // sent by a joining peer until the host starts the match
+    Join,
+    Welcome {
+        slot: u8,
+        players: u8,
+        seed: WorldSeed,
+        size: WorldSize,
+        scenario: Scenario,
+    },
+    // the addresses of all peers by slot, the host's own entry is the address it bound to
+    Start {
+        peers: Vec<SocketAddr>,
+    },
+    Inputs {
+        slot: u8,
+        // the first tick the sender is still missing from the receiver
+        ack: u64,
+        batches: Vec<Batch>,
+    },
+}
+
+/// The inputs of one peer for one tick.
+#[derive(Clone, Serialize, Deserialize)]
+struct Batch {
+    tick: u64,
+    inputs: Vec<PlayerInput>,
+    // `state_hash` at the start of an earlier tick, to detect desyncs
+    checksum: Option<(u64, u64)>,
+}
+
+enum LobbyState {
+    Hosting {
+        players: u8,
+        // by slot, the host is slot 0 and has no entry
+        joined: Vec<SocketAddr>,
+    },
+    Joining {
+        host: SocketAddr,
+    },
+    Joined {
+        host: SocketAddr,
+        slot: u8,
+        players: u8,
+    },
+}
+
+/// The handshake before a match: the host hands out player slots, the world seed and size and
+/// the scenario, and starts the match once every slot is taken. Poll it until it returns the session.
+pub struct Lobby {
+    socket: UdpSocket,
+    seed: WorldSeed,
+    size: WorldSize,
+    scenario: Scenario,
+    state: LobbyState,
+}
+
+impl Lobby {
+    pub fn host(
+        addr: impl ToSocketAddrs,
+        players: u8,
+        seed: WorldSeed,
+        size: WorldSize,
+        scenario: Scenario,
+    ) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        info!(
+            "hosting a match for {players} players on {}",
+            socket.local_addr()?
+        );
+        Ok(Lobby {
+            socket,
+            seed,
+            size,
+            scenario,
+            state: LobbyState::Hosting {
+                players,
+                joined: vec![],
+            },
+        })
+    }
+
+    pub fn join(host: impl ToSocketAddrs) -> io::Result<Self> {
+        let host = host
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no host address"))?;
+        let bind: SocketAddr = if host.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(bind)?;
+        socket.set_nonblocking(true)?;
+        Ok(Lobby {
+            socket,
+            seed: WorldSeed::default(),
+            size: WorldSize::default(),
+            scenario: Scenario::default(),
+            state: LobbyState::Joining { host },
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Handles the messages that arrived, `Some` once the match starts.
+    pub fn poll(&mut self) -> io::Result<Option<LockstepSession>> {
+        let mut buffer = vec![0; MAX_DATAGRAM];
+        while let Some((message, from)) = receive(&self.socket, &mut buffer)? {
+            match (&mut self.state, message) {
+                (LobbyState::Hosting { players, joined }, Message::Join) => {
+                    let slot = match joined.iter().position(|peer| *peer == from) {
+                        Some(index) => index + 1,
+                        None if joined.len() + 1 < *players as usize => {
+                            joined.push(from);
+                            info!("{from} joined as player {}", joined.len());
+                            joined.len()
+                        }
+                        None => {
+                            warn!("{from} tried to join a full match");
+                            continue;
+                        }
+                    };
+                    let welcome = Message::Welcome {
+                        slot: slot as u8,
+                        players: *players,
+                        seed: self.seed,
+                        size: self.size,
+                        scenario: self.scenario.clone(),
+                    };
+                    send(&self.socket, &welcome, from)?;
+                }
+                (
+                    LobbyState::Joining { host },
+                    Message::Welcome {
+                        slot,
+                        players,
+                        seed,
+                        size,
+                        scenario,
+                    },
+                ) if from == *host => {
+                    info!(
+                        "joined as player {slot} of {players}, world seed = {}",
+                        *seed
+                    );
+                    self.seed = seed;
+                    self.size = size;
+                    self.scenario = scenario;
+                    self.state = LobbyState::Joined {
+                        host: *host,
+                        slot,
+                        players,
+                    };
+                }
+                (
+                    LobbyState::Joined {
+                        host,
+                        slot,
+                        players,
+                    },
+                    Message::Start { mut peers },
+                ) if from == *host && peers.len() == *players as usize => {
+                    // the host only knows the address it bound to
+                    peers[0] = *host;
+                    let slot = *slot;
+                    return Ok(Some(self.session(slot, peers)));
+                }
+                _ => {}
+            }
+        }
+
+        match &self.state {
+            LobbyState::Hosting { players, joined } if joined.len() + 1 == *players as usize => {
+                let mut peers = vec![self.socket.local_addr()?];
+                peers.extend(joined.iter().copied());
+                let start = Message::Start {
+                    peers: peers.clone(),
+                };
+                for peer in joined.iter() {
+                    send(&self.socket, &start, *peer)?;
+                }
+                return Ok(Some(self.session(0, peers)));
+            }
+            // repeated until the host answers, datagrams can get lost
+            LobbyState::Joining { host } | LobbyState::Joined { host, .. } => {
+                send(&self.socket, &Message::Join, *host)?;
+            }
+            LobbyState::Hosting { .. } => {}
+        }
+        Ok(None)
+    }
+
+    /// Blocks until the match starts.
+    pub fn wait(mut self) -> io::Result<LockstepSession> {
+        loop {
+            if let Some(session) = self.poll()? {
+                return Ok(session);
+            }
+            thread::sleep(LOBBY_POLL_INTERVAL);
+        }
+    }
+
+    fn session(&self, slot: u8, peers: Vec<SocketAddr>) -> LockstepSession {
+        LockstepSession {
+            socket: self.socket.try_clone().expect("a clonable socket"),
+            slot,
+            seed: self.seed,
+            size: self.size,
+            scenario: self.scenario.clone(),
+            acks: vec![0; peers.len()],
+            received: vec![BTreeMap::new(); peers.len()],
+            received_until: vec![INPUT_DELAY; peers.len()],
+            peers,
+            checksums: BTreeMap::new(),
+            peer_checksums: vec![],
+            desync: None,
+        }
+    }
+}
+
+/// A running lockstep match. Insert it together with its seed, size, scenario and
+/// `InputSource::Lockstep`.
+#[derive(Resource)]
+pub struct LockstepSession {
+    socket: UdpSocket,
+    slot: u8,
+    seed: WorldSeed,
+    size: WorldSize,
+    scenario: Scenario,
+    // by slot, the own entry is unused
+    peers: Vec<SocketAddr>,
+    // by slot, the first tick the peer is still missing from us
+    acks: Vec<u64>,
+    // by slot, batches that are not applied yet, the own batches are kept until every peer
+    // has them
+    received: Vec<BTreeMap<u64, Batch>>,
+    // by slot, every batch before this tick has arrived
+    received_until: Vec<u64>,
+    checksums: BTreeMap<u64, u64>,
+    // (slot, tick, hash) of peers whose own checksum isn't known yet
+    peer_checksums: Vec<(u8, u64, u64)>,
+    desync: Option<u64>,
+}
+
+impl LockstepSession {
+    pub fn slot(&self) -> u8 {
+        self.slot
+    }
+
+    pub fn players(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn seed(&self) -> WorldSeed {
+        self.seed
+    }
+
+    pub fn size(&self) -> WorldSize {
+        self.size
+    }
+
+    pub fn scenario(&self) -> &Scenario {
+        &self.scenario
+    }
+
+    /// The first tick whose checksum differed between two peers.
+    pub fn desync(&self) -> Option<u64> {
+        self.desync
+    }
+
+    /// Adds the resources of the session to `app`, the match starts with the next tick.
+    pub fn insert(self, app: &mut App) {
+        app.insert_resource(self.seed)
+            .insert_resource(self.size)
+            .insert_resource(self.scenario.clone())
+            .insert_resource(InputSource::Lockstep)
+            .insert_resource(LocalTeam(Team::of_slot(self.slot)))
+            .insert_resource(self);
+    }
+
+    fn remote_slots(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.peers.len()).filter(move |slot| *slot != self.slot as usize)
+    }
+
+    fn ready(&self, tick: u64) -> bool {
+        self.remote_slots()
+            .all(|slot| self.received_until[slot] > tick)
+    }
+
+    fn handle(&mut self, message: Message, from: SocketAddr) {
+        let (slot, ack, batches) = match message {
+            Message::Inputs { slot, ack, batches } => (slot, ack, batches),
+            // the start of the match got lost on the way to a peer
+            Message::Join if self.slot == 0 && self.peers.contains(&from) => {
+                let start = Message::Start {
+                    peers: self.peers.clone(),
+                };
+                if let Err(e) = send(&self.socket, &start, from) {
+                    warn!("could not send the start to {from}: {e}");
+                }
+                return;
+            }
+            _ => return,
+        };
+        let slot = slot as usize;
+        if slot >= self.peers.len() || slot == self.slot as usize {
+            return;
+        }
+        self.acks[slot] = self.acks[slot].max(ack);
+        for batch in batches {
+            if batch.tick < self.received_until[slot] {
+                continue;
+            }
+            if let Some((tick, hash)) = batch.checksum {
+                self.peer_checksums.push((slot as u8, tick, hash));
+            }
+            self.received[slot].insert(batch.tick, batch);
+        }
+        while self.received[slot].contains_key(&self.received_until[slot]) {
+            self.received_until[slot] += 1;
+        }
+        self.compare_checksums();
+    }
+
+    fn compare_checksums(&mut self) {
+        let checksums = &self.checksums;
+        let mut desync = self.desync;
+        self.peer_checksums.retain(|(slot, tick, hash)| {
+            let Some(own) = checksums.get(tick) else {
+                return true;
+            };
+            if *own != *hash && desync.is_none() {
+                error!("desync with player {slot} at tick {tick}");
+                desync = Some(*tick);
+            }
+            false
+        });
+        self.desync = desync;
+    }
+
+    // every peer gets the own batches it hasn't acknowledged yet, until it does
+    fn send_batches(&self) {
+        let own = &self.received[self.slot as usize];
+        for slot in self.remote_slots() {
+            let message = Message::Inputs {
+                slot: self.slot,
+                ack: self.received_until[slot],
+                batches: own
+                    .range(self.acks[slot]..)
+                    .map(|(_, batch)| batch.clone())
+                    .collect(),
+            };
+            if let Err(e) = send(&self.socket, &message, self.peers[slot]) {
+                warn!("could not send inputs to player {slot}: {e}");
+            }
+        }
+    }
+}
+
+fn receive(socket: &UdpSocket, buffer: &mut [u8]) -> io::Result<Option<(Message, SocketAddr)>> {
+    loop {
+        let (length, from) = match socket.recv_from(buffer) {
+            Ok(received) => received,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+            // e.g. a peer that closed its socket, the datagram is lost either way
+            Err(e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+            Err(e) => return Err(e),
+        };
+        let message = std::str::from_utf8(&buffer[..length])
+            .ok()
+            .and_then(|source| ron::from_str(source).ok());
+        match message {
+            Some(message) => return Ok(Some((message, from))),
+            None => warn!("ignored a malformed datagram from {from}"),
+        }
+    }
+}
+
+fn send(socket: &UdpSocket, message: &Message, to: SocketAddr) -> io::Result<()> {
+    let source =
+        ron::to_string(message).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    socket.send_to(source.as_bytes(), to).map(|_| ())
+}
+
+// runs for every due tick, also the held ones, so lost datagrams are sent again
+fn lockstep_receive(
+    mut session: ResMut<LockstepSession>,
+    tick: Res<SimulationTick>,
+    mut ready: ResMut<TickReady>,
+    mut buffer: Local<Vec<u8>>,
+) {
+    buffer.resize(MAX_DATAGRAM, 0);
+    loop {
+        match receive(&session.socket, &mut buffer) {
+            Ok(Some((message, from))) => session.handle(message, from),
+            Ok(None) => break,
+            Err(e) => {
+                warn!("could not receive inputs: {e}");
+                break;
+            }
+        }
+    }
+    **ready = session.ready(**tick);
+    if !**ready {
+        session.send_batches();
+    }
+}
+
+fn lockstep_checksum(world: &mut World) {
+    let tick = **world.resource::<SimulationTick>();
+    if !tick.is_multiple_of(CHECKSUM_INTERVAL) {
+        return;
+    }
+    let hash = state_hash(world);
+    let mut session = world.resource_mut::<LockstepSession>();
+    session.checksums.insert(tick, hash);
+    session
+        .checksums
+        .retain(|checksum_tick, _| checksum_tick + CHECKSUM_HISTORY > tick);
+    session.compare_checksums();
+}
+
+// the local inputs become the own batch `INPUT_DELAY` ticks ahead, the batches of this tick
+// are applied in slot order
+fn lockstep_send(
+    mut session: ResMut<LockstepSession>,
+    tick: Res<SimulationTick>,
+    ids: SimIds,
+    mut local: ResMut<LocalInputs>,
+    mut inputs: ResMut<TickInputs>,
+) {
+    let tick = **tick;
+    let slot = session.slot as usize;
+    let checksum = session.checksums.get(&tick).map(|hash| (tick, *hash));
+    let batch = Batch {
+        tick: tick + INPUT_DELAY,
+        inputs: local
+            .drain(..)
+            .filter_map(|input| ids.export(&input))
+            .collect(),
+        checksum,
+    };
+    session.received[slot].insert(batch.tick, batch);
+    session.received_until[slot] = tick + INPUT_DELAY + 1;
+    session.send_batches();
+
+    for slot in 0..session.players() {
+        let Some(batch) = session.received[slot].get(&tick) else {
+            continue;
+        };
+        let team = Team::of_slot(slot as u8);
+        inputs.extend(
+            batch
+                .inputs
+                .iter()
+                .filter_map(|input| ids.import(input))
+                .map(|input| (team, input)),
+        );
+    }
+    // own batches stay until every peer acknowledged them
+    let own = session.slot as usize;
+    let oldest_ack = session
+        .remote_slots()
+        .map(|slot| session.acks[slot])
+        .min()
+        .unwrap_or(u64::MAX);
+    for slot in 0..session.players() {
+        let keep_from = if slot == own {
+            tick.min(oldest_ack)
+        } else {
+            tick + 1
+        };
+        session.received[slot].retain(|batch_tick, _| *batch_tick >= keep_from);
+    }
+}
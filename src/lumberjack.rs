@@ -1,28 +1,46 @@
-use crate::unit::{SelectedMark, SelectionBox, Unit};
+use crate::command::{Command, OrderQueue};
+use crate::definition::Definitions;
+use crate::forest::{stump_near, ClearStumpEvent, Sapling, Stump};
+use crate::health::spawn_health_bar;
+use crate::research::ResearchState;
+use crate::simulation::{tick_seconds, SimulationAppExt, SimulationSet};
+use crate::unit::{SelectionBox, Team, Unit, UnitKind};
 use crate::util::{find_nearest, nearest_entity};
-use crate::{Barrack, Cull2D, Cursor, DepositWoodEvent, SpriteSheets, Tree, TreeChopEvent, YSort};
+use crate::{
+    spawn_building, Barrack, BuildingKind, Cull2D, DepositWoodEvent, SpriteSheets, Stats, Tree,
+    TreeChopEvent, YSort,
+};
+use bevy::ecs::entity::{EntityMap, MapEntities, MapEntitiesError};
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
 pub struct LumberjackPlugin;
 
 impl Plugin for LumberjackPlugin {
     fn build(&self, app: &mut App) {
-        app.add_event::<SpawnLumberjackEvent>()
-            .add_system(lumberjack_spawning)
-            .add_system(lumberjack_animation)
-            .add_system(lumberjack_next_action)
-            .add_system(lumberjack_move_to_position_action);
+        app.add_simulation_event::<SpawnLumberjackEvent>()
+            .add_system(
+                lumberjack_spawning
+                    .in_set(SimulationSet::Input)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            )
+            .add_systems(
+                (lumberjack_orders, lumberjack_next_action)
+                    .chain()
+                    .in_set(SimulationSet::Actions)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            );
     }
 }
 
-#[derive(Component, Default, Reflect)]
+#[derive(Component, Default, Clone, Reflect, Serialize, Deserialize)]
 pub struct Lumberjack {
     action: Action,
     wood: u32,
     animation_timer: f32,
 }
 
-#[derive(Default, Reflect)]
+#[derive(Default, Clone, Reflect, Serialize, Deserialize)]
 pub enum Action {
     #[default]
     Idle,
@@ -33,40 +51,92 @@ pub enum Action {
         timeout: f32,
         target: Entity,
     },
+    ClearStump(Entity),
+    Dig {
+        timeout: f32,
+        target: Entity,
+    },
+    // walks to the site and puts the building up once there
+    Build {
+        kind: BuildingKind,
+        pos: Vec2,
+    },
+}
+
+// seconds it takes to dig out a stump
+const CLEAR_TIME: f32 = 3.0;
+
+impl Lumberjack {
+    pub fn carries_wood(&self) -> bool {
+        self.wood > 0
+    }
+}
+
+// targets that are not part of the map (e.g. an already felled tree) reset the action
+impl MapEntities for Lumberjack {
+    fn map_entities(&mut self, entity_map: &EntityMap) -> Result<(), MapEntitiesError> {
+        match &mut self.action {
+            Action::CollectResource(target)
+            | Action::DepositResource(target)
+            | Action::Chop { target, .. }
+            | Action::ClearStump(target)
+            | Action::Dig { target, .. } => match entity_map.get(*target) {
+                Ok(mapped) => *target = mapped,
+                Err(_) => self.action = Action::Idle,
+            },
+            Action::Idle | Action::MoveToPosition(_) | Action::Build { .. } => {}
+        }
+        Ok(())
+    }
 }
 
-pub struct SpawnLumberjackEvent(pub Vec2);
+pub struct SpawnLumberjackEvent(pub Vec2, pub Team);
 
 pub fn lumberjack_spawning(
     mut commands: Commands,
     mut events: EventReader<SpawnLumberjackEvent>,
     sprite_sheets: Res<SpriteSheets>,
+    definitions: Res<Definitions>,
 ) {
-    for event in events.iter() {
-        let pos = event.0;
-        commands
-            .spawn(SpriteSheetBundle {
-                texture_atlas: sprite_sheets.farmer_red.clone(),
-                transform: Transform::from_translation(pos.extend(1.0)),
-                ..default()
-            })
-            .insert(Name::new("Luberjack"))
-            .insert(YSort)
-            .insert(Cull2D)
-            .insert(Unit::default())
-            .insert(Lumberjack::default())
-            .with_children(|builder| {
-                builder
-                    .spawn(SpriteSheetBundle {
-                        texture_atlas: sprite_sheets.box_selector.clone(),
-                        visibility: Visibility::Hidden,
-                        ..default()
-                    })
-                    .insert(SelectionBox);
-            });
+    for SpawnLumberjackEvent(pos, team) in events.iter() {
+        spawn_lumberjack(&mut commands, &sprite_sheets, &definitions, *pos, *team);
     }
 }
 
+pub fn spawn_lumberjack(
+    commands: &mut Commands,
+    sprite_sheets: &SpriteSheets,
+    definitions: &Definitions,
+    pos: Vec2,
+    team: Team,
+) -> Entity {
+    commands
+        .spawn(SpriteSheetBundle {
+            texture_atlas: sprite_sheets.farmer_red.clone(),
+            transform: Transform::from_translation(pos.extend(1.0)),
+            ..default()
+        })
+        .insert(Name::new("Luberjack"))
+        .insert(YSort)
+        .insert(Cull2D)
+        .insert(Unit::new(definitions.unit(UnitKind::Lumberjack)))
+        .insert(UnitKind::Lumberjack)
+        .insert(team)
+        .insert(Lumberjack::default())
+        .insert(OrderQueue::default())
+        .with_children(|builder| {
+            builder
+                .spawn(SpriteSheetBundle {
+                    texture_atlas: sprite_sheets.box_selector.clone(),
+                    visibility: Visibility::Hidden,
+                    ..default()
+                })
+                .insert(SelectionBox);
+            spawn_health_bar(builder);
+        })
+        .id()
+}
+
 pub fn lumberjack_animation(mut query: Query<(&Unit, &Lumberjack, &mut TextureAtlasSprite)>) {
     for (unit, worker, mut sprite) in query.iter_mut() {
         let frame = (worker.animation_timer * 8.0).round() as usize;
@@ -83,6 +153,11 @@ pub fn lumberjack_animation(mut query: Query<(&Unit, &Lumberjack, &mut TextureAt
                 let animation_frame = (timeout.clamp(0.0, 1.0) * 3.0).floor() as usize;
                 40 + direction + animation_frame
             }
+            // swings the axe once a second
+            Action::Dig { timeout, target: _ } => {
+                let animation_frame = (timeout.fract() * 3.0).floor() as usize;
+                40 + direction + animation_frame
+            }
             _ if unit.vel.length() > 20.0 => {
                 direction + (frame % 4 + 1) + if worker.wood > 0 { 20 } else { 0 }
             }
@@ -91,31 +166,42 @@ pub fn lumberjack_animation(mut query: Query<(&Unit, &Lumberjack, &mut TextureAt
     }
 }
 
-const BARACK_SIZE: f32 = 20.0;
-
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub fn lumberjack_next_action(
-    mut query: Query<(&mut Lumberjack, &mut Unit, &Transform)>,
+    mut query: Query<(&mut Lumberjack, &mut Unit, &Transform, &Team)>,
     barrack_query: Query<(Entity, &Transform), (With<Barrack>, Without<Unit>)>,
-    tree_query: Query<(Entity, &Transform, &Tree), Without<Unit>>,
+    building_query: Query<&Transform, (With<BuildingKind>, Without<Unit>)>,
+    tree_query: Query<(Entity, &Transform, &Tree), (Without<Unit>, Without<Sapling>)>,
+    stump_query: Query<&Transform, With<Stump>>,
+    mut clear_stump_events: EventWriter<ClearStumpEvent>,
     mut tree_chop_event: EventWriter<TreeChopEvent>,
-    _entity_query: Query<Entity>,
     mut deposit_wood: EventWriter<DepositWoodEvent>,
-    time: Res<Time>,
+    mut stats: ResMut<Stats>,
+    mut commands: Commands,
+    sprite_sheets: Res<SpriteSheets>,
+    definitions: Res<Definitions>,
+    research: Res<ResearchState>,
 ) {
-    for (mut worker, mut unit, transform) in query.iter_mut() {
-        worker.animation_timer += time.delta_seconds();
+    // buildings put up this tick, the query doesn't see them yet
+    let mut built = vec![];
+    let definition = definitions.unit(UnitKind::Lumberjack);
+    let barrack_size = definitions.building(BuildingKind::Barrack).size;
+    for (mut worker, mut unit, transform, team) in query.iter_mut() {
+        let carry_capacity = definition.carry_capacity + research.carry_bonus(*team, &definitions);
+        let chop_speed = research.chop_speed(*team, &definitions) / definition.chop_time;
+        worker.animation_timer += tick_seconds();
         let pos = transform.translation.truncate();
         match worker.action {
             Action::Idle => {
                 unit.target_direction = Vec2::ZERO;
-                if worker.wood >= 5 {
-                    // can carry 5 wood
+                if worker.wood >= carry_capacity {
                     worker.action = find_nearest(barrack_query.iter(), pos)
                         .map(|f| f.0)
                         .map_or(Action::Idle, Action::DepositResource);
                 } else {
                     worker.action = tree_query
                         .iter()
+                        .filter(|(_, _, tree)| tree.resource > 0)
                         .fold(None, |acc, (a, b, _c)| {
                             nearest_entity(acc, pos, (a, b.translation.truncate()))
                         })
@@ -133,10 +219,10 @@ pub fn lumberjack_next_action(
             }
             Action::CollectResource(target) => {
                 match tree_query.get(target) {
-                    Ok((tree_entity, tree_transform, tree)) if tree.resource >= 0 => {
+                    Ok((tree_entity, tree_transform, tree)) if tree.resource > 0 => {
                         // move towards tree
                         let target_pos = tree_transform.translation.truncate();
-                        unit.target_direction = (target_pos - pos).normalize();
+                        unit.target_direction = (target_pos - pos).normalize_or_zero();
                         if Vec2::distance_squared(target_pos, pos) < 10.0 * 10.0 {
                             worker.action = Action::Chop {
                                 timeout: 1.0,
@@ -147,18 +233,76 @@ pub fn lumberjack_next_action(
                     _ => worker.action = Action::Idle,
                 }
             }
+            Action::ClearStump(target) => match stump_query.get(target) {
+                Ok(stump_transform) => {
+                    let target_pos = stump_transform.translation.truncate();
+                    unit.target_direction = (target_pos - pos).normalize_or_zero();
+                    if Vec2::distance_squared(target_pos, pos) < 10.0 * 10.0 {
+                        worker.action = Action::Dig {
+                            timeout: CLEAR_TIME,
+                            target,
+                        };
+                    }
+                }
+                // the stump rotted away or someone else cleared it
+                Err(_) => worker.action = Action::Idle,
+            },
+            Action::Dig { timeout, target } => {
+                unit.target_direction = Vec2::ZERO;
+                if timeout > 0.0 {
+                    worker.action = Action::Dig {
+                        timeout: timeout - tick_seconds(),
+                        target,
+                    };
+                } else {
+                    clear_stump_events.send(ClearStumpEvent(target));
+                    worker.action = Action::Idle;
+                    worker.animation_timer = 0.0;
+                }
+            }
+            Action::Build { kind, pos: site } => {
+                let delta = site - pos;
+                unit.target_direction = delta;
+                if delta.length_squared() < 25.0 * 25.0 {
+                    let building = definitions.building(kind);
+                    // several lumberjacks with the same order put up one building
+                    let taken = building_query
+                        .iter()
+                        .map(|transform| transform.translation.truncate())
+                        .chain(built.iter().copied())
+                        .any(|other| other.distance(site) < building.size);
+                    if stump_near(&stump_query, site, building.size) {
+                        info!("a stump blocks the {kind:?} at {site}");
+                    } else if !taken && !building.cost.affordable(&stats.team(*team)) {
+                        info!("a {kind:?} costs {}", building.cost);
+                    } else if !taken {
+                        building.cost.pay(stats.team_mut(*team));
+                        spawn_building(
+                            &mut commands,
+                            &sprite_sheets,
+                            &definitions,
+                            kind,
+                            site,
+                            *team,
+                        );
+                        built.push(site);
+                    }
+                    worker.action = Action::Idle;
+                    worker.animation_timer = 0.0;
+                }
+            }
             Action::DepositResource(target) => {
                 match barrack_query.get_component::<Transform>(target) {
                     Ok(barrack_transform) => {
                         // move towards barrack
                         let target_pos = barrack_transform.translation.truncate();
-                        unit.target_direction = (target_pos - pos).normalize();
-                        if Vec2::distance_squared(target_pos, pos) < BARACK_SIZE * BARACK_SIZE {
+                        unit.target_direction = (target_pos - pos).normalize_or_zero();
+                        if Vec2::distance_squared(target_pos, pos) < barrack_size * barrack_size {
                             // found target
+                            deposit_wood.send(DepositWoodEvent(worker.wood, *team));
                             worker.wood = 0;
                             worker.action = Action::Idle;
                             worker.animation_timer = 0.0;
-                            deposit_wood.send(DepositWoodEvent(1))
                         }
                     }
                     _ => worker.action = Action::Idle,
@@ -168,12 +312,12 @@ pub fn lumberjack_next_action(
                 unit.target_direction = Vec2::ZERO;
                 if timeout > 0.0 {
                     worker.action = Action::Chop {
-                        timeout: timeout - time.delta_seconds() * (8.0 / 3.0),
+                        timeout: timeout - tick_seconds() * chop_speed,
                         target,
                     };
                 } else {
                     if let Ok((tree_entity, _tree_transform, tree)) = tree_query.get(target) {
-                        if tree.resource >= 0 {
+                        if tree.resource > 0 {
                             tree_chop_event.send(TreeChopEvent(tree_entity));
                             worker.wood += 1;
                         }
@@ -186,14 +330,19 @@ pub fn lumberjack_next_action(
     }
 }
 
-pub fn lumberjack_move_to_position_action(
-    mut query: Query<(&Unit, &mut Lumberjack), With<SelectedMark>>,
-    input: Res<Input<MouseButton>>,
-    cursor: Res<Cursor>,
-) {
-    if input.just_pressed(MouseButton::Right) {
-        for (_, mut worker) in query.iter_mut() {
-            worker.action = Action::MoveToPosition(cursor.0);
+pub fn lumberjack_orders(mut query: Query<(&mut OrderQueue, &mut Lumberjack)>) {
+    for (mut orders, mut worker) in query.iter_mut() {
+        while let Some(command) = orders.next(matches!(worker.action, Action::Idle)) {
+            match command {
+                Command::Move(pos) => worker.action = Action::MoveToPosition(pos),
+                Command::Gather(tree) => worker.action = Action::CollectResource(tree),
+                Command::Deposit(barrack) => worker.action = Action::DepositResource(barrack),
+                Command::Clear(stump) => worker.action = Action::ClearStump(stump),
+                Command::Build(kind, pos) => worker.action = Action::Build { kind, pos },
+                Command::Stop => worker.action = Action::Idle,
+                // lumberjacks don't fight
+                _ => {}
+            }
         }
     }
 }
@@ -0,0 +1,181 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::definition::Definitions;
+use crate::health::{apply_damage, DamageEvent, UnitDeathEvent};
+use crate::simulation::{tick_seconds, SimulationSet};
+use crate::soldier::{soldier_orders, Soldier};
+use crate::unit::{Team, Unit};
+use crate::{Barrack, UnitQuadTree};
+
+pub struct MoralePlugin;
+
+impl Plugin for MoralePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(
+            morale_shock
+                .after(apply_damage)
+                .in_set(SimulationSet::Effects)
+                .in_schedule(CoreSchedule::FixedUpdate),
+        )
+        .add_system(
+            morale_update
+                .before(soldier_orders)
+                .in_set(SimulationSet::Actions)
+                .in_schedule(CoreSchedule::FixedUpdate),
+        );
+    }
+}
+
+pub const MAX_MORALE: f32 = 100.0;
+// soldiers at or below this break and rout
+pub const BREAK_MORALE: f32 = 20.0;
+// what a soldier has left once it rallied
+const RALLY_MORALE: f32 = 50.0;
+// seconds a soldier routs before it rallies
+pub const RALLY_TIME: f32 = 10.0;
+// soldiers this close to each other count as allies or enemies
+const MORALE_RADIUS: f32 = 80.0;
+// morale per second
+const RECOVERY: f32 = 1.0;
+const ALLY_SUPPORT: f32 = 0.5;
+const ENEMY_PRESSURE: f32 = 1.0;
+// morale lost per hp lost, armor already absorbed its share of the damage
+const DAMAGE_SHOCK: f32 = 1.0;
+// strikes from behind the way a soldier faces
+const FLANK_SHOCK: f32 = 2.0;
+// lost by every ally close to a soldier that died
+const CASUALTY_SHOCK: f32 = 15.0;
+
+/// How willing a soldier is to fight, it breaks and routs to the nearest friendly barrack when
+/// this runs out.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct Morale {
+    pub value: f32,
+    // seconds until a routing soldier rallies
+    pub rout: Option<f32>,
+}
+
+impl Default for Morale {
+    fn default() -> Self {
+        Morale {
+            value: MAX_MORALE,
+            rout: None,
+        }
+    }
+}
+
+impl Morale {
+    pub fn is_broken(&self) -> bool {
+        self.rout.is_some()
+    }
+
+    fn lose(&mut self, amount: f32) {
+        self.value = (self.value - amount).max(0.0);
+    }
+}
+
+// damage, strikes in the back and dead allies shake soldiers
+pub(crate) fn morale_shock(
+    mut damage_events: EventReader<DamageEvent>,
+    mut death_events: EventReader<UnitDeathEvent>,
+    mut morale_query: Query<(&mut Morale, &Soldier, &Unit, &Transform, &Team)>,
+    source_query: Query<(&Transform, &Team), With<Unit>>,
+    unit_quad_tree: Res<UnitQuadTree>,
+    definitions: Res<Definitions>,
+) {
+    for event in damage_events.iter() {
+        let Ok((mut morale, soldier, unit, transform, _)) = morale_query.get_mut(event.target)
+        else {
+            continue;
+        };
+        let protection = soldier.loadout(&definitions).protection;
+        let mut shock = event.amount * (1.0 - protection) * DAMAGE_SHOCK;
+        let source = event
+            .source
+            .and_then(|source| source_query.get(source).ok());
+        if let Some((source_transform, _)) = source {
+            let towards = (source_transform.translation - transform.translation).truncate();
+            if unit.last_direction.dot(towards) < 0.0 {
+                shock *= FLANK_SHOCK;
+            }
+        }
+        morale.lose(shock);
+    }
+    for UnitDeathEvent { unit, .. } in death_events.iter() {
+        // the dead unit is despawned at the end of the tick, so it is still there
+        let Ok((transform, team)) = source_query.get(*unit) else {
+            continue;
+        };
+        let pos = transform.translation.truncate();
+        for ally in unit_quad_tree.nearby(pos, MORALE_RADIUS) {
+            if ally == *unit {
+                continue;
+            }
+            let Ok((mut morale, _, _, ally_transform, ally_team)) = morale_query.get_mut(ally)
+            else {
+                continue;
+            };
+            let distance = ally_transform.translation.truncate().distance(pos);
+            if ally_team == team && distance <= MORALE_RADIUS {
+                morale.lose(CASUALTY_SHOCK);
+            }
+        }
+    }
+}
+
+// allies close by steady a soldier and enemies wear it down, broken soldiers rout and rally
+// after `RALLY_TIME`
+pub(crate) fn morale_update(
+    mut query: Query<(Entity, &mut Morale, &mut Soldier, &Transform, &Team)>,
+    others_query: Query<(&Transform, &Team), With<Soldier>>,
+    barrack_query: Query<(Entity, &Transform, &Team), With<Barrack>>,
+    unit_quad_tree: Res<UnitQuadTree>,
+) {
+    for (entity, mut morale, mut soldier, transform, team) in query.iter_mut() {
+        let pos = transform.translation.truncate();
+        let (allies, enemies) = unit_quad_tree
+            .nearby(pos, MORALE_RADIUS)
+            .filter(|other| *other != entity)
+            .filter_map(|other| others_query.get(other).ok())
+            .filter(|(other_transform, _)| {
+                other_transform.translation.truncate().distance(pos) <= MORALE_RADIUS
+            })
+            .fold((0, 0), |(allies, enemies), (_, other_team)| {
+                if other_team == team {
+                    (allies + 1, enemies)
+                } else {
+                    (allies, enemies + 1)
+                }
+            });
+        let drift = RECOVERY + ALLY_SUPPORT * allies as f32 - ENEMY_PRESSURE * enemies as f32;
+        morale.value = (morale.value + drift * tick_seconds()).clamp(0.0, MAX_MORALE);
+
+        match morale.rout {
+            Some(remaining) if remaining > tick_seconds() => {
+                morale.rout = Some(remaining - tick_seconds());
+            }
+            Some(_) => {
+                morale.rout = None;
+                morale.value = morale.value.max(RALLY_MORALE);
+                soldier.stop();
+            }
+            None if morale.value <= BREAK_MORALE => {
+                let barrack = barrack_query
+                    .iter()
+                    .filter(|(_, _, barrack_team)| *barrack_team == team)
+                    .map(|(entity, barrack_transform, _)| {
+                        (
+                            entity,
+                            barrack_transform.translation.truncate().distance(pos),
+                        )
+                    })
+                    .min_by(|a, b| a.1.total_cmp(&b.1))
+                    .map(|(entity, _)| entity);
+                morale.rout = Some(RALLY_TIME);
+                soldier.rout(barrack);
+            }
+            None => {}
+        }
+    }
+}
@@ -0,0 +1,692 @@
+use bevy::input::mouse::MouseMotion;
+use bevy::prelude::*;
+use bevy::render::camera::RenderTarget;
+use bevy::transform::TransformSystem;
+use bevy::utils::HashMap;
+use bevy::window::WindowMode;
+use bevy::window::WindowRef;
+use bevy_tweening::*;
+
+use crate::armory_panel::ArmoryPanelPlugin;
+use crate::command::{
+    command_input, cursor_icon, order_hotkeys, panel_orders, waypoint_markers, PanelOrderEvent,
+    PendingOrder,
+};
+use crate::command_panel::CommandPanelPlugin;
+use crate::control_group::ControlGroupPlugin;
+use crate::definition::DefinitionPlugin;
+use crate::forest::{fallen_tree_cleanup, tree_death, tree_fall, tree_frame, tree_shake};
+use crate::fps_plugin::FpsPlugin;
+use crate::ground::ground_meshes;
+use crate::health::{apply_damage, corpse_cleanup, health_bar, spawn_corpse, DamageEvent};
+use crate::input_action::{InputAction, InputActionPlugin};
+use crate::lockstep::LockstepSession;
+use crate::lumberjack::lumberjack_animation;
+use crate::presentation::Selection::Dragging;
+use crate::replay::{
+    record_selection, replay_controls, replay_selection, replay_text, spawn_replay_text,
+    ReplayPlayer, ReplayRecorder, ReplayVision,
+};
+use crate::research_panel::ResearchPanelPlugin;
+use crate::save::quick_save_input;
+use crate::scenario_panel::ScenarioPanelPlugin;
+use crate::simulation::{InputSource, LocalInputs, LocalTeam, PlayerInput, SimulationSet};
+use crate::soldier::soldier_animation;
+use crate::unit::{selection_added, selection_removed, unit_select, SelectedMark, Team, Unit};
+use crate::util::{ease_in_out_cubic, random_vec2};
+use crate::veterancy::rank_insignia;
+use crate::{Cull2D, SpriteSheets, Stats, YSort};
+
+/// Draws the simulation and turns player input into orders. Needs `DefaultPlugins` and
+/// goes together with `SimulationPlugin`.
+pub struct PresentationPlugin;
+
+impl Plugin for PresentationPlugin {
+    fn build(&self, app: &mut App) {
+        // the unit and building atlases need the loader of their definition files
+        app.add_plugin(DefinitionPlugin);
+        let sprite_sheets = SpriteSheets::load(&mut app.world);
+        app.add_plugin(TweeningPlugin)
+            .add_plugin(FpsPlugin)
+            .add_plugin(InputActionPlugin)
+            // .add_plugin(WorldInspectorPlugin::new())
+            .add_plugin(ControlGroupPlugin)
+            .add_plugin(CommandPanelPlugin)
+            .add_plugin(ResearchPanelPlugin)
+            .add_plugin(ArmoryPanelPlugin)
+            .add_plugin(ScenarioPanelPlugin)
+            .insert_resource(sprite_sheets)
+            .init_resource::<Cursor>()
+            .init_resource::<PendingOrder>()
+            .init_resource::<ReplayVision>()
+            .add_event::<ApplySelectionEvent>()
+            .add_event::<PanelOrderEvent>()
+            .add_startup_system(setup)
+            .add_startup_system(setup_ui)
+            .add_startup_system(spawn_camera)
+            .add_startup_system(spawn_selection)
+            .add_system(
+                component_animator_system::<TextureAtlasSprite>
+                    .in_set(AnimationSystem::AnimationUpdate),
+            )
+            .add_system(cursor_world_position)
+            .add_system(keyboard_input)
+            .add_system(move_camera)
+            .add_system(selection_change)
+            .add_system(selection_visual)
+            .add_system(unit_select)
+            .add_system(selection_added)
+            .add_system(selection_removed.after(selection_change))
+            .add_system(order_hotkeys)
+            .add_system(panel_orders.after(order_hotkeys))
+            .add_system(command_input.after(panel_orders))
+            .add_system(waypoint_markers)
+            .add_system(cursor_icon)
+            .add_system(quick_save_input)
+            .add_system(button_style)
+            .add_system(lumberjack_spawn_button)
+            .add_system(spawn_menu_tween)
+            .add_system(stat_text)
+            .add_system(game_speed)
+            .add_system(speed_text)
+            .add_system(lumberjack_animation)
+            .add_system(soldier_animation)
+            .add_system(rank_insignia)
+            // these react to a tick, e.g. the dead unit is despawned at the end of the tick it
+            // died in
+            .add_systems(
+                (
+                    tree_shake.after(tree_death),
+                    tree_fall.after(tree_death),
+                    spawn_corpse.after(apply_damage),
+                )
+                    .in_set(SimulationSet::Effects)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            )
+            .add_system(
+                record_previous_translation
+                    .in_set(SimulationSet::Receive)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            )
+            .add_system(
+                interpolate_units
+                    .in_base_set(CoreSet::PostUpdate)
+                    .after(TransformSystem::TransformPropagate),
+            )
+            .add_system(fallen_tree_cleanup)
+            .add_system(tree_frame)
+            .add_system(corpse_cleanup)
+            .add_system(health_bar)
+            .add_system(ground_meshes)
+            .add_system(ysort)
+            .add_system(camera_view_check)
+            .add_system(fullscreen_toggle)
+            .add_system(record_selection.run_if(resource_exists::<ReplayRecorder>()))
+            .add_startup_system(spawn_replay_text.run_if(resource_exists::<ReplayPlayer>()))
+            .add_systems(
+                (replay_controls, replay_selection, replay_text)
+                    .distributive_run_if(resource_exists::<ReplayPlayer>()),
+            );
+    }
+}
+
+// ui components
+#[derive(Default, Resource, Deref)]
+pub struct Cursor(pub Vec2);
+
+#[derive(Component)]
+enum Selection {
+    None,
+    Dragging(Vec2, Vec2),
+}
+#[derive(Component)]
+struct SpawnMenu;
+
+#[derive(Component)]
+struct StatsText;
+
+#[derive(Component)]
+struct SpeedText;
+
+// the speeds the player can pick, the replay viewer can go faster
+const GAME_SPEEDS: [f32; 4] = [0.5, 1.0, 2.0, 4.0];
+
+#[derive(Component)]
+struct SpawnButton;
+
+// where a unit was before the last tick, it is drawn between there and its current position.
+// Sparse like `SelectedMark`, headless peers don't have it.
+#[derive(Component)]
+#[component(storage = "SparseSet")]
+struct PreviousTranslation(Vec3);
+
+pub struct ApplySelectionEvent {
+    pub start: Vec2,
+    pub end: Vec2,
+    pub mode: SelectionMode,
+}
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum SelectionMode {
+    /// select what is inside the box and deselect everything else
+    Replace,
+    /// add what is inside the box to the current selection
+    Add,
+    /// select every unit on screen of the same kind as the clicked one
+    SameKind,
+}
+
+fn setup(
+    _commands: Commands,
+    _asset_server: Res<AssetServer>,
+    _texture_atlases: ResMut<Assets<TextureAtlas>>,
+) {
+    // todo move into setup functions
+}
+
+fn setup_ui(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Px(100.0)),
+                position_type: PositionType::Absolute,
+                ..default()
+            },
+            ..default()
+        })
+        .insert(SpawnMenu)
+        .with_children(|parent| {
+            parent
+                .spawn(ButtonBundle {
+                    style: Style {
+                        margin: UiRect::all(Val::Auto),
+                        padding: UiRect::all(Val::Px(16.0)),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    background_color: NORMAL_BUTTON.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent.spawn(TextBundle {
+                        text: Text::from_section(
+                            "Spawn Worker",
+                            TextStyle {
+                                font: asset_server.load("fonts/roboto_regular.ttf"),
+                                font_size: 32.0,
+                                color: Color::WHITE,
+                            },
+                        ),
+                        ..default()
+                    });
+                })
+                .insert(SpawnButton);
+        });
+
+    commands
+        .spawn(NodeBundle {
+            background_color: Color::WHITE.into(),
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect::default(),
+                padding: UiRect::all(Val::Px(32.0)),
+                gap: Size::all(Val::Px(32.)),
+                flex_direction: FlexDirection::Column,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            parent
+                .spawn(TextBundle {
+                    background_color: Color::CRIMSON.into(),
+                    style: Style {
+                        padding: UiRect::all(Val::Px(12.)),
+                        ..default()
+                    },
+                    text: Text::from_section(
+                        "stats go here",
+                        TextStyle {
+                            font: asset_server.load("fonts/roboto_regular.ttf"),
+                            font_size: 32.0,
+                            color: Color::WHITE,
+                        },
+                    ),
+                    ..default()
+                })
+                .insert(StatsText);
+            parent
+                .spawn(TextBundle {
+                    background_color: Color::CRIMSON.into(),
+                    style: Style {
+                        padding: UiRect::all(Val::Px(12.)),
+                        ..default()
+                    },
+                    text: Text::from_section(
+                        "speed = 1x",
+                        TextStyle {
+                            font: asset_server.load("fonts/roboto_regular.ttf"),
+                            font_size: 32.0,
+                            color: Color::WHITE,
+                        },
+                    ),
+                    ..default()
+                })
+                .insert(SpeedText);
+        });
+}
+
+fn spawn_selection(mut commands: Commands, sprite_sheets: Res<SpriteSheets>) {
+    commands
+        .spawn(SpriteSheetBundle {
+            texture_atlas: sprite_sheets.highlighted_boxes.clone(),
+            transform: Transform::from_xyz(0.0, 0.0, 200.0),
+            sprite: TextureAtlasSprite {
+                index: 1,
+                custom_size: Some(Vec2::ONE),
+                color: Color::rgba(1.0, 1.0, 1.0, 0.5),
+                ..default()
+            },
+            ..default()
+        })
+        .insert(Selection::None);
+}
+
+fn spawn_camera(mut commands: Commands) {
+    let mut camera = Camera2dBundle::default();
+    camera.projection.scale = 0.5;
+    commands.spawn(camera);
+}
+
+// systems
+
+pub const NORMAL_BUTTON: Color = Color::rgb(0.15, 0.15, 0.15);
+const HOVERED_BUTTON: Color = Color::rgb(0.25, 0.25, 0.25);
+const PRESSED_BUTTON: Color = Color::rgb(0.35, 0.75, 0.35);
+
+fn button_style(
+    mut interaction_query: Query<
+        (&Interaction, &mut BackgroundColor, &mut Style),
+        (Changed<Interaction>, With<Button>),
+    >,
+) {
+    for (interaction, mut color, mut style) in interaction_query.iter_mut() {
+        *color = match *interaction {
+            Interaction::Clicked => PRESSED_BUTTON.into(),
+            Interaction::Hovered => HOVERED_BUTTON.into(),
+            Interaction::None => NORMAL_BUTTON.into(),
+        };
+        style.border = match *interaction {
+            Interaction::Hovered => UiRect::all(Val::Px(2.0)),
+            _ => UiRect::default(),
+        };
+    }
+}
+
+fn lumberjack_spawn_button(
+    query: Query<&Interaction, (Changed<Interaction>, With<SpawnButton>)>,
+    _commands: Commands,
+    _asset_server: Res<AssetServer>,
+    _texture_atlases: ResMut<Assets<TextureAtlas>>,
+    mut inputs: ResMut<LocalInputs>,
+) {
+    for interaction in query.iter() {
+        if *interaction == Interaction::Clicked {
+            for _ in 0..20 {
+                let pos = Vec2::new(0.0, 0.0) + random_vec2() * 100.0;
+                inputs.push(PlayerInput::SpawnSoldier(pos));
+            }
+        };
+    }
+}
+
+fn spawn_menu_tween(
+    mut query: Query<&mut Style, With<SpawnMenu>>,
+    time: Res<Time>,
+    windows: Query<&Window>,
+    mut var: Local<f32>,
+) {
+    let win = windows.single();
+    let is_hidden = if let Some(position) = win.cursor_position() {
+        position.y < 100.0
+    } else {
+        true
+    };
+    *var += time.raw_delta_seconds() * if is_hidden { -1.0 } else { 1.0 } / 0.2;
+    *var = var.clamp(0.0, 1.0);
+
+    for mut style in query.iter_mut() {
+        style.position.bottom = Val::Px(ease_in_out_cubic(*var) * -100.0);
+    }
+}
+
+fn stat_text(
+    mut query: Query<&mut Text, With<StatsText>>,
+    stats: Res<Stats>,
+    team: Res<LocalTeam>,
+) {
+    let resources = stats.team(**team);
+    for mut text in query.iter_mut() {
+        text.sections[0].value = format!("wood = {}\nmetal = {}", resources.wood, resources.metal)
+    }
+}
+
+// the game clock drives the ticks, pausing it stops the match but not the camera, the UI or
+// the player's orders, they apply once the game runs again
+fn game_speed(
+    actions: Res<Input<InputAction>>,
+    mut time: ResMut<Time>,
+    session: Option<Res<LockstepSession>>,
+) {
+    let pressed = [
+        InputAction::Pause,
+        InputAction::SpeedUp,
+        InputAction::SpeedDown,
+    ]
+    .into_iter()
+    .filter(|action| actions.just_pressed(*action))
+    .collect::<Vec<_>>();
+    if pressed.is_empty() {
+        return;
+    }
+    if session.is_some() {
+        warn!("the speed of a multiplayer match can't be changed");
+        return;
+    }
+    let speed = time.relative_speed();
+    for action in pressed {
+        match action {
+            InputAction::Pause if time.is_paused() => time.unpause(),
+            InputAction::Pause => time.pause(),
+            InputAction::SpeedUp => {
+                if let Some(faster) = GAME_SPEEDS.into_iter().find(|s| *s > speed) {
+                    time.set_relative_speed(faster);
+                }
+            }
+            _ => {
+                if let Some(slower) = GAME_SPEEDS.into_iter().rev().find(|s| *s < speed) {
+                    time.set_relative_speed(slower);
+                }
+            }
+        }
+    }
+}
+
+fn speed_text(mut query: Query<&mut Text, With<SpeedText>>, time: Res<Time>) {
+    for mut text in query.iter_mut() {
+        text.sections[0].value = if time.is_paused() {
+            "paused".to_string()
+        } else {
+            format!("speed = {}x", time.relative_speed())
+        };
+    }
+}
+
+// the debug damage is no input of the match, so peers and replays never see it. It only works
+// in a local match and ends its recording, the replay would go a different way from here.
+fn keyboard_input(
+    mut commands: Commands,
+    actions: Res<Input<InputAction>>,
+    selected_query: Query<Entity, With<SelectedMark>>,
+    source: Res<InputSource>,
+    recorder: Option<Res<ReplayRecorder>>,
+    mut damage_events: EventWriter<DamageEvent>,
+) {
+    if actions.just_pressed(InputAction::Debug) {
+        if *source != InputSource::Local {
+            return;
+        }
+        if recorder.is_some() {
+            warn!("debug damage can't be replayed, the match is no longer recorded");
+            commands.remove_resource::<ReplayRecorder>();
+        }
+        // hurt the selection to try out health bars and death
+        for entity in selected_query.iter() {
+            damage_events.send(DamageEvent {
+                target: entity,
+                amount: 10.0,
+                source: None,
+            });
+        }
+    }
+}
+
+fn move_camera(
+    mut query: Query<&mut Transform, With<Camera2d>>,
+    actions: Res<Input<InputAction>>,
+    time: Res<Time>,
+    mut motion_evr: EventReader<MouseMotion>,
+) {
+    let mut dir_keyboard = Vec2::ZERO;
+
+    if actions.pressed(InputAction::CameraPanLeft) {
+        dir_keyboard -= Vec2::X
+    }
+    if actions.pressed(InputAction::CameraPanRight) {
+        dir_keyboard += Vec2::X
+    }
+    if actions.pressed(InputAction::CameraPanUp) {
+        dir_keyboard += Vec2::Y
+    }
+    if actions.pressed(InputAction::CameraPanDown) {
+        dir_keyboard -= Vec2::Y
+    }
+    // the camera keeps moving while a replay is paused or fast forwarded
+    let move_keyboard = dir_keyboard.clamp_length_max(1.0) * 200.0 * time.raw_delta_seconds();
+
+    let dir_mouse = motion_evr
+        .iter()
+        .map(|e| e.delta)
+        .fold(Vec2::ZERO, |x, y| x + y)
+        * 0.6 // slow down a little
+        * if actions.pressed(InputAction::CameraDrag) {
+            Vec2::new(-1.0, 1.0)
+        } else {
+            Vec2::ZERO
+        };
+
+    let mut camera_transform = query.single_mut();
+    camera_transform.translation += (move_keyboard + dir_mouse).round().extend(0.0);
+}
+
+fn fullscreen_toggle(actions: Res<Input<InputAction>>, mut windows: Query<&mut Window>) {
+    if actions.just_pressed(InputAction::ToggleFullscreen) {
+        let mut window = windows.single_mut();
+        window.mode = if window.mode == WindowMode::Windowed {
+            WindowMode::BorderlessFullscreen
+        } else {
+            WindowMode::Windowed
+        };
+    }
+}
+
+// fn inspector_toggle(keys: Res<Input<KeyCode>>) {
+//     if keys.just_pressed(KeyCode::F11) {
+//         let mut window = windows.single_mut();
+//         window.mode = if window.mode == WindowMode::Windowed {
+//             WindowMode::BorderlessFullscreen
+//         } else {
+//             WindowMode::Windowed
+//         };
+//     }
+// }
+
+fn cursor_world_position(
+    // need to get window dimensions
+    windows: Query<&Window>,
+    // query to get camera transform
+    camera_query: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
+    mut cursor: ResMut<Cursor>,
+) {
+    // get the camera info and transform
+    // assuming there is exactly one main camera entity, so query::single() is OK
+    let (camera, camera_transform) = camera_query.single();
+
+    // get the window that the camera is displaying to (or the primary window)
+    let window = match camera.target {
+        RenderTarget::Window(window_ref) => match window_ref {
+            WindowRef::Entity(e) => windows.get(e).expect("a window from an entity"),
+            WindowRef::Primary => windows.single(),
+        },
+        _ => windows.single(),
+    };
+
+    // check if the cursor is inside the window and get its position
+    if let Some(screen_pos) = window.cursor_position() {
+        // get the size of the window
+        let window_size = Vec2::new(window.width(), window.height());
+        // convert screen position [0..resolution] to ndc [-1..1] (gpu coordinates)
+        let ndc = (screen_pos / window_size) * 2.0 - Vec2::ONE;
+        // matrix for undoing the projection and camera transform
+        let ndc_to_world = camera_transform.compute_matrix() * camera.projection_matrix().inverse();
+        // use it to convert ndc to world-space coordinates
+        let world_pos = ndc_to_world.project_point3(ndc.extend(-1.0));
+        // reduce it to a 2D value
+        let world_pos: Vec2 = world_pos.truncate();
+        cursor.0 = world_pos;
+    }
+}
+
+const CLICK_DISTANCE: f32 = 4.0;
+
+fn selection_change(
+    mut query: Query<&mut Selection>,
+    cursor: Res<Cursor>,
+    actions: Res<Input<InputAction>>,
+    mut apply_selection: EventWriter<ApplySelectionEvent>,
+) {
+    let mut selection = query.single_mut();
+
+    match *selection {
+        Selection::None => {
+            if actions.pressed(InputAction::Select) {
+                *selection = Dragging(cursor.0, cursor.0)
+            }
+        }
+        Dragging(start, end) => {
+            *selection = if actions.pressed(InputAction::Select) {
+                Dragging(start, cursor.0)
+            } else {
+                let is_click = start.distance(end) < CLICK_DISTANCE;
+                let mode = if actions.pressed(InputAction::Queue) {
+                    SelectionMode::Add
+                } else if is_click && actions.pressed(InputAction::GroupModifier) {
+                    SelectionMode::SameKind
+                } else {
+                    SelectionMode::Replace
+                };
+                apply_selection.send(ApplySelectionEvent { start, end, mode });
+                Selection::None
+            }
+        }
+    }
+}
+
+fn selection_visual(mut query: Query<(&mut Transform, &mut TextureAtlasSprite, &Selection)>) {
+    let (mut transform, mut sprite, selection) = query.single_mut();
+
+    transform.scale = Vec3::ZERO;
+
+    if let Dragging(start, end) = *selection {
+        let center = (start + end) * 0.5;
+        let size = Vec2::abs(start - end);
+
+        transform.translation = center.extend(2.0);
+        transform.scale = size.extend(1.0);
+        sprite.index = 1;
+    }
+}
+
+fn record_previous_translation(
+    mut query: Query<(Entity, &Transform, Option<&mut PreviousTranslation>), With<Unit>>,
+    mut commands: Commands,
+) {
+    for (entity, transform, previous) in query.iter_mut() {
+        match previous {
+            Some(mut previous) => previous.0 = transform.translation,
+            None => {
+                commands
+                    .entity(entity)
+                    .insert(PreviousTranslation(transform.translation));
+            }
+        }
+    }
+}
+
+// the simulation moves units in ticks, so without this they would stutter whenever a frame
+// runs no tick or several
+fn interpolate_units(
+    fixed_time: Res<FixedTime>,
+    query: Query<(Entity, &Transform, &PreviousTranslation, Option<&Children>)>,
+    mut global_query: Query<&mut GlobalTransform>,
+) {
+    let alpha = (fixed_time.accumulated().as_secs_f32() / fixed_time.period.as_secs_f32()).min(1.0);
+    for (entity, transform, previous, children) in query.iter() {
+        // only x and y, the z of the unit is its draw order
+        let offset = ((previous.0 - transform.translation) * (1.0 - alpha)).truncate();
+        let offset = GlobalTransform::from_translation(offset.extend(0.0));
+        let children = children.map(|c| c.iter()).into_iter().flatten();
+        for entity in std::iter::once(&entity).chain(children) {
+            if let Ok(mut global) = global_query.get_mut(*entity) {
+                *global = offset * *global;
+            }
+        }
+    }
+}
+
+fn ysort(mut query: Query<&mut Transform, With<YSort>>) {
+    for mut transform in query.iter_mut() {
+        transform.translation.z = 200.0 - transform.translation.y * 0.0001;
+    }
+}
+#[allow(clippy::type_complexity)]
+pub fn camera_view_check(
+    camera_query: Query<(&Transform, &OrthographicProjection), With<Camera>>,
+    mut visible_query: Query<
+        (
+            &GlobalTransform,
+            &mut Visibility,
+            &Handle<TextureAtlas>,
+            Option<&Team>,
+        ),
+        With<Cull2D>,
+    >,
+    vision: Res<ReplayVision>,
+    texute_atlases: Res<Assets<TextureAtlas>>,
+    mut size_cache: Local<HashMap<Handle<TextureAtlas>, f32>>,
+) {
+    for (camera_transform, projection) in camera_query.iter() {
+        let camera_pos = camera_transform.translation.truncate();
+        for (transform, mut visible, tex, team) in visible_query.iter_mut() {
+            let size = *(*size_cache).entry(tex.clone()).or_insert_with(|| {
+                texute_atlases
+                    .get(tex)
+                    .expect("valid texture")
+                    .textures
+                    .iter()
+                    .map(Rect::size)
+                    .map(Vec2::max_element)
+                    .map(f32::round)
+                    .map(|f| f as i32)
+                    .collect::<Vec<_>>()
+                    .into_iter()
+                    .max()
+                    .unwrap_or_default() as f32
+            });
+
+            let mut rect = projection.area.inset(size);
+            rect.min += camera_pos;
+            rect.max += camera_pos;
+            let pos = transform.translation().truncate();
+            let seen = match (**vision, team) {
+                (Some(vision), Some(team)) => vision == *team,
+                _ => true,
+            };
+            *visible = if seen && rect.contains(pos) {
+                Visibility::Inherited
+            } else {
+                Visibility::Hidden
+            };
+        }
+    }
+}
@@ -0,0 +1,430 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use serde::{Deserialize, Serialize};
+
+use crate::ground::WorldSize;
+use crate::input_action::InputAction;
+use crate::scenario::Scenario;
+use crate::simulation::{
+    apply_inputs, assign_sim_ids, local_inputs, state_hash, PlayerInput, SimIds, SimulationSet,
+    SimulationTick, TickInputs,
+};
+use crate::unit::{SelectedMark, Team, Unit};
+use crate::util::{parse_arg, ArgError};
+use crate::WorldSeed;
+
+/// Records the inputs of the match while a `ReplayRecorder` exists and feeds them back in
+/// while a `ReplayPlayer` does.
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ReplayedSelection>()
+            .add_system(
+                replay_checksums
+                    .before(assign_sim_ids)
+                    .in_set(SimulationSet::Receive)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            )
+            .add_systems(
+                (
+                    play_inputs.run_if(resource_exists::<ReplayPlayer>()),
+                    record_inputs.run_if(resource_exists::<ReplayRecorder>()),
+                )
+                    .chain()
+                    .after(local_inputs)
+                    .before(apply_inputs)
+                    .in_set(SimulationSet::Receive)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            )
+            .add_system(
+                write_replay_on_exit
+                    .run_if(resource_exists::<ReplayRecorder>())
+                    .in_base_set(CoreSet::Last),
+            );
+    }
+}
+
+// bump whenever the layout of `Replay` changes
+pub const REPLAY_VERSION: u32 = 4;
+pub const LAST_REPLAY_PATH: &str = "replays/last.ron";
+// ticks between two state checksums, the recording is also written to disk this often
+const CHECKSUM_INTERVAL: u64 = 100;
+const REPLAY_SPEEDS: [f32; 3] = [1.0, 2.0, 8.0];
+
+/// Everything needed to play a match again: the world it started from and the inputs in the
+/// order they were applied. Entities are stored as `SimId`s.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Replay {
+    version: u32,
+    pub seed: WorldSeed,
+    pub size: WorldSize,
+    pub scenario: Scenario,
+    // the last tick that was recorded
+    ticks: u64,
+    events: Vec<(u64, ReplayEvent)>,
+    // `state_hash` of the start of every `CHECKSUM_INTERVAL`th tick
+    checksums: Vec<(u64, u64)>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+enum ReplayEvent {
+    // with the team of the player that sent it
+    Input(Team, PlayerInput),
+    // the selected units after the player changed the selection, only the viewer shows it
+    Selection(Vec<Entity>),
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(std::io::Error),
+    Serialize(ron::Error),
+    Parse(ron::error::SpannedError),
+    Version(u32),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "could not access replay file: {e}"),
+            ReplayError::Serialize(e) => write!(f, "could not serialize replay: {e}"),
+            ReplayError::Parse(e) => write!(f, "could not parse replay file: {e}"),
+            ReplayError::Version(version) => write!(
+                f,
+                "replay file has version {version} but version {REPLAY_VERSION} is expected"
+            ),
+        }
+    }
+}
+
+impl Replay {
+    pub fn new(seed: WorldSeed, size: WorldSize, scenario: Scenario) -> Self {
+        Replay {
+            version: REPLAY_VERSION,
+            seed,
+            size,
+            scenario,
+            ticks: 0,
+            events: vec![],
+            checksums: vec![],
+        }
+    }
+
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    // not pretty printed, a long match has a lot of inputs
+    pub fn write(&self, path: &Path) -> Result<(), ReplayError> {
+        let source = ron::to_string(self).map_err(ReplayError::Serialize)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(ReplayError::Io)?;
+        }
+        fs::write(path, source).map_err(ReplayError::Io)
+    }
+
+    pub fn read(path: &Path) -> Result<Replay, ReplayError> {
+        let source = fs::read_to_string(path).map_err(ReplayError::Io)?;
+        let replay: Replay = ron::from_str(&source).map_err(ReplayError::Parse)?;
+        if replay.version != REPLAY_VERSION {
+            return Err(ReplayError::Version(replay.version));
+        }
+        Ok(replay)
+    }
+}
+
+/// Records the match to `path`, the file is rewritten every `CHECKSUM_INTERVAL` ticks and
+/// when the app exits.
+#[derive(Resource)]
+pub struct ReplayRecorder {
+    path: PathBuf,
+    replay: Replay,
+}
+
+impl ReplayRecorder {
+    pub fn new(
+        path: impl Into<PathBuf>,
+        seed: WorldSeed,
+        size: WorldSize,
+        scenario: Scenario,
+    ) -> Self {
+        ReplayRecorder {
+            path: path.into(),
+            replay: Replay::new(seed, size, scenario),
+        }
+    }
+
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    fn write(&mut self, tick: SimulationTick) {
+        self.replay.ticks = *tick;
+        match self.replay.write(&self.path) {
+            Ok(()) => debug!("wrote replay to {}", self.path.display()),
+            Err(e) => error!("{e}"),
+        }
+    }
+}
+
+/// Plays a replay back, goes together with `InputSource::Replay` so the local player can't
+/// change the match.
+#[derive(Resource)]
+pub struct ReplayPlayer {
+    replay: Replay,
+    next_event: usize,
+    next_checksum: usize,
+    // the first tick whose checksum didn't match the recording
+    desync: Option<u64>,
+}
+
+impl ReplayPlayer {
+    pub fn new(replay: Replay) -> Self {
+        ReplayPlayer {
+            replay,
+            next_event: 0,
+            next_checksum: 0,
+            desync: None,
+        }
+    }
+
+    /// Reads the replay from `--replay <path>`, `None` if the game wasn't started with one.
+    pub fn from_args() -> Result<Option<Self>, ArgError> {
+        let Some(path) = parse_arg::<PathBuf>("--replay")? else {
+            return Ok(None);
+        };
+        let replay = Replay::read(&path).map_err(|e| ArgError::new("--replay", e))?;
+        info!("playing replay {}", path.display());
+        Ok(Some(ReplayPlayer::new(replay)))
+    }
+
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    pub fn desync(&self) -> Option<u64> {
+        self.desync
+    }
+
+    fn check(&mut self, tick: u64, hash: u64) {
+        while let Some((checksum_tick, expected)) = self.replay.checksums.get(self.next_checksum) {
+            if *checksum_tick > tick {
+                break;
+            }
+            self.next_checksum += 1;
+            if *checksum_tick == tick && *expected != hash && self.desync.is_none() {
+                error!("replay desynced at tick {tick}");
+                self.desync = Some(tick);
+            }
+        }
+    }
+}
+
+/// The selection of the recorded player, the viewer selects these units.
+#[derive(Resource, Default)]
+pub struct ReplayedSelection(Option<Vec<Entity>>);
+
+/// Which team's units the replay viewer shows, `None` shows everyone.
+#[derive(Resource, Default, Deref)]
+pub struct ReplayVision(Option<Team>);
+
+#[derive(Component)]
+pub(crate) struct ReplayText;
+
+fn replay_checksums(world: &mut World) {
+    let tick = *world.resource::<SimulationTick>();
+    if !tick.is_multiple_of(CHECKSUM_INTERVAL)
+        || !(world.contains_resource::<ReplayRecorder>()
+            || world.contains_resource::<ReplayPlayer>())
+    {
+        return;
+    }
+    let hash = state_hash(world);
+    if let Some(mut recorder) = world.get_resource_mut::<ReplayRecorder>() {
+        recorder.replay.checksums.push((*tick, hash));
+        recorder.write(tick);
+    }
+    if let Some(mut player) = world.get_resource_mut::<ReplayPlayer>() {
+        player.check(*tick, hash);
+    }
+}
+
+fn play_inputs(
+    tick: Res<SimulationTick>,
+    mut player: ResMut<ReplayPlayer>,
+    ids: SimIds,
+    mut inputs: ResMut<TickInputs>,
+    mut selection: ResMut<ReplayedSelection>,
+) {
+    let player = player.as_mut();
+    while let Some((event_tick, event)) = player.replay.events.get(player.next_event) {
+        if *event_tick > **tick {
+            break;
+        }
+        player.next_event += 1;
+        match event {
+            ReplayEvent::Input(team, input) => {
+                inputs.extend(ids.import(input).map(|input| (*team, input)))
+            }
+            ReplayEvent::Selection(units) => selection.0 = Some(ids.import_entities(units)),
+        }
+    }
+    if **tick == player.replay.ticks {
+        info!("replay finished at tick {}", **tick);
+    }
+}
+
+fn record_inputs(
+    tick: Res<SimulationTick>,
+    mut recorder: ResMut<ReplayRecorder>,
+    ids: SimIds,
+    inputs: Res<TickInputs>,
+) {
+    for (team, input) in inputs.iter() {
+        if let Some(input) = ids.export(input) {
+            recorder
+                .replay
+                .events
+                .push((**tick, ReplayEvent::Input(*team, input)));
+        }
+    }
+}
+
+fn write_replay_on_exit(
+    mut exit_events: EventReader<AppExit>,
+    tick: Res<SimulationTick>,
+    mut recorder: ResMut<ReplayRecorder>,
+) {
+    if exit_events.iter().next().is_some() {
+        recorder.write(*tick);
+    }
+}
+
+// the selection is recorded as a whole whenever it changes, however it was changed
+pub(crate) fn record_selection(
+    added_query: Query<(), Added<SelectedMark>>,
+    mut removed: RemovedComponents<SelectedMark>,
+    selected_query: Query<Entity, With<SelectedMark>>,
+    tick: Res<SimulationTick>,
+    ids: SimIds,
+    mut recorder: ResMut<ReplayRecorder>,
+) {
+    if added_query.is_empty() && removed.iter().next().is_none() {
+        return;
+    }
+    let units = selected_query
+        .iter()
+        .filter_map(|entity| ids.export_entity(entity))
+        .collect();
+    recorder
+        .replay
+        .events
+        .push((**tick, ReplayEvent::Selection(units)));
+}
+
+pub(crate) fn replay_selection(
+    mut selection: ResMut<ReplayedSelection>,
+    unit_query: Query<(Entity, Option<&SelectedMark>), With<Unit>>,
+    mut commands: Commands,
+) {
+    let Some(units) = selection.0.take() else {
+        return;
+    };
+    let units = units.into_iter().collect::<HashSet<_>>();
+    for (entity, selected) in unit_query.iter() {
+        match (units.contains(&entity), selected.is_some()) {
+            (true, false) => {
+                commands.entity(entity).insert(SelectedMark);
+            }
+            (false, true) => {
+                commands.entity(entity).remove::<SelectedMark>();
+            }
+            _ => {}
+        }
+    }
+}
+
+// fast forward and whose units are shown, pause and the camera work as in a match
+pub(crate) fn replay_controls(
+    actions: Res<Input<InputAction>>,
+    mut time: ResMut<Time>,
+    mut vision: ResMut<ReplayVision>,
+    team_query: Query<&Team, With<Unit>>,
+) {
+    if actions.just_pressed(InputAction::ReplaySpeed) {
+        let speed = time.relative_speed();
+        let next = REPLAY_SPEEDS
+            .iter()
+            .position(|s| *s == speed)
+            .map_or(0, |i| (i + 1) % REPLAY_SPEEDS.len());
+        time.set_relative_speed(REPLAY_SPEEDS[next]);
+    }
+    if actions.just_pressed(InputAction::ReplayVision) {
+        let mut teams = team_query.iter().map(|team| team.0).collect::<Vec<_>>();
+        teams.sort_unstable();
+        teams.dedup();
+        // everyone, then each team in turn
+        vision.0 = match vision.0 {
+            None => teams.first().copied().map(Team),
+            Some(Team(current)) => teams.into_iter().find(|team| *team > current).map(Team),
+        };
+    }
+}
+
+pub(crate) fn spawn_replay_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(10.0),
+                    top: Val::Px(10.0),
+                    ..default()
+                },
+                ..default()
+            },
+            text: Text::from_section(
+                "replay",
+                TextStyle {
+                    font: asset_server.load("fonts/roboto_regular.ttf"),
+                    font_size: 32.0,
+                    color: Color::WHITE,
+                },
+            ),
+            ..default()
+        })
+        .insert(ReplayText);
+}
+
+pub(crate) fn replay_text(
+    mut query: Query<&mut Text, With<ReplayText>>,
+    player: Res<ReplayPlayer>,
+    tick: Res<SimulationTick>,
+    time: Res<Time>,
+    vision: Res<ReplayVision>,
+) {
+    let speed = if time.is_paused() {
+        "paused".to_string()
+    } else {
+        format!("{}x", time.relative_speed())
+    };
+    let vision = match vision.0 {
+        Some(Team(team)) => format!("team {team}"),
+        None => "all".to_string(),
+    };
+    let desync = match player.desync {
+        Some(tick) => format!("\ndesync at tick {tick}"),
+        None => String::new(),
+    };
+    for mut text in query.iter_mut() {
+        text.sections[0].value = format!(
+            "replay {}/{} {speed}\nvision = {vision}{desync}",
+            **tick, player.replay.ticks
+        );
+    }
+}
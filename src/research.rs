@@ -0,0 +1,179 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::definition::{Definitions, ResearchEffect};
+use crate::simulation::{tick_seconds, SimulationAppExt, SimulationSet};
+use crate::unit::Team;
+use crate::{BuildingKind, Stats};
+
+pub struct ResearchPlugin;
+
+impl Plugin for ResearchPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_simulation_event::<ResearchEvent>()
+            .init_resource::<ResearchState>()
+            .add_system(
+                start_research
+                    .in_set(SimulationSet::Input)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            )
+            .add_system(
+                research_progress
+                    .in_set(SimulationSet::Actions)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            );
+    }
+}
+
+/// Starts the research with the given id at an idle building of the team, see `TechTree`.
+pub struct ResearchEvent(pub String, pub Team);
+
+/// The research every team has finished, in the order it finished.
+#[derive(Resource, Default, Clone, Debug, Serialize, Deserialize)]
+pub struct ResearchState {
+    // ordered by team, so every machine saves and hashes the same state
+    completed: BTreeMap<Team, Vec<String>>,
+}
+
+/// The research a building is working on, buildings research one thing at a time.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct Researching {
+    pub id: String,
+    // seconds
+    pub remaining: f32,
+}
+
+impl ResearchState {
+    pub fn is_done(&self, team: Team, id: &str) -> bool {
+        self.completed(team).iter().any(|done| done == id)
+    }
+
+    fn completed(&self, team: Team) -> &[String] {
+        self.completed.get(&team).map_or(&[], Vec::as_slice)
+    }
+
+    fn effects<'a>(
+        &'a self,
+        team: Team,
+        definitions: &'a Definitions,
+    ) -> impl Iterator<Item = ResearchEffect> + 'a {
+        self.completed(team)
+            .iter()
+            .filter_map(|id| definitions.research(id))
+            .map(|research| research.effect)
+    }
+
+    /// Multiplier of how fast the workers of `team` chop.
+    pub fn chop_speed(&self, team: Team, definitions: &Definitions) -> f32 {
+        self.effects(team, definitions)
+            .map(|effect| match effect {
+                ResearchEffect::ChopSpeed(speed) => speed,
+                _ => 1.0,
+            })
+            .product()
+    }
+
+    /// Wood a worker of `team` carries on top of its definition.
+    pub fn carry_bonus(&self, team: Team, definitions: &Definitions) -> u32 {
+        self.effects(team, definitions)
+            .map(|effect| match effect {
+                ResearchEffect::CarryCapacity(bonus) => bonus,
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+pub(crate) fn start_research(
+    mut events: EventReader<ResearchEvent>,
+    mut commands: Commands,
+    building_query: Query<(Entity, &BuildingKind, &Transform, &Team), Without<Researching>>,
+    researching_query: Query<(&Researching, &Team)>,
+    state: Res<ResearchState>,
+    mut stats: ResMut<Stats>,
+    definitions: Res<Definitions>,
+) {
+    // commands are applied after the system, so buildings taken this tick are tracked here
+    let mut started: Vec<(Entity, &str, Team)> = Vec::new();
+    for ResearchEvent(id, team) in events.iter() {
+        let Some(research) = definitions.research(id) else {
+            warn!("there is no research {id}");
+            continue;
+        };
+        let in_progress = researching_query
+            .iter()
+            .any(|(other, other_team)| other.id == *id && other_team == team)
+            || started
+                .iter()
+                .any(|(_, other, other_team)| other == id && other_team == team);
+        if state.is_done(*team, id) || in_progress {
+            continue;
+        }
+        if let Some(missing) = research
+            .requires
+            .iter()
+            .find(|id| !state.is_done(*team, id))
+        {
+            info!("{} needs {missing} first", research.name);
+            continue;
+        }
+        if !research.cost.affordable(&stats.team(*team)) {
+            info!("not enough resources for {}", research.name);
+            continue;
+        }
+        // the leftmost building, so every machine picks the same one
+        let Some((building, _)) = building_query
+            .iter()
+            .filter(|(entity, kind, _, building_team)| {
+                **kind == research.building
+                    && *building_team == team
+                    && !started.iter().any(|(other, _, _)| other == entity)
+            })
+            .map(|(entity, _, transform, _)| (entity, transform.translation.truncate()))
+            .min_by(|(_, a), (_, b)| {
+                (a.x, a.y)
+                    .partial_cmp(&(b.x, b.y))
+                    .expect("finite building positions")
+            })
+        else {
+            info!("no idle {:?} for {}", research.building, research.name);
+            continue;
+        };
+        research.cost.pay(stats.team_mut(*team));
+        commands.entity(building).insert(Researching {
+            id: research.id.clone(),
+            remaining: research.time,
+        });
+        started.push((building, id, *team));
+    }
+}
+
+pub(crate) fn research_progress(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut Researching, &Team)>,
+    mut state: ResMut<ResearchState>,
+    definitions: Res<Definitions>,
+) {
+    let mut finished = Vec::new();
+    for (entity, mut researching, team) in query.iter_mut() {
+        researching.remaining -= tick_seconds();
+        if researching.remaining <= 0.0 {
+            finished.push((*team, researching.id.clone()));
+            commands.entity(entity).remove::<Researching>();
+        }
+    }
+    // the query order differs between machines, the tech tree order doesn't
+    let tech_tree = definitions.tech_tree();
+    finished.sort_by_key(|(team, id)| {
+        (
+            *team,
+            tech_tree.iter().position(|research| research.id == *id),
+        )
+    });
+    for (team, id) in finished {
+        info!("{team:?} finished research {id}");
+        state.completed.entry(team).or_default().push(id);
+    }
+}
@@ -0,0 +1,164 @@
+use bevy::prelude::*;
+
+use crate::definition::Definitions;
+use crate::input_action::InputAction;
+use crate::presentation::NORMAL_BUTTON;
+use crate::research::{ResearchState, Researching};
+use crate::simulation::{LocalInputs, LocalTeam, PlayerInput};
+use crate::unit::Team;
+use crate::Stats;
+
+pub struct ResearchPanelPlugin;
+
+impl Plugin for ResearchPanelPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(setup_research_panel)
+            .add_system(research_panel_toggle)
+            .add_system(research_panel_rebuild)
+            .add_system(research_panel_buttons)
+            .add_system(research_panel_text.after(research_panel_rebuild));
+    }
+}
+
+// toggled with `InputAction::ToggleResearch`
+#[derive(Component)]
+struct ResearchPanel;
+
+#[derive(Component)]
+struct ResearchButton(String);
+
+#[derive(Component)]
+struct ResearchButtonText(String);
+
+fn setup_research_panel(mut commands: Commands) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(16.0),
+                    bottom: Val::Px(116.0),
+                    ..default()
+                },
+                flex_direction: FlexDirection::Column,
+                gap: Size::all(Val::Px(4.0)),
+                ..default()
+            },
+            visibility: Visibility::Hidden,
+            ..default()
+        })
+        .insert(Name::new("Research Panel"))
+        .insert(ResearchPanel);
+}
+
+fn research_panel_toggle(
+    actions: Res<Input<InputAction>>,
+    mut panel_query: Query<&mut Visibility, With<ResearchPanel>>,
+) {
+    if !actions.just_pressed(InputAction::ToggleResearch) {
+        return;
+    }
+    for mut visibility in panel_query.iter_mut() {
+        *visibility = match *visibility {
+            Visibility::Hidden => Visibility::Inherited,
+            _ => Visibility::Hidden,
+        };
+    }
+}
+
+// the tech tree is reloaded while the game runs, so the buttons follow it
+fn research_panel_rebuild(
+    mut commands: Commands,
+    panel_query: Query<Entity, With<ResearchPanel>>,
+    definitions: Res<Definitions>,
+    asset_server: Res<AssetServer>,
+) {
+    if !definitions.is_changed() {
+        return;
+    }
+    let font = asset_server.load("fonts/roboto_regular.ttf");
+    for panel in panel_query.iter() {
+        commands.entity(panel).despawn_descendants();
+        commands.entity(panel).with_children(|parent| {
+            for research in definitions.tech_tree() {
+                parent
+                    .spawn(ButtonBundle {
+                        style: Style {
+                            padding: UiRect::all(Val::Px(8.0)),
+                            ..default()
+                        },
+                        background_color: NORMAL_BUTTON.into(),
+                        ..default()
+                    })
+                    .with_children(|parent| {
+                        parent
+                            .spawn(TextBundle::from_section(
+                                "",
+                                TextStyle {
+                                    font: font.clone(),
+                                    font_size: 16.0,
+                                    color: Color::WHITE,
+                                },
+                            ))
+                            .insert(ResearchButtonText(research.id.clone()));
+                    })
+                    .insert(ResearchButton(research.id.clone()));
+            }
+        });
+    }
+}
+
+fn research_panel_buttons(
+    query: Query<(&Interaction, &ResearchButton), Changed<Interaction>>,
+    mut inputs: ResMut<LocalInputs>,
+) {
+    for (interaction, button) in query.iter() {
+        if *interaction == Interaction::Clicked {
+            inputs.push(PlayerInput::Research(button.0.clone()));
+        }
+    }
+}
+
+fn research_panel_text(
+    mut text_query: Query<(&mut Text, &ResearchButtonText)>,
+    researching_query: Query<(&Researching, &Team)>,
+    state: Res<ResearchState>,
+    team: Res<LocalTeam>,
+    stats: Res<Stats>,
+    definitions: Res<Definitions>,
+) {
+    for (mut text, ResearchButtonText(id)) in text_query.iter_mut() {
+        let Some(research) = definitions.research(id) else {
+            continue;
+        };
+        let status = if state.is_done(**team, id) {
+            "done".to_string()
+        } else if let Some((researching, _)) = researching_query
+            .iter()
+            .find(|(researching, building_team)| researching.id == *id && **building_team == **team)
+        {
+            let progress = 1.0 - researching.remaining / research.time;
+            format!("researching {:.0}%", progress * 100.0)
+        } else if let Some(missing) = research
+            .requires
+            .iter()
+            .find(|id| !state.is_done(**team, id))
+        {
+            let missing = definitions
+                .research(missing)
+                .map_or(missing.as_str(), |missing| missing.name.as_str());
+            format!("needs {missing}")
+        } else if !research.cost.affordable(&stats.team(**team)) {
+            "not enough resources".to_string()
+        } else {
+            String::new()
+        };
+        let label = format!(
+            "{} - {}, {:.0}s {status}",
+            research.name, research.cost, research.time
+        );
+        if text.sections[0].value != label {
+            text.sections[0].value = label;
+        }
+    }
+}
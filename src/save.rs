@@ -0,0 +1,527 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bevy::ecs::entity::{EntityMap, MapEntities};
+use bevy::ecs::system::SystemState;
+use bevy::hierarchy::despawn_with_children_recursive;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::command::OrderQueue;
+use crate::control_group::ControlGroups;
+use crate::definition::Definitions;
+use crate::equipment::Armory;
+use crate::forest::{Sapling, Stump};
+use crate::ground::WorldSize;
+use crate::input_action::InputAction;
+use crate::lockstep::LockstepSession;
+use crate::lumberjack::{spawn_lumberjack, Lumberjack};
+use crate::morale::Morale;
+use crate::replay::{ReplayPlayer, ReplayRecorder};
+use crate::research::{ResearchState, Researching};
+use crate::scenario::{Scenario, ScenarioState};
+use crate::simulation::SimulationTick;
+use crate::soldier::{spawn_soldier, Soldier, Stance};
+use crate::terrain::TerrainMap;
+use crate::unit::{Team, Unit};
+use crate::veterancy::Veterancy;
+use crate::world::{load_chunk, ChunkRegistry, PersistedTree};
+use crate::{
+    spawn_building, spawn_tree, BuildingKind, Ground, SpriteSheets, Stats, Tree, UnitQuadTree,
+    WorldSeed,
+};
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SaveGameEvent>()
+            .add_event::<LoadGameEvent>()
+            .add_system(save_game)
+            .add_system(load_game);
+    }
+}
+
+// bump whenever the layout of `SaveGame` changes
+pub const SAVE_VERSION: u32 = 19;
+const QUICK_SAVE_PATH: &str = "saves/quicksave.ron";
+
+pub struct SaveGameEvent(pub PathBuf);
+pub struct LoadGameEvent(pub PathBuf);
+
+#[derive(Serialize, Deserialize)]
+pub struct SaveGame {
+    version: u32,
+    seed: WorldSeed,
+    size: WorldSize,
+    tick: SimulationTick,
+    stats: Stats,
+    research: ResearchState,
+    scenario: Scenario,
+    scenario_state: ScenarioState,
+    camera: SavedCamera,
+    loaded_chunks: Vec<IVec2>,
+    // trees of chunks that are not loaded, they can't be referenced by units
+    unloaded_chunks: Vec<(IVec2, Vec<PersistedTree>)>,
+    trees: Vec<SavedTree>,
+    buildings: Vec<SavedBuilding>,
+    units: Vec<SavedUnit>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedCamera {
+    pos: Vec2,
+    scale: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedTree {
+    entity: Entity,
+    pos: Vec2,
+    index: usize,
+    tree: Tree,
+    sapling: Option<Sapling>,
+    stump: Option<Stump>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedBuilding {
+    entity: Entity,
+    pos: Vec2,
+    kind: BuildingKind,
+    team: Team,
+    research: Option<Researching>,
+    armory: Option<Armory>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedUnit {
+    entity: Entity,
+    pos: Vec2,
+    unit: Unit,
+    team: Team,
+    orders: OrderQueue,
+    role: SavedRole,
+}
+
+#[derive(Serialize, Deserialize)]
+enum SavedRole {
+    Lumberjack(Lumberjack),
+    Soldier(Soldier, Stance, Veterancy, Morale),
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(std::io::Error),
+    Serialize(ron::Error),
+    Parse(ron::error::SpannedError),
+    Version(u32),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::Io(e) => write!(f, "could not access save file: {e}"),
+            SaveError::Serialize(e) => write!(f, "could not serialize save game: {e}"),
+            SaveError::Parse(e) => write!(f, "could not parse save file: {e}"),
+            SaveError::Version(version) => write!(
+                f,
+                "save file has version {version} but version {SAVE_VERSION} is expected"
+            ),
+        }
+    }
+}
+
+impl SaveGame {
+    /// Captures the simulation state of `world`. Entities are renumbered in a stable order so
+    /// that saving the same world twice produces the same file.
+    pub fn capture(world: &mut World) -> SaveGame {
+        let mut trees = world
+            .query::<(
+                Entity,
+                &Transform,
+                &TextureAtlasSprite,
+                &Tree,
+                Option<&Sapling>,
+                Option<&Stump>,
+            )>()
+            .iter(world)
+            .map(
+                |(entity, transform, sprite, tree, sapling, stump)| SavedTree {
+                    entity,
+                    pos: transform.translation.truncate(),
+                    index: sprite.index,
+                    tree: tree.clone(),
+                    sapling: sapling.cloned(),
+                    stump: stump.cloned(),
+                },
+            )
+            .collect::<Vec<_>>();
+        let mut buildings = world
+            .query::<(
+                Entity,
+                &Transform,
+                &BuildingKind,
+                Option<&Team>,
+                Option<&Researching>,
+                Option<&Armory>,
+            )>()
+            .iter(world)
+            .map(
+                |(entity, transform, kind, team, research, armory)| SavedBuilding {
+                    entity,
+                    pos: transform.translation.truncate(),
+                    kind: *kind,
+                    team: team.copied().unwrap_or_default(),
+                    research: research.cloned(),
+                    armory: armory.cloned(),
+                },
+            )
+            .collect::<Vec<_>>();
+        let mut units = world
+            .query::<(
+                Entity,
+                &Transform,
+                &Unit,
+                Option<&Team>,
+                Option<&OrderQueue>,
+                Option<&Lumberjack>,
+                Option<&Soldier>,
+                Option<&Stance>,
+                Option<&Veterancy>,
+                Option<&Morale>,
+            )>()
+            .iter(world)
+            .filter_map(
+                |(
+                    entity,
+                    transform,
+                    unit,
+                    team,
+                    orders,
+                    lumberjack,
+                    soldier,
+                    stance,
+                    veterancy,
+                    morale,
+                )| {
+                    let role = match (lumberjack, soldier) {
+                        (Some(lumberjack), _) => SavedRole::Lumberjack(lumberjack.clone()),
+                        (_, Some(soldier)) => SavedRole::Soldier(
+                            soldier.clone(),
+                            stance.copied().unwrap_or_default(),
+                            veterancy.cloned().unwrap_or_default(),
+                            morale.cloned().unwrap_or_default(),
+                        ),
+                        _ => return None,
+                    };
+                    Some(SavedUnit {
+                        entity,
+                        pos: transform.translation.truncate(),
+                        unit: unit.clone(),
+                        team: team.copied().unwrap_or_default(),
+                        orders: orders.cloned().unwrap_or_default(),
+                        role,
+                    })
+                },
+            )
+            .collect::<Vec<_>>();
+
+        trees.sort_by(|a, b| compare_pos(a.pos, b.pos));
+        buildings.sort_by(|a, b| compare_pos(a.pos, b.pos));
+        units.sort_by(|a, b| compare_pos(a.pos, b.pos));
+
+        let mut entity_map = EntityMap::default();
+        let saved_entities = trees
+            .iter_mut()
+            .map(|t| &mut t.entity)
+            .chain(buildings.iter_mut().map(|b| &mut b.entity))
+            .chain(units.iter_mut().map(|u| &mut u.entity));
+        for (index, entity) in saved_entities.enumerate() {
+            let id = Entity::from_raw(index as u32);
+            entity_map.insert(*entity, id);
+            *entity = id;
+        }
+        for unit in units.iter_mut() {
+            unit.role.map_entities(&entity_map);
+            let _ = unit.orders.map_entities(&entity_map);
+        }
+
+        let camera = world
+            .query_filtered::<(&Transform, &OrthographicProjection), With<Camera2d>>()
+            .get_single(world)
+            .map_or(
+                SavedCamera {
+                    pos: Vec2::ZERO,
+                    scale: 1.0,
+                },
+                |(transform, projection)| SavedCamera {
+                    pos: transform.translation.truncate(),
+                    scale: projection.scale,
+                },
+            );
+        let stats = world.get_resource::<Stats>().cloned().unwrap_or_default();
+        let research = world
+            .get_resource::<ResearchState>()
+            .cloned()
+            .unwrap_or_default();
+        let scenario = world
+            .get_resource::<Scenario>()
+            .cloned()
+            .unwrap_or_default();
+        let scenario_state = world
+            .get_resource::<ScenarioState>()
+            .cloned()
+            .unwrap_or_default();
+        let registry = world.resource::<ChunkRegistry>();
+        let mut loaded_chunks = registry.loaded().collect::<Vec<_>>();
+        let mut unloaded_chunks = registry
+            .unloaded()
+            .map(|(chunk, trees)| (chunk, trees.clone()))
+            .collect::<Vec<_>>();
+        loaded_chunks.sort_by_key(|chunk| (chunk.x, chunk.y));
+        unloaded_chunks.sort_by_key(|(chunk, _)| (chunk.x, chunk.y));
+
+        SaveGame {
+            version: SAVE_VERSION,
+            seed: *world.resource::<WorldSeed>(),
+            size: *world.resource::<WorldSize>(),
+            tick: *world.resource::<SimulationTick>(),
+            stats,
+            research,
+            scenario,
+            scenario_state,
+            camera,
+            loaded_chunks,
+            unloaded_chunks,
+            trees,
+            buildings,
+            units,
+        }
+    }
+
+    /// Replaces all units, trees and buildings of `world` with the saved ones.
+    pub fn restore(self, world: &mut World) -> Result<(), SaveError> {
+        if self.version != SAVE_VERSION {
+            return Err(SaveError::Version(self.version));
+        }
+
+        let old = world
+            .query_filtered::<Entity, Or<(With<Unit>, With<Tree>, With<BuildingKind>)>>()
+            .iter(world)
+            .collect::<Vec<_>>();
+        for entity in old {
+            despawn_with_children_recursive(world, entity);
+        }
+        world.insert_resource(UnitQuadTree::default());
+        // the groups hold the despawned units, and the ids in a save file can be from another run
+        if world.contains_resource::<ControlGroups>() {
+            world.insert_resource(ControlGroups::default());
+        }
+        world.insert_resource(self.stats);
+        world.insert_resource(self.research);
+        world.insert_resource(self.scenario);
+        world.insert_resource(self.scenario_state);
+        if let Ok((mut transform, mut projection)) = world
+            .query_filtered::<(&mut Transform, &mut OrthographicProjection), With<Camera2d>>()
+            .get_single_mut(world)
+        {
+            transform.translation = self.camera.pos.extend(transform.translation.z);
+            projection.scale = self.camera.scale;
+        }
+
+        // children go with their parent
+        let ground = world
+            .query_filtered::<Entity, (With<Ground>, Without<Parent>)>()
+            .iter(world)
+            .collect::<Vec<_>>();
+        for entity in ground {
+            despawn_with_children_recursive(world, entity);
+        }
+        world.insert_resource(self.seed);
+        world.insert_resource(self.size);
+        world.insert_resource(self.tick);
+        let mut terrain_map = TerrainMap::new(self.size);
+        let mut registry = ChunkRegistry::with_unloaded(self.unloaded_chunks);
+
+        let mut system_state =
+            SystemState::<(Commands, Res<SpriteSheets>, Res<Definitions>)>::new(world);
+        let (mut commands, sprite_sheets, definitions) = system_state.get_mut(world);
+        let mut entity_map = EntityMap::default();
+
+        for chunk in self.loaded_chunks {
+            // trees come from the save file instead
+            load_chunk(
+                &mut commands,
+                &sprite_sheets,
+                &mut terrain_map,
+                &mut registry,
+                self.seed,
+                chunk,
+            );
+        }
+
+        for saved in self.trees {
+            let entity = spawn_tree(&mut commands, &sprite_sheets, saved.pos, saved.index);
+            commands.entity(entity).insert(saved.tree);
+            if let Some(sapling) = saved.sapling {
+                commands.entity(entity).insert(sapling);
+            }
+            if let Some(stump) = saved.stump {
+                commands.entity(entity).insert(stump);
+            }
+            entity_map.insert(saved.entity, entity);
+        }
+        for saved in self.buildings {
+            let entity = spawn_building(
+                &mut commands,
+                &sprite_sheets,
+                &definitions,
+                saved.kind,
+                saved.pos,
+                saved.team,
+            );
+            if let Some(research) = saved.research {
+                commands.entity(entity).insert(research);
+            }
+            if let Some(armory) = saved.armory {
+                commands.entity(entity).insert(armory);
+            }
+            entity_map.insert(saved.entity, entity);
+        }
+        let mut units = Vec::with_capacity(self.units.len());
+        for saved in self.units {
+            let entity = match saved.role {
+                SavedRole::Lumberjack(_) => spawn_lumberjack(
+                    &mut commands,
+                    &sprite_sheets,
+                    &definitions,
+                    saved.pos,
+                    saved.team,
+                ),
+                SavedRole::Soldier(..) => spawn_soldier(
+                    &mut commands,
+                    &sprite_sheets,
+                    &definitions,
+                    saved.pos,
+                    saved.team,
+                ),
+            };
+            entity_map.insert(saved.entity, entity);
+            units.push((entity, saved));
+        }
+        // all entities exist now, so references between them can be mapped
+        for (entity, mut saved) in units {
+            saved.role.map_entities(&entity_map);
+            let _ = saved.orders.map_entities(&entity_map);
+            let mut entity = commands.entity(entity);
+            entity.insert(saved.unit).insert(saved.orders);
+            match saved.role {
+                SavedRole::Lumberjack(lumberjack) => entity.insert(lumberjack),
+                SavedRole::Soldier(soldier, stance, veterancy, morale) => entity
+                    .insert(soldier)
+                    .insert(stance)
+                    .insert(veterancy)
+                    .insert(morale),
+            };
+        }
+
+        system_state.apply(world);
+        world.insert_resource(terrain_map);
+        world.insert_resource(registry);
+        Ok(())
+    }
+
+    /// Forgets what only matters to the player that saved, e.g. where they looked at.
+    pub fn clear_presentation(&mut self) {
+        self.camera = SavedCamera {
+            pos: Vec2::ZERO,
+            scale: 1.0,
+        };
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), SaveError> {
+        let source = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(SaveError::Serialize)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(SaveError::Io)?;
+        }
+        fs::write(path, source).map_err(SaveError::Io)
+    }
+
+    pub fn read(path: &Path) -> Result<SaveGame, SaveError> {
+        let source = fs::read_to_string(path).map_err(SaveError::Io)?;
+        ron::from_str(&source).map_err(SaveError::Parse)
+    }
+}
+
+impl SavedRole {
+    fn map_entities(&mut self, entity_map: &EntityMap) {
+        // unknown targets reset the action instead of failing, see the `MapEntities` impls
+        let _ = match self {
+            SavedRole::Lumberjack(lumberjack) => lumberjack.map_entities(entity_map),
+            SavedRole::Soldier(soldier, ..) => soldier.map_entities(entity_map),
+        };
+    }
+}
+
+fn compare_pos(a: Vec2, b: Vec2) -> std::cmp::Ordering {
+    a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y))
+}
+
+pub(crate) fn quick_save_input(
+    actions: Res<Input<InputAction>>,
+    mut save_events: EventWriter<SaveGameEvent>,
+    mut load_events: EventWriter<LoadGameEvent>,
+) {
+    if actions.just_pressed(InputAction::QuickSave) {
+        save_events.send(SaveGameEvent(QUICK_SAVE_PATH.into()));
+    }
+    if actions.just_pressed(InputAction::QuickLoad) {
+        load_events.send(LoadGameEvent(QUICK_SAVE_PATH.into()));
+    }
+}
+
+fn save_game(world: &mut World, events: &mut SystemState<EventReader<SaveGameEvent>>) {
+    let paths = events
+        .get_mut(world)
+        .iter()
+        .map(|e| e.0.clone())
+        .collect::<Vec<_>>();
+    for path in paths {
+        match SaveGame::capture(world).write(&path) {
+            Ok(()) => info!("saved game to {}", path.display()),
+            Err(e) => error!("{e}"),
+        }
+    }
+}
+
+fn load_game(world: &mut World, events: &mut SystemState<EventReader<LoadGameEvent>>) {
+    let paths = events
+        .get_mut(world)
+        .iter()
+        .map(|e| e.0.clone())
+        .collect::<Vec<_>>();
+    for path in paths {
+        if world.contains_resource::<ReplayPlayer>() {
+            warn!("can't load a game while watching a replay");
+            continue;
+        }
+        if world.contains_resource::<LockstepSession>() {
+            warn!("can't load a game in a multiplayer match");
+            continue;
+        }
+        match SaveGame::read(&path).and_then(|save_game| save_game.restore(world)) {
+            Ok(()) => info!("loaded game from {}", path.display()),
+            Err(e) => {
+                error!("{e}");
+                continue;
+            }
+        }
+        // a replay always starts from the seed, not from a save game
+        if world.remove_resource::<ReplayRecorder>().is_some() {
+            warn!("stopped recording the replay");
+        }
+    }
+}
@@ -0,0 +1,407 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use noisy_bevy::simplex_noise_2d;
+use serde::{Deserialize, Serialize};
+
+use crate::definition::{Cost, Definitions};
+use crate::forest::{stump_near, Stump};
+use crate::health::{apply_damage, UnitDeathEvent};
+use crate::lumberjack::spawn_lumberjack;
+use crate::simulation::{tick_seconds, SimulationSet, SimulationTick};
+use crate::soldier::spawn_soldier;
+use crate::unit::{Team, Unit, UnitKind};
+use crate::util::{parse_arg, ArgError};
+use crate::{spawn_building, BuildingKind, SpriteSheets, Stats, UnitQuadTree};
+
+/// Sets up the match from the `Scenario` resource and runs its triggers. Without the resource
+/// the world starts empty, e.g. in tests that spawn what they need.
+pub struct ScenarioPlugin;
+
+impl Plugin for ScenarioPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ScenarioState>()
+            .add_startup_system(start_scenario.run_if(resource_exists::<Scenario>()))
+            .add_system(
+                scenario_triggers
+                    .run_if(resource_exists::<Scenario>())
+                    .after(apply_damage)
+                    .in_set(SimulationSet::Effects)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            );
+    }
+}
+
+/// What a match starts with and the triggers that script it, read from a `.scenario.ron`
+/// file. Every player of a match needs the same scenario, so it is part of the lobby and the
+/// replay.
+#[derive(Resource, Default, Clone, Debug, Serialize, Deserialize)]
+pub struct Scenario {
+    pub name: String,
+    // added to what every player already has
+    #[serde(default)]
+    pub resources: Cost,
+    #[serde(default)]
+    pub buildings: Vec<BuildingGroup>,
+    #[serde(default)]
+    pub units: Vec<UnitGroup>,
+    #[serde(default)]
+    pub triggers: Vec<Trigger>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UnitGroup {
+    pub kind: UnitKind,
+    #[serde(default)]
+    pub team: Team,
+    pub at: Placement,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BuildingGroup {
+    pub kind: BuildingKind,
+    #[serde(default)]
+    pub team: Team,
+    pub at: Placement,
+}
+
+/// Where the units or buildings of a group go.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Placement {
+    At(Vec2),
+    // `columns` times `rows` positions `spacing` apart, the first one at `from`. Noise moves
+    // them up to `scatter` pixels so they don't stand in lines.
+    Grid {
+        from: Vec2,
+        columns: u32,
+        rows: u32,
+        spacing: f32,
+        #[serde(default)]
+        scatter: f32,
+    },
+}
+
+// the y of the scatter samples the noise this far from the x, so the two don't match
+const SCATTER_NOISE_OFFSET: Vec2 = Vec2::new(317.0, -211.0);
+
+impl Placement {
+    pub fn positions(&self) -> Vec<Vec2> {
+        match *self {
+            Placement::At(pos) => vec![pos],
+            Placement::Grid {
+                from,
+                columns,
+                rows,
+                spacing,
+                scatter,
+            } => (0..columns)
+                .flat_map(|x| (0..rows).map(move |y| Vec2::new(x as f32, y as f32)))
+                .map(|cell| {
+                    let pos = from + cell * spacing;
+                    let noise = Vec2::new(
+                        simplex_noise_2d(pos),
+                        simplex_noise_2d(pos + SCATTER_NOISE_OFFSET),
+                    );
+                    pos + noise * scatter
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Runs its actions once, the first tick its condition holds.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Trigger {
+    pub when: Condition,
+    pub then: Vec<TriggerAction>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Condition {
+    // a unit of `team` is within `radius` of `center`
+    AreaEntered {
+        team: Team,
+        center: Vec2,
+        radius: f32,
+    },
+    // one of the players has at least this much
+    ResourcesReached(Cost),
+    // `count` units of `team` died since the match started, of `kind` if it is given
+    UnitsKilled {
+        team: Team,
+        #[serde(default)]
+        kind: Option<UnitKind>,
+        count: u32,
+    },
+    // seconds since the match started
+    Timer(f32),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum TriggerAction {
+    Spawn(UnitGroup),
+    Build(BuildingGroup),
+    Message(String),
+    // the team wins and everyone else loses
+    Win(Team),
+    Lose(Team),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Outcome {
+    Won,
+    Lost,
+}
+
+/// How far the match got through its scenario, saved with the game.
+#[derive(Resource, Default, Clone, Debug, Serialize, Deserialize)]
+pub struct ScenarioState {
+    // indices of the triggers that already ran
+    fired: Vec<usize>,
+    kills: Vec<Kills>,
+    // with the tick they were shown at
+    messages: Vec<(u64, String)>,
+    // the first outcome of a team stays
+    outcomes: BTreeMap<Team, Outcome>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Kills {
+    team: Team,
+    kind: UnitKind,
+    count: u32,
+}
+
+impl ScenarioState {
+    /// Whether `team` won or lost, a team loses when another one wins.
+    pub fn outcome(&self, team: Team) -> Option<Outcome> {
+        self.outcomes.get(&team).copied().or_else(|| {
+            self.outcomes
+                .values()
+                .any(|outcome| *outcome == Outcome::Won)
+                .then_some(Outcome::Lost)
+        })
+    }
+
+    pub fn messages(&self) -> &[(u64, String)] {
+        &self.messages
+    }
+
+    fn killed(&self, team: Team, kind: Option<UnitKind>) -> u32 {
+        self.kills
+            .iter()
+            .filter(|kills| kills.team == team && kind.is_none_or(|kind| kills.kind == kind))
+            .map(|kills| kills.count)
+            .sum()
+    }
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(std::io::Error),
+    Parse(ron::error::SpannedError),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Io(e) => write!(f, "could not read scenario file: {e}"),
+            ScenarioError::Parse(e) => write!(f, "could not parse scenario file: {e}"),
+        }
+    }
+}
+
+impl Scenario {
+    /// The scenario shipped with the game, it is part of the build, so a broken one is a bug.
+    pub fn builtin() -> Self {
+        ron::from_str(include_str!("../assets/scenarios/default.scenario.ron"))
+            .expect("valid default scenario")
+    }
+
+    pub fn read(path: &Path) -> Result<Scenario, ScenarioError> {
+        let source = fs::read_to_string(path).map_err(ScenarioError::Io)?;
+        ron::from_str(&source).map_err(ScenarioError::Parse)
+    }
+
+    /// Reads the scenario from `--scenario <path>`, the builtin one is used if it is missing.
+    pub fn from_args() -> Result<Self, ArgError> {
+        let Some(path) = parse_arg::<PathBuf>("--scenario")? else {
+            return Ok(Scenario::builtin());
+        };
+        let scenario = Scenario::read(&path).map_err(|e| ArgError::new("--scenario", e))?;
+        info!("playing scenario {}", scenario.name);
+        Ok(scenario)
+    }
+}
+
+fn spawn_group(
+    commands: &mut Commands,
+    sprite_sheets: &SpriteSheets,
+    definitions: &Definitions,
+    group: &UnitGroup,
+) {
+    for pos in group.at.positions() {
+        match group.kind {
+            UnitKind::Lumberjack => {
+                spawn_lumberjack(commands, sprite_sheets, definitions, pos, group.team)
+            }
+            UnitKind::Soldier => {
+                spawn_soldier(commands, sprite_sheets, definitions, pos, group.team)
+            }
+        };
+    }
+}
+
+// positions with a stump on them are skipped
+fn build_group(
+    commands: &mut Commands,
+    sprite_sheets: &SpriteSheets,
+    definitions: &Definitions,
+    group: &BuildingGroup,
+    stump_query: &Query<&Transform, With<Stump>>,
+) {
+    let size = definitions.building(group.kind).size;
+    for pos in group.at.positions() {
+        if stump_near(stump_query, pos, size) {
+            info!("a stump blocks the {:?} at {pos}", group.kind);
+            continue;
+        }
+        spawn_building(
+            commands,
+            sprite_sheets,
+            definitions,
+            group.kind,
+            pos,
+            group.team,
+        );
+    }
+}
+
+fn start_scenario(
+    mut commands: Commands,
+    scenario: Res<Scenario>,
+    mut stats: ResMut<Stats>,
+    sprite_sheets: Res<SpriteSheets>,
+    definitions: Res<Definitions>,
+    stump_query: Query<&Transform, With<Stump>>,
+) {
+    stats.grant(scenario.resources);
+    // the teams of the scenario are known from the start, e.g. for `ResourcesReached`
+    let teams = scenario
+        .buildings
+        .iter()
+        .map(|group| group.team)
+        .chain(scenario.units.iter().map(|group| group.team));
+    for team in teams.chain([Team::PLAYER]) {
+        stats.team_mut(team);
+    }
+    for group in scenario.buildings.iter() {
+        build_group(
+            &mut commands,
+            &sprite_sheets,
+            &definitions,
+            group,
+            &stump_query,
+        );
+    }
+    for group in scenario.units.iter() {
+        spawn_group(&mut commands, &sprite_sheets, &definitions, group);
+    }
+}
+
+// the triggers run in the order of the file, so every machine sees the same outcome
+#[allow(clippy::too_many_arguments)]
+fn scenario_triggers(
+    mut commands: Commands,
+    mut death_events: EventReader<UnitDeathEvent>,
+    unit_query: Query<(&Transform, &Team, &UnitKind), With<Unit>>,
+    scenario: Res<Scenario>,
+    mut state: ResMut<ScenarioState>,
+    stats: Res<Stats>,
+    tick: Res<SimulationTick>,
+    unit_quad_tree: Res<UnitQuadTree>,
+    sprite_sheets: Res<SpriteSheets>,
+    definitions: Res<Definitions>,
+    stump_query: Query<&Transform, With<Stump>>,
+) {
+    for UnitDeathEvent { unit, .. } in death_events.iter() {
+        // the dead unit is despawned at the end of the tick, so it is still there
+        let Ok((_, team, kind)) = unit_query.get(*unit) else {
+            continue;
+        };
+        match state
+            .kills
+            .iter_mut()
+            .find(|kills| kills.team == *team && kills.kind == *kind)
+        {
+            Some(kills) => kills.count += 1,
+            None => state.kills.push(Kills {
+                team: *team,
+                kind: *kind,
+                count: 1,
+            }),
+        }
+    }
+    // deaths arrive in the order units struck, which differs between machines
+    state.kills.sort_by_key(|kills| (kills.team.0, kills.kind));
+
+    // the match is over for everyone once a team won or lost
+    if !state.outcomes.is_empty() {
+        return;
+    }
+    for (index, trigger) in scenario.triggers.iter().enumerate() {
+        if state.fired.contains(&index) {
+            continue;
+        }
+        let holds = match &trigger.when {
+            Condition::AreaEntered {
+                team,
+                center,
+                radius,
+            } => unit_quad_tree.nearby(*center, *radius).any(|entity| {
+                unit_query
+                    .get(entity)
+                    .is_ok_and(|(transform, unit_team, _)| {
+                        unit_team == team
+                            && transform.translation.truncate().distance(*center) <= *radius
+                    })
+            }),
+            Condition::ResourcesReached(cost) => stats
+                .teams()
+                .any(|(_, resources)| cost.affordable(&resources)),
+            Condition::UnitsKilled { team, kind, count } => state.killed(*team, *kind) >= *count,
+            Condition::Timer(seconds) => **tick as f32 * tick_seconds() >= *seconds,
+        };
+        if !holds {
+            continue;
+        }
+        state.fired.push(index);
+        for action in trigger.then.iter() {
+            match action {
+                TriggerAction::Spawn(group) => {
+                    spawn_group(&mut commands, &sprite_sheets, &definitions, group)
+                }
+                TriggerAction::Build(group) => build_group(
+                    &mut commands,
+                    &sprite_sheets,
+                    &definitions,
+                    group,
+                    &stump_query,
+                ),
+                TriggerAction::Message(message) => {
+                    info!("{message}");
+                    state.messages.push((**tick, message.clone()));
+                }
+                TriggerAction::Win(team) => {
+                    state.outcomes.entry(*team).or_insert(Outcome::Won);
+                }
+                TriggerAction::Lose(team) => {
+                    state.outcomes.entry(*team).or_insert(Outcome::Lost);
+                }
+            }
+        }
+    }
+}
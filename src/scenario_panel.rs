@@ -0,0 +1,92 @@
+use bevy::prelude::*;
+
+use crate::scenario::{Outcome, ScenarioState};
+use crate::simulation::{seconds_to_ticks, LocalTeam, SimulationTick};
+
+pub struct ScenarioPanelPlugin;
+
+impl Plugin for ScenarioPanelPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(setup_scenario_panel)
+            .add_system(scenario_panel_text);
+    }
+}
+
+// seconds a trigger message stays on screen
+const MESSAGE_TIME: f32 = 8.0;
+
+#[derive(Component)]
+struct ScenarioText;
+
+fn setup_scenario_panel(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                // centered at the top of the screen, above the world but out of the way
+                size: Size::width(Val::Percent(100.0)),
+                position: UiRect {
+                    top: Val::Px(16.0),
+                    ..default()
+                },
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            ..default()
+        })
+        .insert(Name::new("Scenario Panel"))
+        .with_children(|parent| {
+            parent
+                .spawn(TextBundle {
+                    background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                    style: Style {
+                        padding: UiRect::all(Val::Px(12.)),
+                        ..default()
+                    },
+                    text: Text::from_section(
+                        "",
+                        TextStyle {
+                            font: asset_server.load("fonts/roboto_regular.ttf"),
+                            font_size: 24.0,
+                            color: Color::WHITE,
+                        },
+                    ),
+                    visibility: Visibility::Hidden,
+                    ..default()
+                })
+                .insert(ScenarioText);
+        });
+}
+
+// the outcome stays, messages fade after `MESSAGE_TIME`
+fn scenario_panel_text(
+    mut query: Query<(&mut Text, &mut Visibility), With<ScenarioText>>,
+    state: Res<ScenarioState>,
+    tick: Res<SimulationTick>,
+    team: Res<LocalTeam>,
+) {
+    let label = match state.outcome(**team) {
+        Some(Outcome::Won) => Some("Victory!".to_string()),
+        Some(Outcome::Lost) => Some("Defeat".to_string()),
+        None => state
+            .messages()
+            .last()
+            .filter(|(shown, _)| **tick < shown + seconds_to_ticks(MESSAGE_TIME))
+            .map(|(_, message)| message.clone()),
+    };
+    for (mut text, mut visibility) in query.iter_mut() {
+        let new_visibility = if label.is_some() {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+        if *visibility != new_visibility {
+            *visibility = new_visibility;
+        }
+        if let Some(label) = &label {
+            if text.sections[0].value != *label {
+                text.sections[0].value = label.clone();
+            }
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
+
+use bevy::ecs::entity::{EntityMap, MapEntities, MapEntitiesError};
+use bevy::ecs::event::Event;
+use bevy::ecs::schedule::ExecutorKind;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::command::CommandEvent;
+use crate::equipment::{CraftEvent, Equipment};
+use crate::research::ResearchEvent;
+use crate::save::SaveGame;
+use crate::soldier::SpawnSoldierEvent;
+use crate::unit::{Team, Unit};
+use crate::{BuildingKind, Tree};
+
+// simulation ticks per second, gameplay only advances in steps of `TICK`
+pub const TICK_RATE: u32 = 20;
+pub const TICK: Duration = Duration::from_millis(1000 / TICK_RATE as u64);
+
+/// The stages of one simulation tick in `CoreSchedule::FixedUpdate`, they run in this order.
+#[derive(SystemSet, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum SimulationSet {
+    /// the inputs of this tick are gathered from the player, a replay or the network
+    Receive,
+    /// orders and spawn requests from the players become part of the world
+    Input,
+    /// units decide what to do and do it
+    Actions,
+    /// damage, chopped trees and deposits caused by the actions
+    Effects,
+    /// steering, pushing apart and moving the units
+    Movement,
+}
+
+/// Number of simulation ticks since the match started.
+#[derive(Resource, Default, Clone, Copy, PartialEq, Eq, Deref, Debug, Serialize, Deserialize)]
+pub struct SimulationTick(pub u64);
+
+/// Seconds a simulation tick lasts, gameplay systems use this instead of `Time::delta`. Ticks
+/// follow the game clock, so pausing or speeding up `Time` does the same to every timer.
+pub fn tick_seconds() -> f32 {
+    TICK.as_secs_f32()
+}
+
+/// Converts a duration in seconds to whole ticks, at least one.
+pub fn seconds_to_ticks(seconds: f32) -> u64 {
+    ((seconds * TICK_RATE as f32).round() as u64).max(1)
+}
+
+pub trait SimulationAppExt {
+    /// Like `add_event`, but the events are kept for two ticks instead of two frames. A frame
+    /// can run no tick or several, so events between simulation systems would get lost
+    /// otherwise.
+    fn add_simulation_event<T: Event>(&mut self) -> &mut Self;
+}
+
+impl SimulationAppExt for App {
+    fn add_simulation_event<T: Event>(&mut self) -> &mut Self {
+        if !self.world.contains_resource::<Events<T>>() {
+            self.init_resource::<Events<T>>().add_system(
+                Events::<T>::update_system
+                    .before(assign_sim_ids)
+                    .in_set(SimulationSet::Receive)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            );
+        }
+        self
+    }
+}
+
+pub(crate) fn configure_simulation_schedule(app: &mut App) {
+    app.insert_resource(FixedTime::new(TICK))
+        .init_resource::<SimulationTick>()
+        .init_resource::<TickReady>()
+        .init_resource::<NextSimId>()
+        .init_resource::<InputSource>()
+        .init_resource::<LocalTeam>()
+        .init_resource::<LocalInputs>()
+        .init_resource::<TickInputs>()
+        .edit_schedule(CoreSchedule::FixedUpdate, |schedule| {
+            // systems of the same stage run in the order they were added on every machine
+            schedule
+                .set_executor_kind(ExecutorKind::SingleThreaded)
+                .configure_sets(
+                    (
+                        SimulationSet::Receive,
+                        SimulationSet::Input,
+                        SimulationSet::Actions,
+                        SimulationSet::Effects,
+                        SimulationSet::Movement,
+                    )
+                        .chain(),
+                );
+            for set in [
+                SimulationSet::Receive,
+                SimulationSet::Input,
+                SimulationSet::Actions,
+                SimulationSet::Effects,
+                SimulationSet::Movement,
+            ] {
+                schedule.configure_set(set.run_if(tick_ready));
+            }
+        })
+        .add_systems(
+            (assign_sim_ids, local_inputs)
+                .chain()
+                .in_set(SimulationSet::Receive)
+                .in_schedule(CoreSchedule::FixedUpdate),
+        )
+        .add_system(
+            apply_inputs
+                .after(local_inputs)
+                .in_set(SimulationSet::Receive)
+                .in_schedule(CoreSchedule::FixedUpdate),
+        )
+        .add_system(
+            tick_count
+                .after(SimulationSet::Movement)
+                .run_if(tick_ready)
+                .in_schedule(CoreSchedule::FixedUpdate),
+        );
+}
+
+/// Whether the tick that is due may run. Lockstep holds the simulation back until the inputs
+/// of every peer have arrived, the time of a held tick is lost.
+#[derive(Resource, Deref, DerefMut)]
+pub struct TickReady(pub bool);
+
+impl Default for TickReady {
+    fn default() -> Self {
+        TickReady(true)
+    }
+}
+
+// every system of a tick checks this, ungrouped systems in `FixedUpdate` have to as well
+pub fn tick_ready(ready: Res<TickReady>) -> bool {
+    **ready
+}
+
+fn tick_count(mut tick: ResMut<SimulationTick>) {
+    tick.0 += 1;
+}
+
+/// Everything a player can do to the match. The same inputs applied at the same ticks always
+/// lead to the same match, so they are all a replay or a peer needs.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum PlayerInput {
+    Command(CommandEvent),
+    SpawnSoldier(Vec2),
+    Research(String),
+    Craft(Equipment),
+}
+
+impl MapEntities for PlayerInput {
+    fn map_entities(&mut self, entity_map: &EntityMap) -> Result<(), MapEntitiesError> {
+        match self {
+            PlayerInput::Command(command) => command.map_entities(entity_map),
+            PlayerInput::SpawnSoldier(_) | PlayerInput::Research(_) | PlayerInput::Craft(_) => {
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The team of the local player, a lockstep session sets it from the lobby slot.
+#[derive(Resource, Default, Clone, Copy, Deref, Debug)]
+pub struct LocalTeam(pub Team);
+
+/// Inputs of the local player since the last tick, the presentation pushes them here instead
+/// of sending simulation events itself.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct LocalInputs(pub Vec<PlayerInput>);
+
+/// The inputs applied at the start of the current tick with the team of the player that sent
+/// them. The team comes from where an input arrived from, not from the input itself, so a
+/// player can't act for another.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct TickInputs(pub Vec<(Team, PlayerInput)>);
+
+/// Where the inputs of a tick come from. Local inputs are dropped unless they drive the match.
+#[derive(Resource, Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InputSource {
+    #[default]
+    Local,
+    // the local player only watches, see `ReplayPlayer`
+    Replay,
+    // the inputs of every peer, see `LockstepSession`
+    Lockstep,
+}
+
+/// A number for units, trees and barracks that is the same on every machine, unlike `Entity`
+/// which also counts the entities of the presentation. Inputs that leave the match refer to
+/// entities by it.
+#[derive(Component, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct SimId(pub u32);
+
+#[derive(Resource, Default)]
+struct NextSimId(u32);
+
+// entities spawned during the same tick are numbered by position, spawn order can differ.
+// Exclusive so the ids exist before the inputs of this tick refer to them.
+pub(crate) fn assign_sim_ids(world: &mut World) {
+    let mut new = world
+        .query_filtered::<(Entity, &Transform), (
+            Without<SimId>,
+            Or<(With<Unit>, With<Tree>, With<BuildingKind>)>,
+        )>()
+        .iter(world)
+        .map(|(entity, transform)| (entity, transform.translation))
+        .collect::<Vec<_>>();
+    new.sort_by(|(_, a), (_, b)| {
+        a.x.total_cmp(&b.x)
+            .then(a.y.total_cmp(&b.y))
+            .then(a.z.total_cmp(&b.z))
+    });
+    for (entity, _) in new {
+        let mut next = world.resource_mut::<NextSimId>();
+        let id = SimId(next.0);
+        next.0 += 1;
+        world.entity_mut(entity).insert(id);
+    }
+}
+
+/// Translates inputs between entities and `SimId`s. Ids are stored as `Entity::from_raw`, so
+/// `MapEntities` does the remapping.
+#[derive(SystemParam)]
+pub struct SimIds<'w, 's> {
+    query: Query<'w, 's, (Entity, &'static SimId)>,
+}
+
+impl<'w, 's> SimIds<'w, 's> {
+    /// The input as it leaves the match, `None` if it refers to entities without an id.
+    pub fn export(&self, input: &PlayerInput) -> Option<PlayerInput> {
+        let mut entity_map = EntityMap::default();
+        for (entity, id) in self.query.iter() {
+            entity_map.insert(entity, Entity::from_raw(id.0));
+        }
+        map_input(input, &entity_map)
+    }
+
+    /// An input from outside the match, `None` if its entities are gone.
+    pub fn import(&self, input: &PlayerInput) -> Option<PlayerInput> {
+        map_input(input, &self.import_map())
+    }
+
+    pub fn export_entity(&self, entity: Entity) -> Option<Entity> {
+        let (_, id) = self.query.get(entity).ok()?;
+        Some(Entity::from_raw(id.0))
+    }
+
+    /// The entities that still exist.
+    pub fn import_entities(&self, ids: &[Entity]) -> Vec<Entity> {
+        let entity_map = self.import_map();
+        ids.iter()
+            .filter_map(|id| entity_map.get(*id).ok())
+            .collect()
+    }
+
+    fn import_map(&self) -> EntityMap {
+        let mut entity_map = EntityMap::default();
+        for (entity, id) in self.query.iter() {
+            entity_map.insert(Entity::from_raw(id.0), entity);
+        }
+        entity_map
+    }
+}
+
+fn map_input(input: &PlayerInput, entity_map: &EntityMap) -> Option<PlayerInput> {
+    let mut input = input.clone();
+    input.map_entities(entity_map).ok()?;
+    Some(input)
+}
+
+pub(crate) fn local_inputs(
+    source: Res<InputSource>,
+    team: Res<LocalTeam>,
+    mut local: ResMut<LocalInputs>,
+    mut inputs: ResMut<TickInputs>,
+) {
+    if *source == InputSource::Local {
+        inputs.extend(local.drain(..).map(|input| (**team, input)));
+    } else {
+        local.clear();
+    }
+}
+
+pub(crate) fn apply_inputs(
+    mut inputs: ResMut<TickInputs>,
+    mut command_events: EventWriter<CommandEvent>,
+    mut spawn_soldier_events: EventWriter<SpawnSoldierEvent>,
+    mut research_events: EventWriter<ResearchEvent>,
+    mut craft_events: EventWriter<CraftEvent>,
+) {
+    for (team, input) in inputs.drain(..) {
+        match input {
+            PlayerInput::Command(command) => command_events.send(CommandEvent { team, ..command }),
+            PlayerInput::SpawnSoldier(pos) => {
+                spawn_soldier_events.send(SpawnSoldierEvent(pos, team))
+            }
+            PlayerInput::Research(id) => research_events.send(ResearchEvent(id, team)),
+            PlayerInput::Craft(equipment) => craft_events.send(CraftEvent(equipment, team)),
+        }
+    }
+}
+
+/// A checksum of the simulation state. Equal worlds have equal hashes on every machine, the
+/// camera and other presentation state are left out.
+pub fn state_hash(world: &mut World) -> u64 {
+    let mut save_game = SaveGame::capture(world);
+    save_game.clear_presentation();
+    let source = ron::to_string(&save_game).expect("a serializable save game");
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    hasher.finish()
+}
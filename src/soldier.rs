@@ -1,29 +1,48 @@
-use bevy::{prelude::*};
+use bevy::ecs::entity::{EntityMap, MapEntities, MapEntitiesError};
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    unit::{SelectedMark, SelectionBox, Unit},
-    Cull2D, Cursor, SpriteSheets, YSort,
+    command::{Command, OrderQueue},
+    definition::{Definitions, RankDefinition},
+    equipment::{Armory, Equipment},
+    health::{spawn_health_bar, DamageEvent},
+    morale::Morale,
+    simulation::{tick_seconds, SimId, SimulationAppExt, SimulationSet, SimulationTick},
+    unit::{SelectionBox, Team, Unit, UnitKind},
+    veterancy::{spawn_rank_insignia, Veterancy},
+    Barrack, BuildingKind, Cull2D, SpriteSheets, WorldSeed, YSort,
 };
 pub struct SoldierPlugin;
 
 impl Plugin for SoldierPlugin {
     fn build(&self, app: &mut App) {
-        app.add_event::<SpawnSoldierEvent>()
-            .add_system(soldier_spawn)
-            .add_system(move_to_position_action)
-            .add_system(next_action);
+        app.add_simulation_event::<SpawnSoldierEvent>()
+            .add_system(
+                soldier_spawn
+                    .in_set(SimulationSet::Input)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            )
+            .add_systems(
+                (soldier_orders, next_action)
+                    .chain()
+                    .in_set(SimulationSet::Actions)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            );
     }
 }
 
-#[derive(Component, Default)]
+#[derive(Component, Default, Clone, Serialize, Deserialize)]
 pub struct Soldier {
     action: SoldierAction,
     weapon_timeout: f32,
     weapon: Option<Weapon>,
     armor: Option<Armor>,
+    animation_timer: f32,
 }
 
-#[derive(Default, PartialEq)]
+#[derive(Default, Clone, PartialEq, Serialize, Deserialize)]
 pub enum SoldierAction {
     #[default]
     Idle,
@@ -32,8 +51,42 @@ pub enum SoldierAction {
         attack_move: bool,
     },
     Attack(Entity),
+    // strikes whatever comes in range but never moves
+    HoldPosition,
+    // walks the points in order and starts over at the first one, striking enemies in range
+    Patrol {
+        points: Vec<Vec2>,
+        next: usize,
+    },
+    // walks to an armory that has better gear in stock, see `soldier_pick_up`
+    Equip(Entity),
+    // broken by low morale, flees to a friendly barrack and doesn't fight, see `Morale`
+    Rout(Option<Entity>),
+}
+
+/// What an idle soldier does about enemies.
+#[derive(Component, Default, Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum Stance {
+    // chases enemies in sight
+    #[default]
+    Aggressive,
+    // strikes enemies in range without moving
+    Defensive,
+    // ignores enemies, even when attacked
+    Passive,
+}
+
+impl Stance {
+    pub fn next(self) -> Stance {
+        match self {
+            Stance::Aggressive => Stance::Defensive,
+            Stance::Defensive => Stance::Passive,
+            Stance::Passive => Stance::Aggressive,
+        }
+    }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 pub enum Weapon {
     Sword,
     Axe,
@@ -43,101 +96,440 @@ pub enum Weapon {
     Crossbow,
 }
 
+impl Weapon {
+    pub const ALL: [Weapon; 6] = [
+        Weapon::Sword,
+        Weapon::Axe,
+        Weapon::Spear,
+        Weapon::Bow,
+        Weapon::Sling,
+        Weapon::Crossbow,
+    ];
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 pub enum Armor {
     Leather,
     Chain,
     Plate,
 }
 
-pub struct SpawnSoldierEvent(pub Vec2);
+impl Armor {
+    pub const ALL: [Armor; 3] = [Armor::Leather, Armor::Chain, Armor::Plate];
+}
+
+/// The stats of a soldier that follow from the weapon and armor it carries and its rank.
+#[derive(Clone, Copy, Debug)]
+pub struct Loadout {
+    pub damage: f32,
+    pub range: f32,
+    // seconds between two strikes
+    pub cooldown: f32,
+    // chance that a strike hits
+    pub accuracy: f32,
+    // multiplies the speed of the unit
+    pub speed: f32,
+    // share of the damage that is absorbed
+    pub protection: f32,
+}
+
+impl Loadout {
+    pub fn promoted(self, rank: Option<&RankDefinition>) -> Loadout {
+        let Some(rank) = rank else {
+            return self;
+        };
+        Loadout {
+            accuracy: (self.accuracy + rank.accuracy).min(1.0),
+            cooldown: self.cooldown / rank.attack_speed,
+            ..self
+        }
+    }
+}
+
+impl Soldier {
+    pub fn weapon(&self) -> Option<Weapon> {
+        self.weapon
+    }
+
+    pub fn armor(&self) -> Option<Armor> {
+        self.armor
+    }
+
+    pub fn action(&self) -> &SoldierAction {
+        &self.action
+    }
+
+    // the piece it replaces is gone
+    pub fn equip(&mut self, equipment: Equipment) {
+        match equipment {
+            Equipment::Weapon(weapon) => self.weapon = Some(weapon),
+            Equipment::Armor(armor) => self.armor = Some(armor),
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.action = SoldierAction::Idle;
+    }
+
+    // without a barrack the soldier cowers where it is
+    pub fn rout(&mut self, barrack: Option<Entity>) {
+        self.action = SoldierAction::Rout(barrack);
+    }
+
+    pub fn is_routing(&self) -> bool {
+        matches!(self.action, SoldierAction::Rout(_))
+    }
+
+    pub fn loadout(&self, definitions: &Definitions) -> Loadout {
+        // soldiers without a weapon fight with a sword
+        let weapon = definitions.weapon(self.weapon.unwrap_or(Weapon::Sword));
+        let armor = self.armor.map(|armor| definitions.armor(armor));
+        Loadout {
+            damage: weapon.damage,
+            range: weapon.range,
+            cooldown: weapon.cooldown,
+            accuracy: weapon.accuracy,
+            speed: armor.map_or(1.0, |armor| armor.speed),
+            protection: armor.map_or(0.0, |armor| armor.protection),
+        }
+    }
+}
+
+// targets that are not part of the map (e.g. an already dead unit) reset the action
+impl MapEntities for Soldier {
+    fn map_entities(&mut self, entity_map: &EntityMap) -> Result<(), MapEntitiesError> {
+        match &mut self.action {
+            SoldierAction::Attack(target) | SoldierAction::Equip(target) => {
+                match entity_map.get(*target) {
+                    Ok(mapped) => *target = mapped,
+                    Err(_) => self.action = SoldierAction::Idle,
+                }
+            }
+            // a routing soldier keeps routing until it rallies
+            SoldierAction::Rout(barrack) => {
+                *barrack = barrack.and_then(|barrack| entity_map.get(barrack).ok());
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Spawns a soldier of the team.
+pub struct SpawnSoldierEvent(pub Vec2, pub Team);
 
 pub fn soldier_spawn(
     mut spawn_event: EventReader<SpawnSoldierEvent>,
     mut commands: Commands,
     sprite_sheets: Res<SpriteSheets>,
+    definitions: Res<Definitions>,
 ) {
-    for event in spawn_event.iter() {
-        commands
-            .spawn(SpriteSheetBundle {
-                texture_atlas: sprite_sheets.swordsman_red.clone(),
-                transform: Transform::from_translation(event.0.extend(1.0)),
-                ..default()
-            })
-            .insert(Name::new("Soldier"))
-            .insert(YSort)
-            .insert(Cull2D)
-            .insert(Unit::default())
-            .insert(Soldier::default())
-            .with_children(|builder| {
-                builder
-                    .spawn(SpriteSheetBundle {
-                        texture_atlas: sprite_sheets.box_selector.clone(),
-                        visibility: Visibility::Hidden,
-                        ..default()
-                    })
-                    .insert(SelectionBox);
-            });
-    }
-}
-
-pub fn move_to_position_action(
-    mut query: Query<(&Unit, &mut Soldier), With<SelectedMark>>,
-    input: Res<Input<MouseButton>>,
-    cursor: Res<Cursor>,
-) {
-    if input.just_pressed(MouseButton::Right) {
-        for (_, mut worker) in query.iter_mut() {
-            worker.action = SoldierAction::MoveToPosition {
-                target: **cursor,
-                attack_move: false,
+    for SpawnSoldierEvent(pos, team) in spawn_event.iter() {
+        spawn_soldier(&mut commands, &sprite_sheets, &definitions, *pos, *team);
+    }
+}
+
+pub fn spawn_soldier(
+    commands: &mut Commands,
+    sprite_sheets: &SpriteSheets,
+    definitions: &Definitions,
+    pos: Vec2,
+    team: Team,
+) -> Entity {
+    let definition = definitions.unit(UnitKind::Soldier);
+    commands
+        .spawn(SpriteSheetBundle {
+            texture_atlas: sprite_sheets.swordsman_red.clone(),
+            transform: Transform::from_translation(pos.extend(1.0)),
+            ..default()
+        })
+        .insert(Name::new("Soldier"))
+        .insert(YSort)
+        .insert(Cull2D)
+        .insert(Unit::new(definition))
+        .insert(UnitKind::Soldier)
+        .insert(team)
+        .insert(Soldier {
+            weapon: definition.weapon,
+            armor: definition.armor,
+            ..default()
+        })
+        .insert(Stance::default())
+        .insert(Veterancy::default())
+        .insert(Morale::default())
+        .insert(OrderQueue::default())
+        .with_children(|builder| {
+            builder
+                .spawn(SpriteSheetBundle {
+                    texture_atlas: sprite_sheets.box_selector.clone(),
+                    visibility: Visibility::Hidden,
+                    ..default()
+                })
+                .insert(SelectionBox);
+            spawn_health_bar(builder);
+            spawn_rank_insignia(builder);
+        })
+        .id()
+}
+
+pub fn soldier_orders(mut query: Query<(&mut OrderQueue, &mut Soldier, &Transform)>) {
+    for (mut orders, mut soldier, transform) in query.iter_mut() {
+        // broken soldiers don't listen, orders wait until they rallied
+        if soldier.is_routing() {
+            continue;
+        }
+        loop {
+            // patrols never end, queued patrol points add to the route instead
+            let extends_patrol = matches!(orders.current(), Some(Command::Patrol(_)))
+                && matches!(orders.peek(), Some(Command::Patrol(_)));
+            let idle = soldier.action == SoldierAction::Idle;
+            let Some(command) = orders.next(idle || extends_patrol) else {
+                break;
             };
+            match command {
+                Command::Move(target) => {
+                    soldier.action = SoldierAction::MoveToPosition {
+                        target,
+                        attack_move: false,
+                    }
+                }
+                Command::AttackMove(target) => {
+                    soldier.action = SoldierAction::MoveToPosition {
+                        target,
+                        attack_move: true,
+                    }
+                }
+                Command::Attack(target) => soldier.action = SoldierAction::Attack(target),
+                Command::Stop => soldier.action = SoldierAction::Idle,
+                Command::Hold => soldier.action = SoldierAction::HoldPosition,
+                Command::Patrol(target) => match &mut soldier.action {
+                    SoldierAction::Patrol { points, .. } if extends_patrol => points.push(target),
+                    _ => {
+                        soldier.action = SoldierAction::Patrol {
+                            points: vec![transform.translation.truncate(), target],
+                            next: 1,
+                        }
+                    }
+                },
+                // soldiers don't gather
+                _ => {}
+            }
         }
     }
 }
 
-pub fn next_action(mut query: Query<(&mut Soldier, &mut Unit, &Transform)>, _time: Res<Time>) {
-    for (mut soldier, mut unit, transform) in query.iter_mut() {
+// idle aggressive soldiers chase enemies this close
+const SIGHT_RANGE: f32 = 120.0;
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn next_action(
+    mut query: Query<(
+        Entity,
+        &SimId,
+        &mut Soldier,
+        &mut Unit,
+        &Transform,
+        &Team,
+        &Stance,
+        &Veterancy,
+    )>,
+    target_query: Query<(Entity, &Transform, &Team), With<Unit>>,
+    armory_query: Query<(Entity, &Transform, &Team, &Armory)>,
+    barrack_query: Query<&Transform, With<Barrack>>,
+    mut damage_events: EventWriter<DamageEvent>,
+    definitions: Res<Definitions>,
+    tick: Res<SimulationTick>,
+    seed: Res<WorldSeed>,
+) {
+    for (entity, id, mut soldier, mut unit, transform, team, stance, veterancy) in query.iter_mut()
+    {
+        soldier.weapon_timeout = (soldier.weapon_timeout - tick_seconds()).max(0.0);
+        soldier.animation_timer += tick_seconds();
         let pos = transform.translation.truncate();
-        match soldier.action {
+        let loadout = soldier
+            .loadout(&definitions)
+            .promoted(veterancy.rank(&definitions, UnitKind::Soldier));
+        let range = loadout.range;
+        let nearest_enemy = |radius: f32| {
+            target_query
+                .iter()
+                .filter(|(_, _, target_team)| *target_team != team)
+                .map(|(entity, target_transform, _)| {
+                    let distance = target_transform.translation.truncate().distance(pos);
+                    (entity, distance)
+                })
+                .filter(|(_, distance)| *distance <= radius)
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(entity, _)| entity)
+        };
+
+        let mut next = None;
+        let mut strike = None;
+        match &mut soldier.action {
             SoldierAction::Idle => {
                 unit.target_direction = Vec2::ZERO;
+                match stance {
+                    Stance::Aggressive => {
+                        next = nearest_enemy(SIGHT_RANGE).map(SoldierAction::Attack)
+                    }
+                    Stance::Defensive => strike = nearest_enemy(range),
+                    Stance::Passive => {}
+                }
+                if next.is_none() && strike.is_none() {
+                    next = armory_query
+                        .iter()
+                        .filter(|(_, _, armory_team, armory)| {
+                            *armory_team == team
+                                && (armory.better_weapon(soldier.weapon, &definitions).is_some()
+                                    || armory.better_armor(soldier.armor, &definitions).is_some())
+                        })
+                        .map(|(entity, armory_transform, _, _)| {
+                            (
+                                entity,
+                                armory_transform.translation.truncate().distance(pos),
+                            )
+                        })
+                        .min_by(|a, b| a.1.total_cmp(&b.1))
+                        .map(|(entity, _)| SoldierAction::Equip(entity));
+                }
             }
             SoldierAction::MoveToPosition {
                 target,
-                attack_move: _,
+                attack_move,
+            } => {
+                // on attack-move enemies in sight are fought first, then the walk goes on
+                let enemy = attack_move
+                    .then(|| nearest_enemy(SIGHT_RANGE))
+                    .flatten()
+                    .and_then(|enemy| target_query.get(enemy).ok());
+                match enemy {
+                    Some((enemy, enemy_transform, _)) => {
+                        let delta = enemy_transform.translation.truncate() - pos;
+                        if delta.length_squared() > range * range {
+                            unit.target_direction = delta;
+                        } else {
+                            unit.target_direction = Vec2::ZERO;
+                            strike = Some(enemy);
+                        }
+                    }
+                    None => {
+                        let delta = *target - pos;
+                        unit.target_direction = delta;
+                        if delta.length_squared() < 25.0 * 25.0 {
+                            next = Some(SoldierAction::Idle);
+                        }
+                    }
+                }
+            }
+            SoldierAction::Attack(target) => match target_query.get(*target) {
+                Ok((_, target_transform, _)) => {
+                    let delta = target_transform.translation.truncate() - pos;
+                    if delta.length_squared() > range * range {
+                        unit.target_direction = delta;
+                    } else {
+                        unit.target_direction = Vec2::ZERO;
+                        strike = Some(*target);
+                    }
+                }
+                // the target is dead
+                Err(_) => {
+                    unit.target_direction = Vec2::ZERO;
+                    next = Some(SoldierAction::Idle);
+                }
+            },
+            SoldierAction::HoldPosition => {
+                unit.target_direction = Vec2::ZERO;
+                strike = nearest_enemy(range);
+            }
+            SoldierAction::Patrol {
+                points,
+                next: index,
             } => {
-                let delta = target - pos;
+                let delta = points[*index] - pos;
                 unit.target_direction = delta;
-
                 if delta.length_squared() < 25.0 * 25.0 {
-                    soldier.action = SoldierAction::Idle;
+                    *index = (*index + 1) % points.len();
                 }
+                strike = nearest_enemy(range);
             }
-            _ => {
-                soldier.action = SoldierAction::Idle;
+            SoldierAction::Equip(armory) => match armory_query.get(*armory) {
+                // `soldier_pick_up` hands out the gear once it is close enough
+                Ok((_, armory_transform, _, _)) => {
+                    unit.target_direction = armory_transform.translation.truncate() - pos;
+                }
+                Err(_) => {
+                    unit.target_direction = Vec2::ZERO;
+                    next = Some(SoldierAction::Idle);
+                }
+            },
+            SoldierAction::Rout(barrack) => {
+                let reach = definitions.building(BuildingKind::Barrack).size;
+                let barrack_pos = barrack
+                    .and_then(|barrack| barrack_query.get(barrack).ok())
+                    .map(|barrack_transform| barrack_transform.translation.truncate());
+                unit.target_direction = match barrack_pos {
+                    Some(barrack_pos) if barrack_pos.distance(pos) > reach => barrack_pos - pos,
+                    _ => Vec2::ZERO,
+                };
+            }
+        }
+        // heavy armor slows the soldier down
+        unit.target_direction = unit.target_direction.clamp_length_max(loadout.speed);
+        if let Some(action) = next {
+            soldier.action = action;
+        }
+        if let Some(target) = strike {
+            if soldier.weapon_timeout <= 0.0 {
+                soldier.weapon_timeout = loadout.cooldown;
+                // a miss still takes the whole cooldown
+                if seed.unit_rng(**tick, *id).gen::<f32>() < loadout.accuracy {
+                    damage_events.send(DamageEvent {
+                        target,
+                        amount: loadout.damage,
+                        source: Some(entity),
+                    });
+                }
             }
         }
     }
 }
 
-pub fn calculate_damage(weapon: Weapon) -> f32 {
-    match weapon {
-        Weapon::Sword => 3.0,
-        Weapon::Axe => 4.0,
-        Weapon::Spear => 2.0,
-        Weapon::Bow => 2.0,
-        Weapon::Sling => 1.0,
-        Weapon::Crossbow => 3.0,
-    }
-}
+// the strike plays right after a strike, its frames come from the weapon
+const STRIKE_ANIMATION: f32 = 0.3;
 
-pub fn calculate_range(weapon: Weapon) -> f32 {
-    match weapon {
-        Weapon::Sword => 15.0,
-        Weapon::Axe => 15.0,
-        Weapon::Spear => 50.0,
-        Weapon::Bow => 1000.0,
-        Weapon::Sling => 500.0,
-        Weapon::Crossbow => 1500.0,
+pub fn soldier_animation(
+    mut query: Query<(&Unit, &Soldier, &Veterancy, &mut TextureAtlasSprite)>,
+    definitions: Res<Definitions>,
+) {
+    for (unit, soldier, veterancy, mut sprite) in query.iter_mut() {
+        let weapon = definitions.weapon(soldier.weapon.unwrap_or(Weapon::Sword));
+        let frame = (soldier.animation_timer * 8.0).round() as usize;
+        let direction = match unit.last_direction {
+            Vec2 { x, y } if x > y && -x > y => 0,    // down
+            Vec2 { x, y } if x > -y && -x > -y => 5,  // up
+            Vec2 { x, y } if x > y && x > -y => 10,   // right
+            Vec2 { x, y } if -x > y && -x > -y => 15, // left
+            _ => 0,                                   // no direction -> down
+        };
+        // the timeout starts at the cooldown of the rank, not the bare weapon's
+        let cooldown = soldier
+            .loadout(&definitions)
+            .promoted(veterancy.rank(&definitions, UnitKind::Soldier))
+            .cooldown;
+        let since_strike = cooldown - soldier.weapon_timeout;
+        sprite.index = if soldier.weapon_timeout > 0.0 && since_strike < STRIKE_ANIMATION {
+            let animation_frame = (since_strike / STRIKE_ANIMATION * 3.0).floor() as usize;
+            weapon.strike_frame + direction + animation_frame.min(2)
+        } else if unit.vel.length() > 20.0 {
+            direction + (frame % 4 + 1)
+        } else {
+            direction
+        };
+        let color = weapon
+            .tint
+            .map_or(Color::WHITE, |[r, g, b]| Color::rgb(r, g, b));
+        if sprite.color != color {
+            sprite.color = color;
+        }
     }
 }
@@ -0,0 +1,396 @@
+use bevy::prelude::*;
+
+use bevy::utils::HashMap;
+
+use crate::ground::{WorldSize, CHUNK_SIZE};
+
+pub const TILE_SIZE: f32 = 16.0;
+
+// ordered from low to high ground
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Terrain {
+    Water,
+    Shore,
+    Grass,
+    DeadGrass,
+    Cliff,
+    Snow,
+}
+
+/// The atlases the ground is drawn with, `SpriteSheets::ground` has their textures.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum GroundSheet {
+    Shore,
+    TexturedGrass,
+    DeadGrass,
+    Winter,
+    Cliff,
+    CliffWater,
+    GrassDeco,
+}
+
+impl Terrain {
+    pub fn from_height(height: f32) -> Terrain {
+        match height {
+            h if h < -0.25 => Terrain::Water,
+            h if h < -0.18 => Terrain::Shore,
+            h if h < 0.22 => Terrain::Grass,
+            h if h < 0.32 => Terrain::DeadGrass,
+            h if h < 0.45 => Terrain::Cliff,
+            _ => Terrain::Snow,
+        }
+    }
+
+    pub fn is_passable(self) -> bool {
+        !matches!(self, Terrain::Water | Terrain::Cliff)
+    }
+
+    pub fn speed(self) -> f32 {
+        match self {
+            Terrain::Shore => 0.5,
+            _ => 1.0,
+        }
+    }
+}
+
+/// Terrain of the loaded chunks, indexed by tile. Tile (0, 0) is centered on the world origin.
+#[derive(Resource, Default)]
+pub struct TerrainMap {
+    // tile bounds of a fixed size world, min inclusive and max exclusive
+    bounds: Option<(IVec2, IVec2)>,
+    chunks: HashMap<IVec2, Vec<Terrain>>,
+}
+
+impl TerrainMap {
+    pub fn new(size: WorldSize) -> TerrainMap {
+        let bounds = match size {
+            WorldSize::Fixed(size) => {
+                let min = -(size / 2).as_ivec2();
+                Some((min, min + size.as_ivec2()))
+            }
+            WorldSize::Infinite => None,
+        };
+        TerrainMap {
+            bounds,
+            chunks: HashMap::default(),
+        }
+    }
+
+    pub fn tile_to_pos(tile: IVec2) -> Vec2 {
+        tile.as_vec2() * TILE_SIZE
+    }
+
+    pub fn pos_to_tile(pos: Vec2) -> IVec2 {
+        (pos / TILE_SIZE).round().as_ivec2()
+    }
+
+    pub fn tile_to_chunk(tile: IVec2) -> IVec2 {
+        IVec2::new(tile.x.div_euclid(CHUNK_SIZE), tile.y.div_euclid(CHUNK_SIZE))
+    }
+
+    pub fn pos_to_chunk(pos: Vec2) -> IVec2 {
+        TerrainMap::tile_to_chunk(TerrainMap::pos_to_tile(pos))
+    }
+
+    // world position of the center of the first tile of `chunk`
+    pub fn chunk_origin(chunk: IVec2) -> Vec2 {
+        TerrainMap::tile_to_pos(chunk * CHUNK_SIZE)
+    }
+
+    pub fn in_bounds(&self, tile: IVec2) -> bool {
+        self.bounds
+            .is_none_or(|(min, max)| tile.cmpge(min).all() && tile.cmplt(max).all())
+    }
+
+    /// All chunks of a fixed size world, an infinite world has none up front.
+    pub fn chunks_in_bounds(&self) -> Vec<IVec2> {
+        let Some((min, max)) = self.bounds else {
+            return vec![];
+        };
+        let (min, max) = (
+            TerrainMap::tile_to_chunk(min),
+            TerrainMap::tile_to_chunk(max - IVec2::ONE),
+        );
+        (min.x..=max.x)
+            .flat_map(|x| (min.y..=max.y).map(move |y| IVec2::new(x, y)))
+            .collect()
+    }
+
+    // tiles outside of the world or in chunks that aren't loaded have no terrain
+    pub fn get(&self, tile: IVec2) -> Option<Terrain> {
+        if !self.in_bounds(tile) {
+            return None;
+        }
+        let chunk = TerrainMap::tile_to_chunk(tile);
+        let local = tile - chunk * CHUNK_SIZE;
+        self.chunks
+            .get(&chunk)
+            .map(|cells| cells[(local.y * CHUNK_SIZE + local.x) as usize])
+    }
+
+    pub fn terrain_at(&self, pos: Vec2) -> Option<Terrain> {
+        self.get(TerrainMap::pos_to_tile(pos))
+    }
+
+    // outside of the map counts as impassable
+    pub fn is_passable(&self, pos: Vec2) -> bool {
+        self.terrain_at(pos).is_some_and(Terrain::is_passable)
+    }
+
+    /// Scales a movement by the terrain speed and slides it along impassable terrain. Units
+    /// that already stand on impassable terrain may always move so they can walk out of it.
+    pub fn constrain_move(&self, pos: Vec2, delta: Vec2) -> Vec2 {
+        let delta = delta * self.terrain_at(pos).map_or(1.0, Terrain::speed);
+        if !self.is_passable(pos) {
+            return delta;
+        }
+        [delta, Vec2::new(delta.x, 0.0), Vec2::new(0.0, delta.y)]
+            .into_iter()
+            .find(|d| self.is_passable(pos + *d))
+            .unwrap_or(Vec2::ZERO)
+    }
+
+    /// Classifies the tiles of `chunk` and a margin around it by their height and smooths the
+    /// result so that each tile can be drawn with the autotile atlases. A tile only depends on
+    /// `height` around its position, so chunks can be generated in any order.
+    pub fn generate_chunk(&self, chunk: IVec2, height: impl Fn(Vec2) -> f32) -> TerrainGrid {
+        self.generate(
+            chunk * CHUNK_SIZE - IVec2::splat(CHUNK_MARGIN),
+            IVec2::splat(CHUNK_SIZE + 2 * CHUNK_MARGIN),
+            height,
+        )
+    }
+
+    fn generate(&self, min: IVec2, size: IVec2, height: impl Fn(Vec2) -> f32) -> TerrainGrid {
+        let mut grid = TerrainGrid {
+            min,
+            size,
+            cells: vec![None; (size.x * size.y) as usize],
+        };
+        for y in 0..size.y {
+            for x in 0..size.x {
+                let tile = min + IVec2::new(x, y);
+                if self.in_bounds(tile) {
+                    let terrain = Terrain::from_height(height(TerrainMap::tile_to_pos(tile)));
+                    grid.set(tile, terrain);
+                }
+            }
+        }
+        grid.erode(Terrain::Cliff, Terrain::DeadGrass);
+        grid.erode(Terrain::Grass, Terrain::Shore);
+        grid
+    }
+
+    pub fn insert_chunk(&mut self, chunk: IVec2, grid: &TerrainGrid) {
+        let start = chunk * CHUNK_SIZE;
+        let cells = (0..CHUNK_SIZE)
+            .flat_map(|y| (0..CHUNK_SIZE).map(move |x| start + IVec2::new(x, y)))
+            // tiles outside of a fixed size world are never looked up
+            .map(|tile| grid.get(tile).unwrap_or(Terrain::Water))
+            .collect();
+        self.chunks.insert(chunk, cells);
+    }
+
+    pub fn remove_chunk(&mut self, chunk: IVec2) {
+        self.chunks.remove(&chunk);
+    }
+}
+
+// a pass of erosion looks one tile further, so after this many passes a tile depends on the
+// heights up to this far away
+const ERODE_PASSES: i32 = 3;
+// tiles around a chunk that are generated with it, so that erosion and autotiling at the
+// chunk border see the same neighbours as the chunk next to it. Both erosions and the
+// autotiling reach this far.
+const CHUNK_MARGIN: i32 = 2 * ERODE_PASSES + 1;
+
+/// A rectangle of classified tiles, one chunk and its margin.
+#[derive(Clone)]
+pub struct TerrainGrid {
+    min: IVec2,
+    size: IVec2,
+    cells: Vec<Option<Terrain>>,
+}
+
+impl TerrainGrid {
+    // tiles outside of the grid or the world have no terrain
+    pub fn get(&self, tile: IVec2) -> Option<Terrain> {
+        let local = tile - self.min;
+        if local.cmplt(IVec2::ZERO).any() || local.cmpge(self.size).any() {
+            return None;
+        }
+        self.cells[(local.y * self.size.x + local.x) as usize]
+    }
+
+    fn set(&mut self, tile: IVec2, terrain: Terrain) {
+        let local = tile - self.min;
+        self.cells[(local.y * self.size.x + local.x) as usize] = Some(terrain);
+    }
+
+    // the autotile atlases can't draw strips that are only one tile wide, so those are lowered.
+    // Every pass looks at the tiles as the pass before left them, the fixed number of passes
+    // keeps the tiles at the border of the grid from reaching into the chunk.
+    fn erode(&mut self, level: Terrain, lower: Terrain) {
+        for _ in 0..ERODE_PASSES {
+            let before = self.clone();
+            for y in 0..self.size.y {
+                for x in 0..self.size.x {
+                    let tile = self.min + IVec2::new(x, y);
+                    let n = before.neighbours(tile, level);
+                    if before.get(tile).is_some_and(|t| t >= level)
+                        && ((!n.n && !n.s) || (!n.e && !n.w))
+                    {
+                        self.set(tile, lower);
+                    }
+                }
+            }
+        }
+    }
+
+    // which neighbours are at least at `level`, tiles without terrain count as matching
+    fn neighbours(&self, tile: IVec2, level: Terrain) -> Neighbours {
+        let at = |dx: i32, dy: i32| {
+            self.get(tile + IVec2::new(dx, dy))
+                .is_none_or(|t| t >= level)
+        };
+        Neighbours {
+            n: at(0, 1),
+            e: at(1, 0),
+            s: at(0, -1),
+            w: at(-1, 0),
+            ne: at(1, 1),
+            nw: at(-1, 1),
+            se: at(1, -1),
+            sw: at(-1, -1),
+        }
+    }
+
+    /// Sprites making up `tile` from bottom to top.
+    pub fn tile_sprites(&self, tile: IVec2, variant: usize) -> Vec<(GroundSheet, usize)> {
+        let terrain = self.get(tile).expect("a tile inside the grid");
+        let grass = (GroundSheet::TexturedGrass, grass_variant(variant));
+        match terrain {
+            Terrain::Water => vec![(GroundSheet::Shore, 4)],
+            Terrain::Shore => vec![(GroundSheet::Shore, 2)],
+            Terrain::Grass | Terrain::DeadGrass => {
+                match autotile(self.neighbours(tile, Terrain::Grass), 5, (3, 0)) {
+                    Some(index) => vec![(GroundSheet::CliffWater, index)],
+                    None if terrain == Terrain::DeadGrass => {
+                        vec![(GroundSheet::DeadGrass, grass_variant(variant))]
+                    }
+                    None => vec![grass],
+                }
+            }
+            Terrain::Cliff | Terrain::Snow => {
+                match autotile(self.neighbours(tile, Terrain::Cliff), 7, (3, 2)) {
+                    // cliff edges have transparent corners, so draw the ground below
+                    Some(index) => vec![grass, (GroundSheet::Cliff, index)],
+                    None if terrain == Terrain::Snow => vec![(GroundSheet::Winter, 3)],
+                    // top of the plateau
+                    None => vec![(GroundSheet::Cliff, 8)],
+                }
+            }
+        }
+    }
+}
+
+struct Neighbours {
+    n: bool,
+    e: bool,
+    s: bool,
+    w: bool,
+    ne: bool,
+    nw: bool,
+    se: bool,
+    sw: bool,
+}
+
+// mostly plain grass with the occasional decorated tile
+fn grass_variant(variant: usize) -> usize {
+    match variant % 16 {
+        0 => 1,
+        1 => 2,
+        2 => 4,
+        3 => 5,
+        v if v < 10 => 0,
+        _ => 3,
+    }
+}
+
+/// Picks the tile of a 13 tile autotile atlas: a 3x3 block of edges and outer corners in the
+/// top left and a 2x2 block of inner corners at `inner`. Returns `None` for the inside.
+fn autotile(n: Neighbours, columns: usize, inner: (usize, usize)) -> Option<usize> {
+    let (column, row) = match n {
+        Neighbours {
+            n: false, w: false, ..
+        } => (0, 0),
+        Neighbours {
+            n: false, e: false, ..
+        } => (2, 0),
+        Neighbours { n: false, .. } => (1, 0),
+        Neighbours {
+            s: false, w: false, ..
+        } => (0, 2),
+        Neighbours {
+            s: false, e: false, ..
+        } => (2, 2),
+        Neighbours { s: false, .. } => (1, 2),
+        Neighbours { w: false, .. } => (0, 1),
+        Neighbours { e: false, .. } => (2, 1),
+        Neighbours { nw: false, .. } => inner,
+        Neighbours { ne: false, .. } => (inner.0 + 1, inner.1),
+        Neighbours { sw: false, .. } => (inner.0, inner.1 + 1),
+        Neighbours { se: false, .. } => (inner.0 + 1, inner.1 + 1),
+        _ => return None,
+    };
+    Some(row * columns + column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::terrain_height;
+
+    // far rougher than the game's terrain, so that lots of strips erode at chunk borders
+    fn height(pos: Vec2) -> f32 {
+        terrain_height(pos * 8.0, Vec2::new(17.0, -3.0))
+    }
+
+    #[test]
+    fn adjacent_chunks_agree_on_shared_tiles() {
+        let map = TerrainMap::new(WorldSize::Infinite);
+        for x in -4..4 {
+            for y in -4..4 {
+                let chunk = IVec2::new(x, y);
+                let next = chunk + IVec2::X;
+                let grids = [chunk, next].map(|chunk| map.generate_chunk(chunk, height));
+                // the last column of `chunk` and the first one of `next`, each in both grids
+                for row in 0..CHUNK_SIZE {
+                    for column in [CHUNK_SIZE - 1, CHUNK_SIZE] {
+                        let tile = chunk * CHUNK_SIZE + IVec2::new(column, row);
+                        assert_eq!(grids[0].get(tile), grids[1].get(tile), "tile {tile}");
+                    }
+                }
+                // both chunks draw the same as one grid around both of them
+                let both = map.generate(
+                    chunk * CHUNK_SIZE - IVec2::splat(CHUNK_MARGIN),
+                    IVec2::new(2 * CHUNK_SIZE, CHUNK_SIZE) + 2 * CHUNK_MARGIN,
+                    height,
+                );
+                for (chunk, grid) in [chunk, next].into_iter().zip(grids.iter()) {
+                    for tile in (0..CHUNK_SIZE)
+                        .flat_map(|y| (0..CHUNK_SIZE).map(move |x| IVec2::new(x, y)))
+                        .map(|local| chunk * CHUNK_SIZE + local)
+                    {
+                        assert_eq!(grid.get(tile), both.get(tile), "tile {tile}");
+                        assert_eq!(
+                            grid.tile_sprites(tile, 0),
+                            both.tile_sprites(tile, 0),
+                            "tile {tile}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,114 +1,331 @@
-use crate::{selection_change, ApplySelectionEvent, UnitQuadTree};
+use crate::definition::{Definitions, UnitDefinition};
+use crate::presentation::{ApplySelectionEvent, SelectionMode};
+use crate::simulation::{tick_seconds, SimId, SimulationSet};
+use crate::terrain::TerrainMap;
+use crate::UnitQuadTree;
 use bevy::prelude::*;
-use quadtree_rs::{area::AreaBuilder, point::Point};
+use quadtree_rs::{area::AreaBuilder, point::Point, Quadtree};
+use serde::{Deserialize, Serialize};
 
 pub struct UnitPlugin;
 
 impl Plugin for UnitPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(unit_push_apart)
-            .add_system(unit_select)
-            .add_system(unit_vel)
-            .add_system(unit_move)
-            .add_system(unit_quad_tree_placement)
-            .add_system(selection_added)
-            .add_system(selection_removed.after(selection_change));
+        app.add_systems(
+            (
+                unit_move,
+                unit_push_apart,
+                unit_vel,
+                unit_quad_tree_removal,
+                unit_quad_tree_placement,
+            )
+                .chain()
+                .in_set(SimulationSet::Movement)
+                .in_schedule(CoreSchedule::FixedUpdate),
+        );
+        if cfg!(debug_assertions) {
+            app.add_system(
+                unit_quad_tree_check
+                    .after(unit_quad_tree_placement)
+                    .in_set(SimulationSet::Movement)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            );
+        }
     }
 }
 
-#[derive(Component, Default)]
+#[derive(Component, Default, Clone, Serialize, Deserialize)]
 pub struct Unit {
     pub vel: Vec2,
     pub target_direction: Vec2,
     pub last_direction: Vec2,
-    pub point: Option<(Point<u32>, u64)>,
     pub hp: f32,
+    pub max_hp: f32,
+}
+
+impl Unit {
+    pub fn new(definition: &UnitDefinition) -> Unit {
+        Unit {
+            hp: definition.hp,
+            max_hp: definition.hp,
+            ..default()
+        }
+    }
+}
+
+#[derive(
+    Component, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize,
+)]
+pub enum UnitKind {
+    Lumberjack,
+    Soldier,
+}
+
+// the player owning a unit or building
+#[derive(
+    Component,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Debug,
+    Serialize,
+    Deserialize,
+)]
+pub struct Team(pub u8);
+
+impl Team {
+    pub const PLAYER: Team = Team(0);
+
+    /// The team of the player in `slot` of a multiplayer lobby, the host is `PLAYER`.
+    pub fn of_slot(slot: u8) -> Team {
+        Team(slot)
+    }
 }
 
 #[derive(Component)]
 pub struct SelectionBox;
 
+// sparse, so selecting doesn't move units between tables and change the order the simulation
+// iterates them in
 #[derive(Component)]
+#[component(storage = "SparseSet")]
 pub struct SelectedMark;
 
+// a region has a tree of 2^depth cells a side
+const REGION_DEPTH: usize = 8;
+const REGION_CELLS: i32 = 1 << REGION_DEPTH;
+
+fn cell_to_region(cell: IVec2) -> IVec2 {
+    IVec2::new(
+        cell.x.div_euclid(REGION_CELLS),
+        cell.y.div_euclid(REGION_CELLS),
+    )
+}
+
+// the point of `cell` in the tree of its region
+fn region_point(cell: IVec2) -> Point<u32> {
+    let local = cell - cell_to_region(cell) * REGION_CELLS;
+    Point {
+        x: local.x as u32,
+        y: local.y as u32,
+    }
+}
+
+impl UnitQuadTree {
+    /// Moves the entry of `entity` to `cell`, the first call inserts it.
+    pub fn update(&mut self, entity: Entity, cell: IVec2) {
+        let current = self.entries.get(&entity).copied();
+        if matches!(current, Some((current_cell, _)) if current_cell == cell) {
+            return;
+        }
+        self.remove(entity);
+        let handle = self
+            .trees
+            .entry(cell_to_region(cell))
+            .or_insert_with(|| Quadtree::new(REGION_DEPTH))
+            .insert_pt(region_point(cell), entity)
+            .expect("a point inside of its region");
+        self.entries.insert(entity, (cell, handle));
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        let Some((cell, handle)) = self.entries.remove(&entity) else {
+            return;
+        };
+        let region = cell_to_region(cell);
+        if let Some(tree) = self.trees.get_mut(&region) {
+            tree.delete_by_handle(handle);
+            if tree.is_empty() {
+                self.trees.remove(&region);
+            }
+        }
+    }
+
+    /// Units in the cells around `pos` that are within `radius`, the caller checks the exact
+    /// distance.
+    pub fn nearby(&self, pos: Vec2, radius: f32) -> impl Iterator<Item = Entity> + '_ {
+        let center = pos_to_cell(pos);
+        let cells = IVec2::splat((radius / 16.0).ceil() as i32 + 1);
+        let (min, max) = (center - cells, center + cells);
+        let (min_region, max_region) = (cell_to_region(min), cell_to_region(max));
+        (min_region.x..=max_region.x)
+            .flat_map(move |x| (min_region.y..=max_region.y).map(move |y| IVec2::new(x, y)))
+            .filter_map(|region| self.trees.get(&region).map(|tree| (region, tree)))
+            .flat_map(move |(region, tree)| {
+                // the part of the area that lies in this region
+                let origin = region * REGION_CELLS;
+                let from = (min - origin).max(IVec2::ZERO);
+                let to = (max - origin).min(IVec2::splat(REGION_CELLS - 1));
+                let area = AreaBuilder::default()
+                    .anchor(Point {
+                        x: from.x as u32,
+                        y: from.y as u32,
+                    })
+                    .dimensions(((to.x - from.x + 1) as u32, (to.y - from.y + 1) as u32))
+                    .build()
+                    .expect("valid area");
+                tree.query_strict(area).map(|entry| *entry.value_ref())
+            })
+    }
+
+    pub fn len(&self) -> usize {
+        self.trees.values().map(|tree| tree.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trees.is_empty()
+    }
+
+    /// Checks that every entry belongs to a live unit and matches the tree. Units spawned this
+    /// frame may not have an entry yet.
+    pub fn check(&self, is_unit: impl Fn(Entity) -> bool) -> Result<(), String> {
+        if self.len() != self.entries.len() {
+            return Err(format!(
+                "the trees have {} entries but {} units are indexed",
+                self.len(),
+                self.entries.len()
+            ));
+        }
+        for (entity, (cell, handle)) in self.entries.iter() {
+            if !is_unit(*entity) {
+                return Err(format!("{entity:?} is indexed but is no unit"));
+            }
+            match self
+                .trees
+                .get(&cell_to_region(*cell))
+                .and_then(|tree| tree.get(*handle))
+            {
+                Some(entry)
+                    if entry.value_ref() == entity && entry.anchor() == region_point(*cell) => {}
+                _ => return Err(format!("the entry of {entity:?} doesn't match the tree")),
+            }
+        }
+        Ok(())
+    }
+}
+
 fn unit_quad_tree_placement(
-    mut query: Query<(&Transform, &mut Unit, Entity)>,
+    query: Query<(&Transform, Entity), With<Unit>>,
     mut unit_quad_tree: ResMut<UnitQuadTree>,
 ) {
-    for (transform, mut unit, entity) in query.iter_mut() {
-        let unit_pos = transform.translation.truncate();
-        let point_pos = pos_to_point(unit_pos);
-        if let Some((current_point, current_handle)) = unit.point {
-            if current_point != point_pos {
-                unit.point = unit_quad_tree
-                    .insert_pt(point_pos, entity)
-                    .map(|handle| (point_pos, handle));
-                if unit.point.is_some() {
-                    unit_quad_tree.delete_by_handle(current_handle);
-                }
-            }
-        } else {
-            unit.point = unit_quad_tree
-                .insert_pt(point_pos, entity)
-                .map(|handle| (point_pos, handle));
-        }
+    for (transform, entity) in query.iter() {
+        unit_quad_tree.update(entity, pos_to_cell(transform.translation.truncate()));
+    }
+}
+
+// despawned units and entities that lost their `Unit` leave the tree
+fn unit_quad_tree_removal(
+    mut removed: RemovedComponents<Unit>,
+    mut unit_quad_tree: ResMut<UnitQuadTree>,
+) {
+    for entity in removed.iter() {
+        unit_quad_tree.remove(entity);
+    }
+}
+
+fn unit_quad_tree_check(query: Query<(), With<Unit>>, unit_quad_tree: Res<UnitQuadTree>) {
+    if let Err(e) = unit_quad_tree.check(|entity| query.contains(entity)) {
+        error!("unit quad tree is out of sync: {e}");
     }
 }
 
-fn unit_vel(mut query: Query<(&mut Transform, &Unit)>, time: Res<Time>) {
+fn unit_vel(mut query: Query<(&mut Transform, &Unit)>, terrain_map: Option<Res<TerrainMap>>) {
     query.par_iter_mut().for_each_mut(|(mut transform, unit)| {
-        transform.translation += unit.vel.extend(0.0) * time.delta_seconds();
+        let delta = unit.vel * tick_seconds();
+        let delta = match &terrain_map {
+            Some(terrain_map) => {
+                terrain_map.constrain_move(transform.translation.truncate(), delta)
+            }
+            None => delta,
+        };
+        transform.translation += delta.extend(0.0);
     });
 }
 
-const MAX_SPEED: f32 = 60.0;
-const ACCELLERATION: f32 = 200.0;
 const MOVEMENT_DEAD_ZONE: f32 = 2.0;
 
-fn unit_move(mut query: Query<&mut Unit>, time: Res<Time>) {
-    query.par_iter_mut().for_each_mut(|mut unit| {
-        let target = unit.target_direction.clamp_length_max(1.0) * MAX_SPEED; // max speed
+fn unit_move(mut query: Query<(&mut Unit, &UnitKind)>, definitions: Res<Definitions>) {
+    query.par_iter_mut().for_each_mut(|(mut unit, kind)| {
+        let definition = definitions.unit(*kind);
+        let target = unit.target_direction.clamp_length_max(1.0) * definition.speed; // max speed
         let delta = target - unit.vel;
-        unit.vel += delta.clamp_length_max(time.delta_seconds() * ACCELLERATION); // accell
+        unit.vel += delta.clamp_length_max(tick_seconds() * definition.acceleration); // accell
         if unit.vel.length_squared() > MOVEMENT_DEAD_ZONE * MOVEMENT_DEAD_ZONE {
             unit.last_direction = unit.vel.normalize();
         }
     });
 }
 
-fn unit_select(
+pub(crate) fn unit_select(
     mut apply_selection: EventReader<ApplySelectionEvent>,
-    query: Query<(&Transform, Entity), With<Unit>>,
+    query: Query<(&Transform, &UnitKind, Entity), With<Unit>>,
+    camera_query: Query<(&Transform, &OrthographicProjection), With<Camera2d>>,
+    definitions: Res<Definitions>,
     mut commands: Commands,
 ) {
     for event in apply_selection.iter() {
-        let rect = Rect::from_corners(event.start, event.end);
-
-        for (transform, entity) in query.iter() {
-            let point = transform.translation.truncate();
-            let mut entity = commands.entity(entity);
-            if rect.contains(point) {
-                entity.insert(SelectedMark);
-            } else {
-                entity.remove::<SelectedMark>();
+        match event.mode {
+            SelectionMode::Replace | SelectionMode::Add => {
+                let rect = Rect::from_corners(event.start, event.end);
+
+                for (transform, _, entity) in query.iter() {
+                    let point = transform.translation.truncate();
+                    let mut entity = commands.entity(entity);
+                    if rect.contains(point) {
+                        entity.insert(SelectedMark);
+                    } else if event.mode == SelectionMode::Replace {
+                        entity.remove::<SelectedMark>();
+                    }
+                }
+            }
+            SelectionMode::SameKind => {
+                // select every unit on screen that has the same kind as the clicked one
+                let clicked = query
+                    .iter()
+                    .map(|(t, kind, _)| (t.translation.truncate().distance(event.end), kind))
+                    .filter(|(distance, kind)| *distance < definitions.unit(**kind).size)
+                    .min_by(|a, b| a.0.total_cmp(&b.0))
+                    .map(|(_, kind)| *kind);
+                let Some(kind) = clicked else { continue };
+                let (camera_transform, projection) = camera_query.single();
+                let mut view = projection.area;
+                view.min += camera_transform.translation.truncate();
+                view.max += camera_transform.translation.truncate();
+
+                for (transform, unit_kind, entity) in query.iter() {
+                    let point = transform.translation.truncate();
+                    let mut entity = commands.entity(entity);
+                    if *unit_kind == kind && view.contains(point) {
+                        entity.insert(SelectedMark);
+                    } else {
+                        entity.remove::<SelectedMark>();
+                    }
+                }
             }
         }
     }
 }
 
-fn selection_added(
+pub(crate) fn selection_added(
     unit_query: Query<&Children, (With<Unit>, Added<SelectedMark>)>,
     mut child_query: Query<&mut Visibility, With<SelectionBox>>,
 ) {
     for children in unit_query.iter() {
         for child in children.iter() {
-            let mut vis = child_query.get_mut(*child).expect("valid child");
-            *vis = Visibility::Inherited;
+            // units also have a health bar child
+            if let Ok(mut vis) = child_query.get_mut(*child) {
+                *vis = Visibility::Inherited;
+            }
         }
     }
 }
 
-fn selection_removed(
+pub(crate) fn selection_removed(
     mut removed: RemovedComponents<SelectedMark>,
     unit_query: Query<&Children, With<Unit>>,
     mut child_query: Query<&mut Visibility, With<SelectionBox>>,
@@ -116,61 +333,58 @@ fn selection_removed(
     // `RemovedComponents<T>::iter()` returns an interator with the `Entity`s that had their
     // `Component` `T` (in this case `MyComponent`) removed at some point earlier during the frame.
     for entity in removed.iter() {
-        let children = unit_query.get(entity).expect("children");
+        // despawned units also show up here
+        let Ok(children) = unit_query.get(entity) else {
+            continue;
+        };
         for child in children.iter() {
-            let mut vis = child_query.get_mut(*child).expect("a valid child");
-            *vis = Visibility::Hidden;
+            if let Ok(mut vis) = child_query.get_mut(*child) {
+                *vis = Visibility::Hidden;
+            }
         }
     }
 }
 
-const UNIT_SIZE: f32 = 12.0;
 const PUSH_APART_FORCE: f32 = 800.0;
 
 fn unit_push_apart(
-    transform_query: Query<(&Transform, Entity), With<Unit>>,
-    mut unit_query: Query<(&mut Unit, Entity)>,
+    transform_query: Query<(&Transform, Entity, &SimId), With<Unit>>,
+    mut unit_query: Query<(&mut Unit, &UnitKind, Entity)>,
     unit_quad_tree: Res<UnitQuadTree>,
-    time: Res<Time>,
+    definitions: Res<Definitions>,
 ) {
-    let quad_tree = &unit_quad_tree.0;
-    unit_query.par_iter_mut().for_each_mut(|(mut a_unit, ae)| {
-        match transform_query.get(ae) {
-            Ok((a, _)) => {
-                let region = pos_to_region(a.translation.truncate());
-
-                let quad_tree_query = quad_tree.query_strict(region);
-                for entry in quad_tree_query {
-                    match transform_query.get(*entry.value_ref()) {
-                        Ok((b, be)) if ae != be => {
-                            let delta = (b.translation - a.translation).truncate() / UNIT_SIZE; // todo how big is a unit?
-                            let l = delta.length_squared();
-                            if l < 1.0 && l > 0.01 {
-                                let push = delta.normalize() * (1.0 - l);
-                                a_unit.vel -= PUSH_APART_FORCE * time.delta_seconds() * push;
+    // every unit only changes its own velocity and reads the positions of the others, so the
+    // result doesn't depend on the order the threads get to the units. The pushes are summed
+    // by id, float sums differ with the order the quad tree returns the neighbours in.
+    unit_query
+        .par_iter_mut()
+        .for_each_mut(|(mut a_unit, kind, ae)| {
+            let size = definitions.unit(*kind).size;
+            match transform_query.get(ae) {
+                Ok((a, _, _)) => {
+                    let mut pushes = vec![];
+                    for other in unit_quad_tree.nearby(a.translation.truncate(), 0.0) {
+                        match transform_query.get(other) {
+                            Ok((b, be, id)) if ae != be => {
+                                let delta = (b.translation - a.translation).truncate() / size;
+                                let l = delta.length_squared();
+                                if l < 1.0 && l > 0.01 {
+                                    pushes.push((*id, delta.normalize() * (1.0 - l)));
+                                }
                             }
+                            Err(_) | Ok(_) => { /* Do nothing */ }
                         }
-                        Err(_) | Ok(_) => { /* Do nothing */ }
+                    }
+                    pushes.sort_by_key(|(id, _)| *id);
+                    for (_, push) in pushes {
+                        a_unit.vel -= PUSH_APART_FORCE * tick_seconds() * push;
                     }
                 }
+                Err(_) => { /* Do nothing */ }
             }
-            Err(_) => { /* Do nothing */ }
-        }
-    })
-}
-
-fn pos_to_point(unit_pos: Vec2) -> Point<u32> {
-    let pos = ((unit_pos / 16.0) + (Vec2::ONE * 128.0)).round();
-    Point {
-        x: pos.x as u32,
-        y: pos.y as u32,
-    }
+        })
 }
 
-fn pos_to_region(unit_pos: Vec2) -> quadtree_rs::area::Area<u32> {
-    AreaBuilder::default()
-        .anchor(pos_to_point(unit_pos) - Point { x: 1, y: 1 })
-        .dimensions((3, 3))
-        .build()
-        .expect("valid region")
+fn pos_to_cell(unit_pos: Vec2) -> IVec2 {
+    (unit_pos / 16.0).round().as_ivec2()
 }
@@ -1,6 +1,10 @@
 use bevy::{asset::AssetPath, ecs::query::ReadOnlyWorldQuery, prelude::*};
+use bevy_tweening::Lens;
+use rand::Rng;
+use std::fmt::Display;
+use std::str::FromStr;
 
-use crate::{Entity, QueryIter, Transform, Vec2};
+use bevy::ecs::query::QueryIter;
 
 pub fn find_nearest<F: ReadOnlyWorldQuery>(
     transform_query: QueryIter<(Entity, &Transform), F>,
@@ -38,8 +42,12 @@ pub fn nearest_entity(
 }
 
 pub fn random_vec2() -> Vec2 {
-    let x = rand::random::<f32>() * 2.0 - 1.0;
-    let y = rand::random::<f32>() * 2.0 - 1.0;
+    random_vec2_from(&mut rand::thread_rng())
+}
+
+pub fn random_vec2_from<R: Rng>(rng: &mut R) -> Vec2 {
+    let x = rng.gen::<f32>() * 2.0 - 1.0;
+    let y = rng.gen::<f32>() * 2.0 - 1.0;
     Vec2::new(x, y)
 }
 
@@ -57,6 +65,59 @@ pub fn add_texture_atlas(world: &mut World, texture_atlas: TextureAtlas) -> Hand
     texture_atlases.add(texture_atlas)
 }
 
+/// A command line value that can't be used, the game reports it and exits at startup.
+#[derive(Debug)]
+pub struct ArgError {
+    pub name: String,
+    pub message: String,
+}
+
+impl ArgError {
+    pub fn new(name: &str, message: impl Display) -> Self {
+        ArgError {
+            name: name.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl Display for ArgError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid value for {}: {}", self.name, self.message)
+    }
+}
+
+/// Parses the value following `name` on the command line, e.g. `--seed 42`. `None` if the
+/// argument is missing.
+pub fn parse_arg<T: FromStr>(name: &str) -> Result<Option<T>, ArgError>
+where
+    T::Err: Display,
+{
+    let mut args = std::env::args().skip_while(|arg| arg != name).skip(1);
+    args.next()
+        .map(|arg| arg.parse().map_err(|e| ArgError::new(name, e)))
+        .transpose()
+}
+
+/// Whether the flag `name` was passed on the command line, e.g. `--sandbox`.
+pub fn has_arg(name: &str) -> bool {
+    std::env::args().any(|arg| arg == name)
+}
+
+/// Tweens the color of a `TextureAtlasSprite`, bevy_tweening only has a lens for `Sprite`.
+pub struct TextureAtlasSpriteColorLens {
+    pub start: Color,
+    pub end: Color,
+}
+
+impl Lens<TextureAtlasSprite> for TextureAtlasSpriteColorLens {
+    fn lerp(&mut self, target: &mut TextureAtlasSprite, ratio: f32) {
+        let start: Vec4 = self.start.into();
+        let end: Vec4 = self.end.into();
+        target.color = start.lerp(end, ratio).into();
+    }
+}
+
 pub fn ease_in_out_cubic(x: f32) -> f32 {
     if x < 0.5 {
         4.0 * x * x * x
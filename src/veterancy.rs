@@ -0,0 +1,121 @@
+use bevy::prelude::*;
+use bevy::sprite::Anchor;
+use serde::{Deserialize, Serialize};
+
+use crate::definition::{Definitions, RankDefinition};
+use crate::health::{apply_damage, DamageDealtEvent, UnitDeathEvent};
+use crate::simulation::SimulationSet;
+use crate::unit::{Unit, UnitKind};
+
+pub struct VeterancyPlugin;
+
+impl Plugin for VeterancyPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(
+            gain_experience
+                .after(apply_damage)
+                .in_set(SimulationSet::Effects)
+                .in_schedule(CoreSchedule::FixedUpdate),
+        );
+    }
+}
+
+// experience for a kill on top of the damage of the last strike
+const KILL_EXPERIENCE: f32 = 20.0;
+const INSIGNIA_SIZE: f32 = 3.0;
+
+/// Experience of a soldier and the rank it earned, ranks are listed in its `UnitDefinition`.
+#[derive(Component, Default, Clone, Debug, Serialize, Deserialize)]
+pub struct Veterancy {
+    pub experience: f32,
+    // 0 is a recruit, 1 the first rank of the definition
+    pub rank: usize,
+}
+
+impl Veterancy {
+    pub fn rank<'a>(
+        &self,
+        definitions: &'a Definitions,
+        kind: UnitKind,
+    ) -> Option<&'a RankDefinition> {
+        self.rank
+            .checked_sub(1)
+            .and_then(|index| definitions.unit(kind).ranks.get(index))
+    }
+}
+
+#[derive(Component)]
+pub struct RankInsignia;
+
+/// Spawns the rank insignia of a unit, call this inside `with_children` like the health bar.
+pub fn spawn_rank_insignia(builder: &mut ChildBuilder) {
+    builder
+        .spawn(SpriteBundle {
+            sprite: Sprite {
+                color: Color::GOLD,
+                custom_size: Some(Vec2::splat(INSIGNIA_SIZE)),
+                // grows upwards with the rank
+                anchor: Anchor::BottomCenter,
+                ..default()
+            },
+            // left of the head, above the selection box
+            transform: Transform::from_xyz(-7.0, 7.0, 0.2),
+            visibility: Visibility::Hidden,
+            ..default()
+        })
+        .insert(RankInsignia);
+}
+
+// damage dealt and kills give experience, a rank up raises hp right away
+pub(crate) fn gain_experience(
+    mut dealt_events: EventReader<DamageDealtEvent>,
+    mut death_events: EventReader<UnitDeathEvent>,
+    mut query: Query<(&mut Veterancy, &mut Unit, &UnitKind)>,
+    definitions: Res<Definitions>,
+) {
+    let damage = dealt_events
+        .iter()
+        .map(|event| (event.source, event.amount));
+    let kills = death_events
+        .iter()
+        .filter_map(|event| Some((event.killer?, KILL_EXPERIENCE)));
+    for (source, experience) in damage.chain(kills) {
+        let Ok((mut veterancy, mut unit, kind)) = query.get_mut(source) else {
+            continue;
+        };
+        veterancy.experience += experience;
+        let ranks = &definitions.unit(*kind).ranks;
+        while let Some(next) = ranks.get(veterancy.rank) {
+            if veterancy.experience < next.experience {
+                break;
+            }
+            let previous_hp = veterancy
+                .rank(&definitions, *kind)
+                .map_or(0.0, |rank| rank.hp);
+            unit.max_hp += next.hp - previous_hp;
+            unit.hp += next.hp - previous_hp;
+            veterancy.rank += 1;
+        }
+    }
+}
+
+// one gold mark per rank
+pub(crate) fn rank_insignia(
+    unit_query: Query<(&Veterancy, &Children), Changed<Veterancy>>,
+    mut insignia_query: Query<(&mut Sprite, &mut Visibility), With<RankInsignia>>,
+) {
+    for (veterancy, children) in unit_query.iter() {
+        for child in children.iter() {
+            let Ok((mut sprite, mut visibility)) = insignia_query.get_mut(*child) else {
+                continue;
+            };
+            *visibility = if veterancy.rank > 0 {
+                Visibility::Inherited
+            } else {
+                Visibility::Hidden
+            };
+            let rank = veterancy.rank.max(1) as f32;
+            sprite.custom_size = Some(Vec2::new(INSIGNIA_SIZE, INSIGNIA_SIZE * rank));
+        }
+    }
+}
@@ -0,0 +1,310 @@
+use std::ops::RangeInclusive;
+
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use noisy_bevy::fbm_simplex_2d;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::forest::{Sapling, Stump};
+use crate::ground::{spawn_chunk, ChunkLayers, WorldSize, CHUNK_SIZE};
+use crate::terrain::{GroundSheet, Terrain, TerrainMap};
+use crate::unit::Unit;
+use crate::util::random_vec2_from;
+use crate::{spawn_tree, SpriteSheets, Tree, TreeSpawnEvent, WorldSeed};
+
+pub struct WorldPlugin;
+
+impl Plugin for WorldPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ChunkRegistry>()
+            .add_startup_system(spawn_world)
+            .add_system(stream_chunks.run_if(resource_equals(WorldSize::Infinite)));
+    }
+}
+
+// chunks around the view that are loaded before they become visible
+const LOAD_MARGIN: i32 = 1;
+// larger than LOAD_MARGIN so chunks at the edge of the view don't load and unload every frame
+const UNLOAD_MARGIN: i32 = 3;
+// generating a chunk takes a few milliseconds, so spread the work over several frames
+const CHUNKS_PER_FRAME: usize = 2;
+
+// trees only grow on this band of terrain height
+pub const TREE_HEIGHT: RangeInclusive<f32> = 0.1..=0.3;
+
+/// Which chunks are loaded and what is left of the chunks that were unloaded.
+#[derive(Resource, Default)]
+pub struct ChunkRegistry {
+    // ground entity of every loaded chunk
+    loaded: HashMap<IVec2, Entity>,
+    // chunks that were generated before, they get these trees back instead of new ones
+    unloaded: HashMap<IVec2, Vec<PersistedTree>>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PersistedTree {
+    pub pos: Vec2,
+    pub index: usize,
+    pub tree: Tree,
+    pub sapling: Option<Sapling>,
+    pub stump: Option<Stump>,
+}
+
+impl ChunkRegistry {
+    pub fn with_unloaded(unloaded: impl IntoIterator<Item = (IVec2, Vec<PersistedTree>)>) -> Self {
+        ChunkRegistry {
+            loaded: HashMap::default(),
+            unloaded: unloaded.into_iter().collect(),
+        }
+    }
+
+    pub fn is_loaded(&self, chunk: IVec2) -> bool {
+        self.loaded.contains_key(&chunk)
+    }
+
+    pub fn loaded(&self) -> impl Iterator<Item = IVec2> + '_ {
+        self.loaded.keys().copied()
+    }
+
+    pub fn unloaded(&self) -> impl Iterator<Item = (IVec2, &Vec<PersistedTree>)> {
+        self.unloaded.iter().map(|(chunk, trees)| (*chunk, trees))
+    }
+}
+
+fn spawn_world(
+    mut commands: Commands,
+    mut spawn_tree_events: EventWriter<TreeSpawnEvent>,
+    sprite_sheets: Res<SpriteSheets>,
+    mut registry: ResMut<ChunkRegistry>,
+    seed: Res<WorldSeed>,
+    size: Res<WorldSize>,
+) {
+    let mut terrain_map = TerrainMap::new(*size);
+    // an infinite world has no chunks in bounds, `stream_chunks` loads them instead
+    for chunk in terrain_map.chunks_in_bounds() {
+        spawn_tree_events.send_batch(load_chunk(
+            &mut commands,
+            &sprite_sheets,
+            &mut terrain_map,
+            &mut registry,
+            *seed,
+            chunk,
+        ));
+    }
+    commands.insert_resource(terrain_map);
+}
+
+// the noise everything on the ground is derived from, `noise_offset` comes from the seed
+pub fn terrain_height(pos: Vec2, noise_offset: Vec2) -> f32 {
+    fbm_simplex_2d(pos * 0.003 + noise_offset, 8, 2.0, 0.5) / 2.
+}
+
+/// Spawns the ground of `chunk` and adds its terrain to `terrain_map`. A chunk that was
+/// unloaded before gets its persisted trees back, otherwise this returns where trees should
+/// grow. The same seed always produces the same chunk.
+pub fn load_chunk(
+    commands: &mut Commands,
+    sprite_sheets: &SpriteSheets,
+    terrain_map: &mut TerrainMap,
+    registry: &mut ChunkRegistry,
+    seed: WorldSeed,
+    chunk: IVec2,
+) -> Vec<TreeSpawnEvent> {
+    let noise_offset = seed.noise_offset();
+    let height = |pos: Vec2| terrain_height(pos, noise_offset);
+    let grid = terrain_map.generate_chunk(chunk, height);
+    let chunk_origin = TerrainMap::chunk_origin(chunk);
+    let mut rng = seed.chunk_rng(chunk);
+    let mut layers = ChunkLayers::default();
+    let mut trees = vec![];
+    for x in 0..CHUNK_SIZE {
+        for y in 0..CHUNK_SIZE {
+            let tile = chunk * CHUNK_SIZE + IVec2::new(x, y);
+            if !terrain_map.in_bounds(tile) {
+                continue;
+            }
+            let pos = TerrainMap::tile_to_pos(tile);
+            let local_pos = pos - chunk_origin;
+            let sprites = grid.tile_sprites(tile, rng.gen());
+            let is_grass = sprites[0].0 == GroundSheet::TexturedGrass;
+            for (layer, (sheet, index)) in sprites.into_iter().enumerate() {
+                layers.add(sheet, layer, local_pos, index, false);
+            }
+            if is_grass && rng.gen_ratio(1, 5) {
+                let offset = (random_vec2_from(&mut rng) * 6.0).round();
+                layers.add(
+                    GroundSheet::GrassDeco,
+                    2,
+                    local_pos + offset,
+                    rng.gen_range(0..4),
+                    rng.gen(),
+                );
+            }
+
+            if TREE_HEIGHT.contains(&height(pos)) && rng.gen_ratio(1, 8) {
+                let pos = pos + (random_vec2_from(&mut rng) * 8.0).round();
+                let index = rng.gen_range(1..4);
+                // trees stay inside their chunk so they are unloaded together with it
+                if TerrainMap::pos_to_chunk(pos) == chunk
+                    && matches!(
+                        grid.get(TerrainMap::pos_to_tile(pos)),
+                        Some(Terrain::Grass | Terrain::DeadGrass)
+                    )
+                {
+                    trees.push(TreeSpawnEvent {
+                        pos,
+                        index,
+                        sapling: false,
+                    })
+                }
+            }
+        }
+    }
+    terrain_map.insert_chunk(chunk, &grid);
+    let entity = spawn_chunk(commands, chunk, chunk_origin, layers);
+    registry.loaded.insert(chunk, entity);
+
+    match registry.unloaded.remove(&chunk) {
+        Some(persisted) => {
+            for PersistedTree {
+                pos,
+                index,
+                tree,
+                sapling,
+                stump,
+            } in persisted
+            {
+                let entity = spawn_tree(commands, sprite_sheets, pos, index);
+                let mut entity = commands.entity(entity);
+                entity.insert(tree);
+                if let Some(sapling) = sapling {
+                    entity.insert(sapling);
+                }
+                if let Some(stump) = stump {
+                    entity.insert(stump);
+                }
+            }
+            vec![]
+        }
+        None => trees,
+    }
+}
+
+/// Despawns the ground of `chunk` and keeps `trees` until it is loaded again.
+pub fn unload_chunk(
+    commands: &mut Commands,
+    terrain_map: &mut TerrainMap,
+    registry: &mut ChunkRegistry,
+    chunk: IVec2,
+    trees: Vec<PersistedTree>,
+) {
+    if let Some(entity) = registry.loaded.remove(&chunk) {
+        commands.entity(entity).despawn_recursive();
+    }
+    terrain_map.remove_chunk(chunk);
+    registry.unloaded.insert(chunk, trees);
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn stream_chunks(
+    mut commands: Commands,
+    mut spawn_tree_events: EventWriter<TreeSpawnEvent>,
+    sprite_sheets: Res<SpriteSheets>,
+    mut terrain_map: ResMut<TerrainMap>,
+    mut registry: ResMut<ChunkRegistry>,
+    seed: Res<WorldSeed>,
+    camera_query: Query<(&Transform, &OrthographicProjection), With<Camera2d>>,
+    unit_query: Query<&Transform, With<Unit>>,
+    tree_query: Query<(
+        Entity,
+        &Transform,
+        &TextureAtlasSprite,
+        &Tree,
+        Option<&Sapling>,
+        Option<&Stump>,
+    )>,
+) {
+    let Ok((camera_transform, projection)) = camera_query.get_single() else {
+        return;
+    };
+    let camera_pos = camera_transform.translation.truncate();
+    let view_min = TerrainMap::pos_to_chunk(camera_pos + projection.area.min);
+    let view_max = TerrainMap::pos_to_chunk(camera_pos + projection.area.max);
+    // units walk on the terrain wherever the camera is, so the chunks they stand on and the
+    // ones they can walk into stay loaded
+    let occupied = unit_query
+        .iter()
+        .map(|transform| TerrainMap::pos_to_chunk(transform.translation.truncate()))
+        .flat_map(|chunk| {
+            (-1..=1).flat_map(move |x| (-1..=1).map(move |y| chunk + IVec2::new(x, y)))
+        })
+        .collect::<HashSet<_>>();
+
+    let far = registry
+        .loaded()
+        .filter(|chunk| {
+            (chunk.cmplt(view_min - UNLOAD_MARGIN).any()
+                || chunk.cmpgt(view_max + UNLOAD_MARGIN).any())
+                && !occupied.contains(chunk)
+        })
+        .collect::<Vec<_>>();
+    if !far.is_empty() {
+        let mut trees = HashMap::<IVec2, Vec<PersistedTree>>::default();
+        for (entity, transform, sprite, tree, sapling, stump) in tree_query.iter() {
+            let pos = transform.translation.truncate();
+            let chunk = TerrainMap::pos_to_chunk(pos);
+            if far.contains(&chunk) {
+                trees.entry(chunk).or_default().push(PersistedTree {
+                    pos,
+                    index: sprite.index,
+                    tree: tree.clone(),
+                    sapling: sapling.cloned(),
+                    stump: stump.cloned(),
+                });
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+        for chunk in far {
+            let trees = trees.remove(&chunk).unwrap_or_default();
+            unload_chunk(&mut commands, &mut terrain_map, &mut registry, chunk, trees);
+        }
+    }
+
+    // all of them this frame, before a unit walks onto one
+    let mut needed = occupied
+        .into_iter()
+        .filter(|chunk| !registry.is_loaded(*chunk))
+        .collect::<Vec<_>>();
+    needed.sort_by_key(|chunk| (chunk.x, chunk.y));
+    for chunk in needed {
+        spawn_tree_events.send_batch(load_chunk(
+            &mut commands,
+            &sprite_sheets,
+            &mut terrain_map,
+            &mut registry,
+            *seed,
+            chunk,
+        ));
+    }
+
+    // nearest chunks first
+    let center = TerrainMap::pos_to_chunk(camera_pos);
+    let mut missing = (view_min.x - LOAD_MARGIN..=view_max.x + LOAD_MARGIN)
+        .flat_map(|x| {
+            (view_min.y - LOAD_MARGIN..=view_max.y + LOAD_MARGIN).map(move |y| IVec2::new(x, y))
+        })
+        .filter(|chunk| !registry.is_loaded(*chunk))
+        .collect::<Vec<_>>();
+    missing.sort_by_key(|chunk| (*chunk - center).abs().max_element());
+    for chunk in missing.into_iter().take(CHUNKS_PER_FRAME) {
+        spawn_tree_events.send_batch(load_chunk(
+            &mut commands,
+            &sprite_sheets,
+            &mut terrain_map,
+            &mut registry,
+            *seed,
+            chunk,
+        ));
+    }
+}
@@ -0,0 +1,1161 @@
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+
+use bevy_rts::command::{Command, CommandEvent, OrderQueue};
+use bevy_rts::definition::Definitions;
+use bevy_rts::equipment::{Armory, Equipment};
+use bevy_rts::forest::{Sapling, Stump, TreeState, MATURE_INDEX};
+use bevy_rts::ground::{WorldSize, CHUNK_SIZE};
+use bevy_rts::health::DamageEvent;
+use bevy_rts::lockstep::{Lobby, LockstepSession};
+use bevy_rts::lumberjack::Lumberjack;
+use bevy_rts::lumberjack::SpawnLumberjackEvent;
+use bevy_rts::morale::Morale;
+use bevy_rts::replay::{ReplayPlayer, ReplayRecorder};
+use bevy_rts::research::{ResearchEvent, ResearchState, Researching};
+use bevy_rts::save::SaveGame;
+use bevy_rts::scenario::{Outcome, Placement, Scenario, ScenarioState};
+use bevy_rts::simulation::{
+    state_hash, InputSource, LocalInputs, PlayerInput, SimulationTick, TICK, TICK_RATE,
+};
+use bevy_rts::soldier::{Armor, Soldier, SpawnSoldierEvent, Stance, Weapon};
+use bevy_rts::terrain::{Terrain, TerrainMap};
+use bevy_rts::unit::{Team, Unit};
+use bevy_rts::veterancy::Veterancy;
+use bevy_rts::world::ChunkRegistry;
+use bevy_rts::{
+    Barrack, BuildingKind, Resources, SimulationPlugin, Stats, Tree, TreeChopEvent, TreeSpawnEvent,
+    UnitQuadTree, WorldSeed,
+};
+
+// every update runs exactly one tick, so tests don't depend on the machine
+const STEP: Duration = TICK;
+
+/// A simulation without window or GPU. An infinite world has no chunks without a camera, so
+/// nothing but what the test spawns is in it.
+fn headless_app(size: WorldSize) -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugin(SimulationPlugin)
+        .insert_resource(WorldSeed(42))
+        .insert_resource(size)
+        .insert_resource(TimeUpdateStrategy::ManualInstant(Instant::now()));
+    app
+}
+
+fn run(app: &mut App, seconds: f32) {
+    for _ in 0..(seconds / STEP.as_secs_f32()).ceil() as usize {
+        let mut strategy = app.world.resource_mut::<TimeUpdateStrategy>();
+        if let TimeUpdateStrategy::ManualInstant(instant) = strategy.as_mut() {
+            *instant += STEP;
+        }
+        app.update();
+    }
+}
+
+/// The barracks and the armory of the default scenario, without its lumberjacks and metal.
+fn buildings_scenario() -> Scenario {
+    Scenario {
+        buildings: Scenario::builtin().buildings,
+        ..default()
+    }
+}
+
+fn unit_positions(app: &mut App) -> Vec<Vec2> {
+    app.world
+        .query_filtered::<&Transform, With<Unit>>()
+        .iter(&app.world)
+        .map(|transform| transform.translation.truncate())
+        .collect()
+}
+
+#[test]
+fn lumberjack_deposits_wood() {
+    let mut app = headless_app(WorldSize::Infinite);
+    app.insert_resource(buildings_scenario());
+    app.world.send_event(TreeSpawnEvent {
+        pos: Vec2::new(0.0, 60.0),
+        index: 3,
+        sapling: false,
+    });
+    app.world
+        .send_event(SpawnLumberjackEvent(Vec2::new(0.0, 40.0), Team::PLAYER));
+
+    run(&mut app, 20.0);
+
+    assert!(app.world.resource::<Stats>().team(Team::PLAYER).wood > 0);
+}
+
+// the wood a lumberjack brings back on its first trip
+fn first_deposit(research: Option<&str>) -> u32 {
+    let mut app = headless_app(WorldSize::Infinite);
+    app.insert_resource(buildings_scenario());
+    if let Some(id) = research {
+        app.world
+            .resource_mut::<Stats>()
+            .team_mut(Team::PLAYER)
+            .wood = 1000;
+        run(&mut app, 0.1);
+        push_input(&mut app, PlayerInput::Research(id.to_string()));
+        run(&mut app, 30.0);
+        assert!(app
+            .world
+            .resource::<ResearchState>()
+            .is_done(Team::PLAYER, id));
+    }
+    let start = app.world.resource::<Stats>().team(Team::PLAYER).wood;
+    app.world.send_event(TreeSpawnEvent {
+        pos: Vec2::new(0.0, 60.0),
+        index: 3,
+        sapling: false,
+    });
+    app.world
+        .send_event(SpawnLumberjackEvent(Vec2::new(0.0, 40.0), Team::PLAYER));
+    for _ in 0..600 {
+        run(&mut app, 0.1);
+        let wood = app.world.resource::<Stats>().team(Team::PLAYER).wood;
+        if wood != start {
+            return wood - start;
+        }
+    }
+    panic!("the lumberjack didn't deposit");
+}
+
+#[test]
+fn lumberjacks_deposit_everything_they_carry() {
+    assert_eq!(first_deposit(None), 5);
+    // baskets carry 3 more
+    assert_eq!(first_deposit(Some("baskets")), 8);
+}
+
+#[test]
+fn units_push_apart() {
+    let mut app = headless_app(WorldSize::Infinite);
+    app.world
+        .send_event(SpawnSoldierEvent(Vec2::ZERO, Team::PLAYER));
+    app.world
+        .send_event(SpawnSoldierEvent(Vec2::new(4.0, 0.0), Team::PLAYER));
+
+    run(&mut app, 2.0);
+
+    let positions = unit_positions(&mut app);
+    assert_eq!(positions.len(), 2);
+    assert!(positions[0].distance(positions[1]) > 8.0);
+}
+
+#[test]
+fn quad_tree_follows_spawns_and_despawns() {
+    let mut app = headless_app(WorldSize::Infinite);
+    for i in 0..10 {
+        app.world.send_event(SpawnLumberjackEvent(
+            Vec2::new(i as f32 * 40.0, 0.0),
+            Team::PLAYER,
+        ));
+    }
+    run(&mut app, 0.2);
+    assert_eq!(app.world.resource::<UnitQuadTree>().len(), 10);
+
+    let units = app
+        .world
+        .query_filtered::<Entity, With<Unit>>()
+        .iter(&app.world)
+        .take(4)
+        .collect::<Vec<_>>();
+    for entity in units {
+        app.world.entity_mut(entity).despawn_recursive();
+    }
+    run(&mut app, 0.2);
+
+    let quad_tree = app.world.resource::<UnitQuadTree>();
+    assert_eq!(quad_tree.len(), 6);
+    assert_eq!(
+        quad_tree.check(|entity| app.world.get::<Unit>(entity).is_some()),
+        Ok(())
+    );
+}
+
+#[test]
+fn quad_tree_has_no_bounds() {
+    let mut app = headless_app(WorldSize::Infinite);
+    // far apart and on both sides of region borders
+    let positions = [
+        Vec2::new(-50_000.0, 30_000.0),
+        Vec2::new(-50_010.0, 30_000.0),
+        Vec2::new(4_090.0, -8.0),
+        Vec2::new(4_100.0, 8.0),
+    ];
+    for pos in positions {
+        app.world
+            .send_event(SpawnLumberjackEvent(pos, Team::PLAYER));
+    }
+    run(&mut app, 0.2);
+
+    let quad_tree = app.world.resource::<UnitQuadTree>();
+    assert_eq!(
+        quad_tree.check(|entity| app.world.get::<Unit>(entity).is_some()),
+        Ok(())
+    );
+    for pos in [positions[0], positions[2]] {
+        assert_eq!(quad_tree.nearby(pos, 20.0).count(), 2);
+    }
+}
+
+#[test]
+fn chunks_with_units_stay_loaded() {
+    let mut app = headless_app(WorldSize::Infinite);
+    let camera = app
+        .world
+        .spawn((
+            Camera2d::default(),
+            OrthographicProjection::default(),
+            Transform::default(),
+        ))
+        .id();
+    let far = Vec2::new(5_000.0, 0.0);
+    app.world
+        .send_event(SpawnLumberjackEvent(far, Team::PLAYER));
+    run(&mut app, 0.2);
+    let loaded = |app: &App, pos: Vec2| {
+        app.world
+            .resource::<ChunkRegistry>()
+            .is_loaded(TerrainMap::pos_to_chunk(pos))
+    };
+    assert!(loaded(&app, Vec2::ZERO));
+    assert!(loaded(&app, far));
+
+    // the camera moves on, the chunk it leaves behind has no units
+    app.world
+        .get_mut::<Transform>(camera)
+        .unwrap()
+        .translation
+        .y = 5_000.0;
+    run(&mut app, 0.2);
+    assert!(!loaded(&app, Vec2::ZERO));
+    assert!(loaded(&app, far));
+}
+
+// the terrain of every tile and the sorted trees of a generated fixed size world
+fn generated_world(seed: u64) -> (Vec<Option<Terrain>>, Vec<(Vec2, usize)>) {
+    let mut app = headless_app(WorldSize::Fixed(UVec2::splat(64)));
+    app.insert_resource(WorldSeed(seed));
+    run(&mut app, 0.1);
+    let terrain_map = app.world.resource::<TerrainMap>();
+    let tiles = terrain_map
+        .chunks_in_bounds()
+        .into_iter()
+        .flat_map(|chunk| {
+            (0..CHUNK_SIZE * CHUNK_SIZE)
+                .map(move |i| chunk * CHUNK_SIZE + IVec2::new(i % CHUNK_SIZE, i / CHUNK_SIZE))
+        })
+        .map(|tile| terrain_map.get(tile))
+        .collect();
+    let mut trees = app
+        .world
+        .query_filtered::<(&Transform, &TextureAtlasSprite), With<Tree>>()
+        .iter(&app.world)
+        .map(|(transform, sprite)| (transform.translation.truncate(), sprite.index))
+        .collect::<Vec<_>>();
+    trees.sort_by(|a, b| a.0.x.total_cmp(&b.0.x).then(a.0.y.total_cmp(&b.0.y)));
+    (tiles, trees)
+}
+
+#[test]
+fn same_seed_generates_the_same_world() {
+    let (tiles, trees) = generated_world(42);
+    assert!(!trees.is_empty());
+    assert_eq!(generated_world(42), (tiles.clone(), trees.clone()));
+
+    let (other_tiles, other_trees) = generated_world(7);
+    assert_ne!(other_tiles, tiles);
+    assert_ne!(other_trees, trees);
+}
+
+#[test]
+fn save_round_trip_restores_identical_world() {
+    let mut app = headless_app(WorldSize::Fixed(UVec2::splat(64)));
+    app.insert_resource(buildings_scenario());
+    for i in 0..8 {
+        app.world.send_event(SpawnLumberjackEvent(
+            Vec2::new(i as f32 * 16.0, 40.0),
+            Team::PLAYER,
+        ));
+        app.world.send_event(SpawnSoldierEvent(
+            Vec2::new(i as f32 * 16.0, -40.0),
+            Team::PLAYER,
+        ));
+    }
+    run(&mut app, 5.0);
+    let saved = ron::to_string(&SaveGame::capture(&mut app.world)).unwrap();
+
+    let mut restored = headless_app(WorldSize::Infinite);
+    restored.insert_resource(WorldSeed(7));
+    restored.update();
+    ron::from_str::<SaveGame>(&saved)
+        .unwrap()
+        .restore(&mut restored.world)
+        .unwrap();
+
+    assert_eq!(
+        ron::to_string(&SaveGame::capture(&mut restored.world)).unwrap(),
+        saved
+    );
+}
+
+fn scenario_hash(seed: u64) -> u64 {
+    let mut app = headless_app(WorldSize::Fixed(UVec2::splat(64)));
+    app.insert_resource(WorldSeed(seed))
+        .insert_resource(Scenario::builtin());
+    for i in 0..8 {
+        app.world.send_event(SpawnSoldierEvent(
+            Vec2::new(i as f32 * 4.0, -40.0),
+            Team::PLAYER,
+        ));
+    }
+    run(&mut app, 2.0);
+    // some soldiers die, so deaths are part of the scenario
+    let soldiers = app
+        .world
+        .query_filtered::<Entity, With<Soldier>>()
+        .iter(&app.world)
+        .take(3)
+        .collect::<Vec<_>>();
+    for target in soldiers {
+        app.world.send_event(DamageEvent {
+            target,
+            amount: 1000.0,
+            source: None,
+        });
+    }
+    run(&mut app, 25.0);
+    state_hash(&mut app.world)
+}
+
+#[test]
+fn same_scenario_same_state_hash() {
+    assert_eq!(scenario_hash(42), scenario_hash(42));
+    assert_ne!(scenario_hash(42), scenario_hash(43));
+}
+
+fn push_input(app: &mut App, input: PlayerInput) {
+    app.world.resource_mut::<LocalInputs>().push(input);
+}
+
+#[test]
+fn replay_reproduces_recorded_match() {
+    let path = std::env::temp_dir().join("bevy_rts_replay_test.ron");
+    let mut app = headless_app(WorldSize::Fixed(UVec2::splat(64)));
+    app.insert_resource(Scenario::builtin())
+        .insert_resource(ReplayRecorder::new(
+            &path,
+            WorldSeed(42),
+            WorldSize::Fixed(UVec2::splat(64)),
+            Scenario::builtin(),
+        ));
+    for i in 0..6 {
+        push_input(
+            &mut app,
+            PlayerInput::SpawnSoldier(Vec2::new(i as f32 * 8.0, -40.0)),
+        );
+    }
+    run(&mut app, 1.0);
+    let lumberjacks = app
+        .world
+        .query_filtered::<Entity, With<Lumberjack>>()
+        .iter(&app.world)
+        .take(20)
+        .collect();
+    push_input(
+        &mut app,
+        PlayerInput::Command(CommandEvent {
+            team: Team::PLAYER,
+            units: lumberjacks,
+            command: Command::Move(Vec2::new(100.0, 100.0)),
+            queue: false,
+        }),
+    );
+    let soldiers = app
+        .world
+        .query_filtered::<Entity, With<Soldier>>()
+        .iter(&app.world)
+        .take(2)
+        .collect();
+    push_input(
+        &mut app,
+        PlayerInput::Command(CommandEvent {
+            team: Team::PLAYER,
+            units: soldiers,
+            command: Command::Patrol(Vec2::new(-100.0, 50.0)),
+            queue: false,
+        }),
+    );
+    run(&mut app, 10.0);
+    let recorded_hash = state_hash(&mut app.world);
+    let replay = app.world.resource::<ReplayRecorder>().replay().clone();
+
+    let mut replayed = headless_app(replay.size);
+    // entities of the viewer's own shift the entity numbers of the match
+    replayed.world.spawn_batch((0..50).map(|_| ()));
+    replayed
+        .insert_resource(replay.scenario.clone())
+        .insert_resource(replay.seed)
+        .insert_resource(InputSource::Replay)
+        .insert_resource(ReplayPlayer::new(replay));
+    run(&mut replayed, 11.0);
+
+    assert_eq!(replayed.world.resource::<ReplayPlayer>().desync(), None);
+    assert_eq!(state_hash(&mut replayed.world), recorded_hash);
+}
+
+// advances both peers until they reach `tick`, a peer that waits for the other doesn't tick
+fn run_peers(peers: &mut [App; 2], tick: u64) {
+    for _ in 0..tick * 20 {
+        if peers
+            .iter()
+            .all(|app| **app.world.resource::<SimulationTick>() >= tick)
+        {
+            return;
+        }
+        for app in peers.iter_mut() {
+            if **app.world.resource::<SimulationTick>() < tick {
+                run(app, STEP.as_secs_f32());
+            }
+        }
+    }
+    panic!("the peers didn't reach tick {tick}");
+}
+
+#[test]
+fn lockstep_peers_stay_in_sync() {
+    let mut host = Lobby::host(
+        ("127.0.0.1", 0),
+        2,
+        WorldSeed(7),
+        WorldSize::Fixed(UVec2::splat(64)),
+        Scenario::builtin(),
+    )
+    .unwrap();
+    let mut client = Lobby::join(host.local_addr().unwrap()).unwrap();
+    let (mut host_session, mut client_session) = (None, None);
+    for _ in 0..1000 {
+        if host_session.is_none() {
+            host_session = host.poll().unwrap();
+        }
+        if client_session.is_none() {
+            client_session = client.poll().unwrap();
+        }
+        if host_session.is_some() && client_session.is_some() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    let (host_session, client_session) = (host_session.unwrap(), client_session.unwrap());
+    assert_eq!(client_session.seed(), WorldSeed(7));
+    assert_eq!(client_session.slot(), 1);
+
+    let mut peers = [host_session, client_session].map(|session| {
+        let mut app = headless_app(WorldSize::Infinite);
+        session.insert(&mut app);
+        app
+    });
+    for i in 0..6 {
+        push_input(
+            &mut peers[0],
+            PlayerInput::SpawnSoldier(Vec2::new(i as f32 * 8.0, -40.0)),
+        );
+    }
+    // the client's soldiers are its own team and fight the host's
+    for i in 0..3 {
+        push_input(
+            &mut peers[1],
+            PlayerInput::SpawnSoldier(Vec2::new(i as f32 * 8.0, -24.0)),
+        );
+    }
+    run_peers(&mut peers, 20);
+    let soldiers = |app: &mut App, team: Team| {
+        app.world
+            .query::<(&Soldier, &Team)>()
+            .iter(&app.world)
+            .filter(|(_, soldier_team)| **soldier_team == team)
+            .count()
+    };
+    for app in peers.iter_mut() {
+        assert_eq!(soldiers(app, Team::PLAYER), 6);
+        assert_eq!(soldiers(app, Team(1)), 3);
+    }
+    // the client orders units it only knows from its own world, they are the host's so they
+    // ignore it
+    let lumberjacks = peers[1]
+        .world
+        .query_filtered::<Entity, With<Lumberjack>>()
+        .iter(&peers[1].world)
+        .take(20)
+        .collect();
+    push_input(
+        &mut peers[1],
+        PlayerInput::Command(CommandEvent {
+            team: Team(1),
+            units: lumberjacks,
+            command: Command::Move(Vec2::new(10_000.0, 10_000.0)),
+            queue: false,
+        }),
+    );
+    run_peers(&mut peers, 200);
+
+    let [host, client] = &mut peers;
+    let ordered = host
+        .world
+        .query_filtered::<&OrderQueue, With<Lumberjack>>()
+        .iter(&host.world)
+        .flat_map(OrderQueue::iter)
+        .count();
+    assert_eq!(ordered, 0);
+    let wounded = |app: &mut App| {
+        app.world
+            .query_filtered::<&Unit, With<Soldier>>()
+            .iter(&app.world)
+            .filter(|unit| unit.hp < unit.max_hp)
+            .count()
+    };
+    assert!(wounded(host) > 0);
+    assert_eq!(wounded(client), wounded(host));
+    for app in [&*host, &*client] {
+        assert_eq!(app.world.resource::<LockstepSession>().desync(), None);
+    }
+    assert_eq!(state_hash(&mut host.world), state_hash(&mut client.world));
+}
+
+#[test]
+fn game_speed_scales_ticks() {
+    let mut app = headless_app(WorldSize::Infinite);
+    let tick = |app: &App| **app.world.resource::<SimulationTick>();
+    run(&mut app, 1.0);
+    let start = tick(&app);
+
+    app.world.resource_mut::<Time>().pause();
+    run(&mut app, 1.0);
+    assert_eq!(tick(&app), start);
+
+    let mut time = app.world.resource_mut::<Time>();
+    time.unpause();
+    time.set_relative_speed(2.0);
+    run(&mut app, 1.0);
+    assert_eq!(tick(&app), start + 2 * TICK_RATE as u64);
+}
+
+#[test]
+fn research_pays_and_finishes() {
+    let mut app = headless_app(WorldSize::Infinite);
+    app.insert_resource(buildings_scenario());
+    app.world
+        .resource_mut::<Stats>()
+        .team_mut(Team::PLAYER)
+        .wood = 120;
+    run(&mut app, 1.0);
+
+    // bows need spears first, a second axes is already in progress
+    push_input(&mut app, PlayerInput::Research("axes".to_string()));
+    push_input(&mut app, PlayerInput::Research("bows".to_string()));
+    push_input(&mut app, PlayerInput::Research("axes".to_string()));
+    run(&mut app, 1.0);
+    assert_eq!(app.world.resource::<Stats>().team(Team::PLAYER).wood, 20);
+    let mut researching = app.world.query::<&Researching>();
+    assert_eq!(researching.iter(&app.world).count(), 1);
+
+    run(&mut app, 20.0);
+    let state = app.world.resource::<ResearchState>();
+    assert!(state.is_done(Team::PLAYER, "axes"));
+    assert!(!state.is_done(Team::PLAYER, "bows"));
+    assert_eq!(researching.iter(&app.world).count(), 0);
+}
+
+#[test]
+fn research_only_helps_its_team() {
+    let mut app = headless_app(WorldSize::Infinite);
+    let scenario = ron::from_str::<Scenario>(
+        r#"(
+            name: "Test",
+            resources: (wood: 200),
+            buildings: [(kind: Barrack, team: Team(1), at: At((0.0, 0.0)))],
+        )"#,
+    )
+    .unwrap();
+    app.insert_resource(scenario);
+    run(&mut app, 0.1);
+    app.world
+        .send_event(ResearchEvent("baskets".to_string(), Team(1)));
+    run(&mut app, 25.0);
+
+    let definitions = app.world.resource::<Definitions>();
+    let state = app.world.resource::<ResearchState>();
+    assert!(state.is_done(Team(1), "baskets"));
+    assert!(!state.is_done(Team::PLAYER, "baskets"));
+    assert_eq!(state.carry_bonus(Team(1), definitions), 3);
+    assert_eq!(state.carry_bonus(Team::PLAYER, definitions), 0);
+    // both got the wood of the scenario, only the researching team paid
+    let stats = app.world.resource::<Stats>();
+    assert_eq!(stats.team(Team(1)).wood, 125);
+    assert_eq!(stats.team(Team::PLAYER).wood, 200);
+}
+
+#[test]
+fn wood_goes_to_the_team_of_the_lumberjack() {
+    let mut app = headless_app(WorldSize::Infinite);
+    let scenario = ron::from_str::<Scenario>(
+        r#"(
+            name: "Test",
+            buildings: [(kind: Barrack, team: Team(1), at: At((0.0, 0.0)))],
+        )"#,
+    )
+    .unwrap();
+    app.insert_resource(scenario);
+    app.world.send_event(TreeSpawnEvent {
+        pos: Vec2::new(0.0, 60.0),
+        index: 3,
+        sapling: false,
+    });
+    app.world
+        .send_event(SpawnLumberjackEvent(Vec2::new(0.0, 40.0), Team(1)));
+    run(&mut app, 20.0);
+
+    let stats = app.world.resource::<Stats>();
+    assert!(stats.team(Team(1)).wood > 0);
+    assert_eq!(stats.team(Team::PLAYER).wood, 0);
+}
+
+#[test]
+fn soldiers_pick_up_crafted_equipment() {
+    let mut app = headless_app(WorldSize::Infinite);
+    app.insert_resource(buildings_scenario());
+    *app.world.resource_mut::<Stats>().team_mut(Team::PLAYER) = Resources {
+        wood: 200,
+        metal: 100,
+    };
+    app.world
+        .send_event(SpawnSoldierEvent(Vec2::new(100.0, -60.0), Team::PLAYER));
+    run(&mut app, 1.0);
+
+    // chain armor needs research first
+    push_input(&mut app, PlayerInput::Craft(Equipment::Armor(Armor::Chain)));
+    push_input(&mut app, PlayerInput::Research("chain_armor".to_string()));
+    run(&mut app, 31.0);
+    assert_eq!(app.world.resource::<Stats>().team(Team::PLAYER).metal, 100);
+
+    // slings are worth less than the sword the soldier starts with
+    push_input(&mut app, PlayerInput::Craft(Equipment::Armor(Armor::Chain)));
+    push_input(
+        &mut app,
+        PlayerInput::Craft(Equipment::Weapon(Weapon::Sling)),
+    );
+    run(&mut app, 30.0);
+    assert_eq!(app.world.resource::<Stats>().team(Team::PLAYER).metal, 80);
+
+    let soldier = app.world.query::<&Soldier>().single(&app.world).clone();
+    assert_eq!(soldier.armor(), Some(Armor::Chain));
+    assert_eq!(soldier.weapon(), Some(Weapon::Sword));
+    let armory = app.world.query::<&Armory>().single(&app.world);
+    assert_eq!(armory.stock, vec![Equipment::Weapon(Weapon::Sling)]);
+}
+
+#[test]
+fn soldiers_rank_up_and_keep_their_rank() {
+    let mut app = headless_app(WorldSize::Infinite);
+    app.world
+        .send_event(SpawnSoldierEvent(Vec2::new(0.0, 0.0), Team::PLAYER));
+    app.world
+        .send_event(SpawnSoldierEvent(Vec2::new(12.0, 0.0), Team::PLAYER));
+    run(&mut app, 0.1);
+    // a tough enemy that doesn't die before the other soldier ranks up
+    let enemy = app
+        .world
+        .query_filtered::<(Entity, &Transform), With<Soldier>>()
+        .iter(&app.world)
+        .find(|(_, transform)| transform.translation.x > 6.0)
+        .map(|(entity, _)| entity)
+        .unwrap();
+    app.world.entity_mut(enemy).insert(Team(1));
+    app.world.get_mut::<Unit>(enemy).unwrap().hp = 10_000.0;
+    run(&mut app, 20.0);
+
+    let mut query = app.world.query::<(&Veterancy, &Unit, &Team)>();
+    let (veterancy, unit, _) = query
+        .iter(&app.world)
+        .find(|(_, _, team)| **team == Team::PLAYER)
+        .unwrap();
+    assert!(veterancy.experience >= 30.0);
+    assert_eq!(veterancy.rank, 1);
+    assert_eq!(unit.max_hp, 120.0);
+
+    let saved = ron::to_string(&SaveGame::capture(&mut app.world)).unwrap();
+    let mut restored = headless_app(WorldSize::Infinite);
+    restored.update();
+    ron::from_str::<SaveGame>(&saved)
+        .unwrap()
+        .restore(&mut restored.world)
+        .unwrap();
+    restored.update();
+    let ranks = restored
+        .world
+        .query::<&Veterancy>()
+        .iter(&restored.world)
+        .map(|veterancy| veterancy.rank)
+        .max();
+    assert_eq!(ranks, Some(1));
+}
+
+#[test]
+fn experience_counts_only_the_damage_dealt() {
+    let mut app = headless_app(WorldSize::Infinite);
+    app.world
+        .send_event(SpawnSoldierEvent(Vec2::ZERO, Team::PLAYER));
+    app.world
+        .send_event(SpawnLumberjackEvent(Vec2::new(200.0, 0.0), Team(1)));
+    run(&mut app, 0.1);
+    let soldier = app
+        .world
+        .query_filtered::<Entity, With<Soldier>>()
+        .single(&app.world);
+    let (target, hp) = app
+        .world
+        .query_filtered::<(Entity, &Unit), With<Lumberjack>>()
+        .iter(&app.world)
+        .map(|(entity, unit)| (entity, unit.hp))
+        .next()
+        .unwrap();
+    // the first strike overkills, the second hits a dead unit
+    for _ in 0..2 {
+        app.world.send_event(DamageEvent {
+            target,
+            amount: hp * 10.0,
+            source: Some(soldier),
+        });
+    }
+    run(&mut app, 0.1);
+    let veterancy = app.world.get::<Veterancy>(soldier).unwrap();
+    // the hp of the target and the kill
+    assert_eq!(veterancy.experience, hp + 20.0);
+}
+
+#[test]
+fn attack_move_fights_on_the_way() {
+    let mut app = headless_app(WorldSize::Infinite);
+    app.world
+        .send_event(SpawnSoldierEvent(Vec2::ZERO, Team::PLAYER));
+    app.world
+        .send_event(SpawnSoldierEvent(Vec2::new(100.0, 0.0), Team::PLAYER));
+    run(&mut app, 0.1);
+    let mut soldiers = app
+        .world
+        .query_filtered::<(Entity, &Transform), With<Soldier>>();
+    let (soldier, enemy) =
+        soldiers
+            .iter(&app.world)
+            .fold((None, None), |(soldier, enemy), (entity, transform)| {
+                if transform.translation.x > 50.0 {
+                    (soldier, Some(entity))
+                } else {
+                    (Some(entity), enemy)
+                }
+            });
+    let (soldier, enemy) = (soldier.unwrap(), enemy.unwrap());
+    // an enemy that neither fights back nor dies
+    app.world
+        .entity_mut(enemy)
+        .insert((Team(1), Stance::Passive));
+    app.world.get_mut::<Unit>(enemy).unwrap().hp = 10_000.0;
+    push_input(
+        &mut app,
+        PlayerInput::Command(CommandEvent {
+            team: Team::PLAYER,
+            units: vec![soldier],
+            command: Command::AttackMove(Vec2::new(400.0, 0.0)),
+            queue: false,
+        }),
+    );
+    run(&mut app, 5.0);
+
+    assert!(app.world.get::<Unit>(enemy).unwrap().hp < 10_000.0);
+    // the soldier stays with the enemy while it lives
+    let pos = app.world.get::<Transform>(soldier).unwrap().translation;
+    assert!(pos.x < 200.0);
+}
+
+#[test]
+fn queued_orders_run_in_order_and_patrols_loop() {
+    let mut app = headless_app(WorldSize::Infinite);
+    app.world
+        .send_event(SpawnSoldierEvent(Vec2::ZERO, Team::PLAYER));
+    run(&mut app, 0.1);
+    let soldier = app
+        .world
+        .query_filtered::<Entity, With<Soldier>>()
+        .single(&app.world);
+    let orders = [
+        Command::Move(Vec2::new(100.0, 0.0)),
+        Command::Move(Vec2::new(100.0, 100.0)),
+        Command::Patrol(Vec2::new(0.0, 100.0)),
+    ];
+    for (i, command) in orders.into_iter().enumerate() {
+        push_input(
+            &mut app,
+            PlayerInput::Command(CommandEvent {
+                team: Team::PLAYER,
+                units: vec![soldier],
+                command,
+                queue: i > 0,
+            }),
+        );
+    }
+
+    let mut started = vec![];
+    // patrol points reached, the route starts where the soldier got the patrol order
+    let mut reached = vec![];
+    for _ in 0..600 {
+        run(&mut app, 0.1);
+        let current = app.world.get::<OrderQueue>(soldier).unwrap().current();
+        if let Some(command) = current {
+            if started.last() != Some(&command) {
+                started.push(command);
+            }
+        }
+        let pos = app.world.get::<Transform>(soldier).unwrap().translation;
+        let point = [Vec2::new(100.0, 100.0), Vec2::new(0.0, 100.0)]
+            .into_iter()
+            .position(|point| point.distance(pos.truncate()) < 25.0)
+            .filter(|point| reached.last() != Some(point));
+        if let Some(point) = point {
+            reached.push(point);
+        }
+        if reached.len() >= 4 {
+            break;
+        }
+    }
+    assert_eq!(started, orders);
+    // back and forth between the two points
+    assert_eq!(reached, vec![0, 1, 0, 1]);
+}
+
+#[test]
+fn outnumbered_soldiers_rout_to_a_barrack_and_rally() {
+    let mut app = headless_app(WorldSize::Infinite);
+    app.insert_resource(buildings_scenario());
+    app.world
+        .send_event(SpawnSoldierEvent(Vec2::new(0.0, 150.0), Team::PLAYER));
+    for x in [-30.0, -15.0, 15.0, 30.0] {
+        for y in [135.0, 165.0] {
+            app.world
+                .send_event(SpawnSoldierEvent(Vec2::new(x, y), Team::PLAYER));
+        }
+    }
+    run(&mut app, 0.1);
+    // nobody strikes, the pressure alone breaks the soldier
+    let mut soldiers = app
+        .world
+        .query_filtered::<(Entity, &Transform), With<Soldier>>();
+    let soldiers = soldiers
+        .iter(&app.world)
+        .map(|(entity, transform)| (entity, transform.translation.x))
+        .collect::<Vec<_>>();
+    assert_eq!(soldiers.len(), 9);
+    for (soldier, x) in soldiers {
+        app.world.entity_mut(soldier).insert(Stance::Passive);
+        if x != 0.0 {
+            app.world.entity_mut(soldier).insert(Team(1));
+        }
+    }
+    run(&mut app, 15.0);
+
+    let mut query = app.world.query::<(&Soldier, &Morale, &Transform, &Team)>();
+    let (soldier, morale, transform, _) = query
+        .iter(&app.world)
+        .find(|(_, _, _, team)| **team == Team::PLAYER)
+        .unwrap();
+    assert!(soldier.is_routing());
+    assert!(morale.is_broken());
+    // the barrack right below
+    assert!(transform.translation.truncate().distance(Vec2::ZERO) <= 25.0);
+
+    run(&mut app, 10.0);
+    let (soldier, morale, _, _) = query
+        .iter(&app.world)
+        .find(|(_, _, _, team)| **team == Team::PLAYER)
+        .unwrap();
+    assert!(!soldier.is_routing());
+    assert!(!morale.is_broken());
+    assert!(morale.value >= 50.0);
+}
+
+#[test]
+fn scenario_triggers_run_once_in_order() {
+    let mut app = headless_app(WorldSize::Infinite);
+    let scenario = ron::from_str::<Scenario>(
+        r#"(
+            name: "Test",
+            resources: (wood: 10),
+            triggers: [
+                (when: Timer(1.0), then: [Message("one second")]),
+                (
+                    when: ResourcesReached((wood: 10)),
+                    then: [Spawn((kind: Soldier, team: Team(1), at: At((0.0, 0.0))))],
+                ),
+                (when: UnitsKilled(team: Team(1), count: 1), then: [Win(Team(0))]),
+            ],
+        )"#,
+    )
+    .unwrap();
+    app.insert_resource(scenario);
+    run(&mut app, 0.5);
+    let teams = app
+        .world
+        .query_filtered::<&Team, With<Soldier>>()
+        .iter(&app.world)
+        .copied()
+        .collect::<Vec<_>>();
+    assert_eq!(teams, vec![Team(1)]);
+    assert!(app.world.resource::<ScenarioState>().messages().is_empty());
+
+    run(&mut app, 1.0);
+    let state = app.world.resource::<ScenarioState>();
+    let messages = state.messages().iter().map(|(_, message)| message.as_str());
+    assert_eq!(messages.collect::<Vec<_>>(), vec!["one second"]);
+    assert_eq!(state.outcome(Team::PLAYER), None);
+
+    let soldier = app
+        .world
+        .query_filtered::<Entity, With<Soldier>>()
+        .single(&app.world);
+    app.world.send_event(DamageEvent {
+        target: soldier,
+        amount: 1000.0,
+        source: None,
+    });
+    run(&mut app, 0.5);
+    let state = app.world.resource::<ScenarioState>();
+    assert_eq!(state.outcome(Team::PLAYER), Some(Outcome::Won));
+    assert_eq!(state.outcome(Team(1)), Some(Outcome::Lost));
+    // the soldier of the second trigger only comes once
+    assert_eq!(
+        app.world
+            .query_filtered::<(), With<Soldier>>()
+            .iter(&app.world)
+            .count(),
+        0
+    );
+}
+
+#[test]
+fn stumps_block_buildings_until_cleared() {
+    let mut app = headless_app(WorldSize::Infinite);
+    let scenario = ron::from_str::<Scenario>(
+        r#"(
+            name: "Test",
+            triggers: [
+                (when: Timer(1.0), then: [Build((kind: Barrack, at: At((0.0, 60.0))))]),
+                (when: Timer(10.0), then: [Build((kind: Barrack, at: At((0.0, 60.0))))]),
+            ],
+        )"#,
+    )
+    .unwrap();
+    app.insert_resource(scenario);
+    app.world.send_event(TreeSpawnEvent {
+        pos: Vec2::new(0.0, 60.0),
+        index: 3,
+        sapling: false,
+    });
+    app.world
+        .send_event(SpawnLumberjackEvent(Vec2::new(0.0, 40.0), Team::PLAYER));
+    run(&mut app, 0.1);
+    let tree = app
+        .world
+        .query_filtered::<Entity, With<Tree>>()
+        .single(&app.world);
+    let frame = |app: &App| app.world.get::<TextureAtlasSprite>(tree).unwrap().index;
+    assert_eq!(frame(&app), 3);
+    // half the wood is gone, the tree shows its damaged frame
+    for _ in 0..50 {
+        app.world.send_event(TreeChopEvent(tree));
+    }
+    run(&mut app, 0.1);
+    assert_eq!(frame(&app), 2);
+    for _ in 0..50 {
+        app.world.send_event(TreeChopEvent(tree));
+    }
+    run(&mut app, 0.9);
+    assert!(app.world.get::<Stump>(tree).is_some());
+    assert_eq!(frame(&app), 0);
+    let mut barracks = app.world.query_filtered::<(), With<Barrack>>();
+    assert_eq!(barracks.iter(&app.world).count(), 0);
+
+    let lumberjack = app
+        .world
+        .query_filtered::<Entity, With<Lumberjack>>()
+        .single(&app.world);
+    push_input(
+        &mut app,
+        PlayerInput::Command(CommandEvent {
+            team: Team::PLAYER,
+            units: vec![lumberjack],
+            command: Command::Clear(tree),
+            queue: false,
+        }),
+    );
+    run(&mut app, 8.0);
+    assert!(app.world.get_entity(tree).is_none());
+    assert_eq!(barracks.iter(&app.world).count(), 0);
+
+    run(&mut app, 1.0);
+    assert_eq!(barracks.iter(&app.world).count(), 1);
+}
+
+#[test]
+fn mature_trees_spread_saplings_that_grow_up() {
+    let mut app = headless_app(WorldSize::Fixed(UVec2::splat(64)));
+    run(&mut app, 0.1);
+    // generated trees stand on fertile ground, keep one of them as the only parent
+    let mut trees = app
+        .world
+        .query_filtered::<(Entity, &Transform), With<Tree>>()
+        .iter(&app.world)
+        .map(|(entity, transform)| (entity, transform.translation.truncate()))
+        .collect::<Vec<_>>();
+    trees.sort_by(|a, b| a.1.x.total_cmp(&b.1.x).then(a.1.y.total_cmp(&b.1.y)));
+    let (parent, parent_pos) = trees[0];
+    for (tree, _) in &trees[1..] {
+        app.world.despawn(*tree);
+    }
+
+    // every interval the parent has a small chance to sprout one
+    let mut saplings = app.world.query_filtered::<Entity, With<Sapling>>();
+    for _ in 0..100 {
+        run(&mut app, 10.0);
+        if saplings.iter(&app.world).next().is_some() {
+            break;
+        }
+    }
+    let sapling = saplings.single(&app.world);
+    let sapling_pos = app
+        .world
+        .get::<Transform>(sapling)
+        .unwrap()
+        .translation
+        .truncate();
+    assert!(sapling_pos.distance(parent_pos) <= 48.0);
+    let frame = |app: &App| app.world.get::<TextureAtlasSprite>(sapling).unwrap().index;
+    assert_eq!(frame(&app), 1);
+
+    // with the parent gone, the sapling is the only tree and the lumberjack leaves it alone
+    app.world.despawn(parent);
+    let lumberjack_pos = sapling_pos + Vec2::new(30.0, 0.0);
+    app.world
+        .send_event(SpawnLumberjackEvent(lumberjack_pos, Team::PLAYER));
+    run(&mut app, 10.0);
+    assert_eq!(unit_positions(&mut app), vec![lumberjack_pos]);
+    assert_eq!(
+        app.world.get::<Tree>(sapling).unwrap().state(),
+        TreeState::Full
+    );
+
+    // two growth stages of 60 seconds to the mature frame
+    let lumberjack = app
+        .world
+        .query_filtered::<Entity, With<Lumberjack>>()
+        .single(&app.world);
+    app.world.despawn(lumberjack);
+    run(&mut app, 120.0);
+    assert_eq!(frame(&app), MATURE_INDEX);
+    assert!(app.world.get::<Sapling>(sapling).is_none());
+}
+
+#[test]
+fn lumberjacks_build_where_they_are_ordered() {
+    let mut app = headless_app(WorldSize::Infinite);
+    app.world
+        .resource_mut::<Stats>()
+        .team_mut(Team::PLAYER)
+        .wood = 450;
+    // a felled tree leaves a stump on the second site
+    app.world.send_event(TreeSpawnEvent {
+        pos: Vec2::new(-100.0, 0.0),
+        index: 3,
+        sapling: false,
+    });
+    app.world
+        .send_event(SpawnLumberjackEvent(Vec2::ZERO, Team::PLAYER));
+    app.world
+        .send_event(SpawnLumberjackEvent(Vec2::new(0.0, 10.0), Team::PLAYER));
+    run(&mut app, 0.1);
+    let tree = app
+        .world
+        .query_filtered::<Entity, With<Tree>>()
+        .single(&app.world);
+    for _ in 0..100 {
+        app.world.send_event(TreeChopEvent(tree));
+    }
+    let lumberjacks = app
+        .world
+        .query_filtered::<Entity, With<Lumberjack>>()
+        .iter(&app.world)
+        .collect::<Vec<_>>();
+    for command in [
+        Command::Build(BuildingKind::Barrack, Vec2::new(100.0, 0.0)),
+        Command::Build(BuildingKind::Barrack, Vec2::new(-100.0, 0.0)),
+    ] {
+        push_input(
+            &mut app,
+            PlayerInput::Command(CommandEvent {
+                team: Team::PLAYER,
+                units: lumberjacks.clone(),
+                command,
+                queue: true,
+            }),
+        );
+    }
+    run(&mut app, 10.0);
+
+    let barracks = app
+        .world
+        .query_filtered::<&Transform, With<Barrack>>()
+        .iter(&app.world)
+        .map(|transform| transform.translation.truncate())
+        .collect::<Vec<_>>();
+    // both lumberjacks got the order, the barrack is paid and put up once
+    assert_eq!(barracks, vec![Vec2::new(100.0, 0.0)]);
+    assert_eq!(app.world.resource::<Stats>().team(Team::PLAYER).wood, 250);
+    // they went to the stump and left it standing
+    assert!(unit_positions(&mut app)
+        .iter()
+        .all(|pos| pos.distance(Vec2::new(-100.0, 0.0)) < 40.0));
+    assert!(app.world.get::<Stump>(tree).is_some());
+}
+
+#[test]
+fn grid_scatter_moves_along_both_axes() {
+    let placement = Placement::Grid {
+        from: Vec2::ZERO,
+        columns: 4,
+        rows: 4,
+        spacing: 20.0,
+        scatter: 5.0,
+    };
+    let offsets = placement
+        .positions()
+        .into_iter()
+        .map(|pos| pos - (pos / 20.0).round() * 20.0)
+        .collect::<Vec<_>>();
+    assert_eq!(offsets.len(), 16);
+    assert!(offsets
+        .iter()
+        .all(|offset| offset.abs().max_element() <= 5.0));
+    // x and y come from separate noise samples, not the same one twice
+    assert!(offsets.iter().any(|offset| offset.x != offset.y));
+}
+
+// the default scenario is parsed by `Scenario::builtin` in the other tests
+#[test]
+fn tutorial_scenario_parses() {
+    let tutorial =
+        ron::from_str::<Scenario>(include_str!("../assets/scenarios/tutorial.scenario.ron"))
+            .unwrap();
+    assert_eq!(tutorial.name, "Tutorial");
+}